@@ -1,149 +1,382 @@
-use enigo::Keyboard;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 use std::{collections::VecDeque, sync::Arc};
-use tokio::sync::Mutex;
+use tokio::sync::{Mutex, Notify};
 use tokio::time::{sleep, Duration};
 use tokio_util::sync::CancellationToken;
 
 use tokio::task;
 
+use crate::action_sequencer_keys::ActionSequencerKeyName;
+use crate::action_sequencer_output::ActionSequencerOutput;
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSequencerPriority {
+    #[default]
+    Normal,
+    /* skips ahead of the queued normal actions - eg: for the emergency brake */
+    High,
+}
+
 #[derive(Debug, Clone)]
 pub struct ActionSequencerAction {
     pub keys: String,
     pub press_time: Option<f32>, /* if none specified - key will be held */
     pub wait_time: Option<f32>,
     pub release: Option<bool>, /* if specified and set to true key will be released */
+    pub priority: ActionSequencerPriority,
+}
+
+impl ActionSequencerAction {
+    /* held until a release action for the same keys */
+    fn is_hold(&self) -> bool {
+        self.release != Some(true) && self.press_time.is_none()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ActionSequencerMouseAction {
+    Button { button: enigo::Button, direction: enigo::Direction },
+    /* in wheel notches - positive values scroll down/right */
+    Scroll { amount: i32, axis: enigo::Axis },
+    /* relative to the current position in pixels */
+    Move { x: i32, y: i32 },
+}
+
+impl ActionSequencerMouseAction {
+    /* the action which lets go of a pressed button */
+    pub fn release_action(&self) -> Option<ActionSequencerMouseAction> {
+        match self {
+            ActionSequencerMouseAction::Button {
+                button,
+                direction: enigo::Direction::Press,
+            } => Some(ActionSequencerMouseAction::Button {
+                button: *button,
+                direction: enigo::Direction::Release,
+            }),
+            _ => None,
+        }
+    }
+}
+
+enum ActionSequencerStep {
+    Keys(ActionSequencerAction),
+    Mouse(ActionSequencerMouseAction),
+}
+
+struct ActionSequencerQueuedAction {
+    step: ActionSequencerStep,
+    /* name of the control which queued the action */
+    source: Option<String>,
+}
+
+impl ActionSequencerQueuedAction {
+    fn keys_action(&self) -> Option<&ActionSequencerAction> {
+        match &self.step {
+            ActionSequencerStep::Keys(action) => Some(action),
+            ActionSequencerStep::Mouse(_) => None,
+        }
+    }
+
+    fn priority(&self) -> ActionSequencerPriority {
+        self.keys_action().map_or(ActionSequencerPriority::Normal, |action| action.priority)
+    }
+}
+
+/**
+ * Pending actions - high priority actions are kept in front of the normal ones
+ */
+#[derive(Default)]
+struct ActionSequencerQueue {
+    actions: VecDeque<ActionSequencerQueuedAction>,
+}
+
+impl ActionSequencerQueue {
+    /**
+     * Returns false when the action cancelled out a queued action and doesn't need to run
+     */
+    fn push(&mut self, action: ActionSequencerAction, source: Option<String>) -> bool {
+        /* releasing keys a control is still waiting to hold - neither needs to run */
+        if let (Some(source), Some(true)) = (&source, action.release) {
+            let superseded_hold = self
+                .actions
+                .iter()
+                .rposition(|queued| queued.source.as_ref() == Some(source) && queued.keys_action().is_some_and(|queued_action| queued_action.keys == action.keys && queued_action.is_hold()));
+            if let Some(index) = superseded_hold {
+                self.actions.remove(index);
+                return false;
+            }
+        }
+
+        let index = match action.priority {
+            ActionSequencerPriority::Normal => self.actions.len(),
+            ActionSequencerPriority::High => {
+                let first_normal = self
+                    .actions
+                    .iter()
+                    .position(|queued| queued.priority() == ActionSequencerPriority::Normal)
+                    .unwrap_or(self.actions.len());
+                /* never overtake queued actions for the same keys - a release would run before its press */
+                let after_same_keys = self
+                    .actions
+                    .iter()
+                    .rposition(|queued| queued.keys_action().is_some_and(|queued_action| queued_action.keys == action.keys))
+                    .map_or(0, |index| index + 1);
+                first_normal.max(after_same_keys)
+            }
+        };
+        self.actions.insert(
+            index,
+            ActionSequencerQueuedAction {
+                step: ActionSequencerStep::Keys(action),
+                source,
+            },
+        );
+        true
+    }
+
+    fn push_mouse(&mut self, action: ActionSequencerMouseAction) {
+        self.actions.push_back(ActionSequencerQueuedAction {
+            step: ActionSequencerStep::Mouse(action),
+            source: None,
+        });
+    }
+
+    fn pop(&mut self) -> Option<ActionSequencerStep> {
+        self.actions.pop_front().map(|queued| queued.step)
+    }
+
+    fn clear(&mut self) {
+        self.actions.clear();
+    }
+}
+
+/**
+ * Sends the keys and mouse actions to the output and keeps track of which keys and buttons are currently held down
+ */
+pub struct ActionSequencerInput {
+    output: Box<dyn ActionSequencerOutput>,
+    /* in the order they were pressed */
+    pressed_keys: Vec<enigo::Key>,
+    pressed_buttons: Vec<enigo::Button>,
+}
+
+impl ActionSequencerInput {
+    fn key(&mut self, key: enigo::Key, direction: enigo::Direction) {
+        if let Err(e) = self.output.key(key, direction) {
+            eprintln!("[ActionSequencer] {}", e);
+            return;
+        }
+        match direction {
+            enigo::Direction::Press if !self.pressed_keys.contains(&key) => self.pressed_keys.push(key),
+            enigo::Direction::Release => self.pressed_keys.retain(|pressed_key| *pressed_key != key),
+            _ => {}
+        }
+    }
+
+    fn mouse(&mut self, action: &ActionSequencerMouseAction) {
+        let result = match action {
+            ActionSequencerMouseAction::Button { button, direction } => self.output.button(*button, *direction),
+            ActionSequencerMouseAction::Scroll { amount, axis } => self.output.scroll(*amount, *axis),
+            ActionSequencerMouseAction::Move { x, y } => self.output.move_mouse(*x, *y),
+        };
+        if let Err(e) = result {
+            eprintln!("[ActionSequencer] {}", e);
+            return;
+        }
+        match action {
+            ActionSequencerMouseAction::Button {
+                button,
+                direction: enigo::Direction::Press,
+            } if !self.pressed_buttons.contains(button) => self.pressed_buttons.push(*button),
+            ActionSequencerMouseAction::Button {
+                button,
+                direction: enigo::Direction::Release,
+            } => self.pressed_buttons.retain(|pressed_button| pressed_button != button),
+            _ => {}
+        }
+    }
+
+    /* releases in reverse order so modifiers are let go last - returns the names of the released keys and buttons */
+    fn release_all(&mut self) -> Vec<String> {
+        let mut released = Vec::new();
+        for button in std::mem::take(&mut self.pressed_buttons).iter().rev() {
+            if let Err(e) = self.output.button(*button, enigo::Direction::Release) {
+                eprintln!("[ActionSequencer] {}", e);
+            }
+            released.push(format!("{:?} mouse button", button));
+        }
+        for key in std::mem::take(&mut self.pressed_keys).iter().rev() {
+            if let Err(e) = self.output.key(*key, enigo::Direction::Release) {
+                eprintln!("[ActionSequencer] {}", e);
+            }
+            released.push(format!("{:?}", key));
+        }
+        released
+    }
 }
 
 pub struct ActionSequencer {
-    pub actions: Arc<Mutex<VecDeque<ActionSequencerAction>>>,
-    enigo: Arc<Mutex<enigo::Enigo>>,
+    actions: Arc<Mutex<ActionSequencerQueue>>,
+    /* wakes up the run loop when actions are queued */
+    actions_notify: Arc<Notify>,
+    input: Arc<Mutex<ActionSequencerInput>>,
+    /* cancelled and replaced by release_all */
+    release_token: std::sync::Mutex<CancellationToken>,
 }
 
 impl ActionSequencer {
-    pub fn new() -> ActionSequencer {
+    pub fn new(output: Box<dyn ActionSequencerOutput>) -> ActionSequencer {
         ActionSequencer {
-            actions: Arc::new(Mutex::new(VecDeque::new())),
-            enigo: Arc::new(Mutex::new(
-                enigo::Enigo::new(&enigo::Settings::default()).unwrap(),
-            )),
+            actions: Arc::new(Mutex::new(ActionSequencerQueue::default())),
+            actions_notify: Arc::new(Notify::new()),
+            input: Arc::new(Mutex::new(ActionSequencerInput {
+                output,
+                pressed_keys: Vec::new(),
+                pressed_buttons: Vec::new(),
+            })),
+            release_token: std::sync::Mutex::new(CancellationToken::new()),
         }
     }
 
+    /**
+     * Cancelled by the next release_all - lets background tasks like sequences stop queueing actions
+     */
+    pub fn release_token(&self) -> CancellationToken {
+        self.release_token.lock().unwrap().clone()
+    }
+
     pub async fn add_action(&self, action: ActionSequencerAction) {
-        self.actions.lock().await.push_back(action);
+        self.queue_action(action, None).await;
+    }
+
+    /**
+     * Same as add_action but a release queued by the same control cancels its hold if that didn't run yet
+     */
+    pub async fn add_action_for_control<T: AsRef<str>>(&self, control_name: T, action: ActionSequencerAction) {
+        self.queue_action(action, Some(control_name.as_ref().to_string())).await;
+    }
+
+    pub async fn add_mouse_action(&self, action: ActionSequencerMouseAction) {
+        self.actions.lock().await.push_mouse(action);
+        self.actions_notify.notify_one();
     }
 
+    async fn queue_action(&self, action: ActionSequencerAction, source: Option<String>) {
+        if self.actions.lock().await.push(action, source) {
+            self.actions_notify.notify_one();
+        }
+    }
+
+    /**
+     * Drops the queued actions and releases every key and mouse button which is still held down
+     * Returns the released keys and buttons
+     */
+    pub async fn release_all(&self) -> Vec<String> {
+        std::mem::replace(&mut *self.release_token.lock().unwrap(), CancellationToken::new()).cancel();
+        self.actions.lock().await.clear();
+        let released_keys = self.input.lock().await.release_all();
+        if !released_keys.is_empty() {
+            println!("[ActionSequencer] Released {:?}", released_keys);
+        }
+        released_keys
+    }
+
+    /**
+     * Splits keys like "ctrl+shift+a" into the modifier keys and the action keys - unknown key names are ignored
+     */
     pub fn parse_keys<T: AsRef<str>>(input: T) -> (Vec<enigo::Key>, Vec<enigo::Key>) {
-        use enigo::Key;
-        let split = input.as_ref().split('+');
-        let modifier_keys = split
-            .clone()
-            .filter_map(|key| match key.to_lowercase().as_str() {
-                "ctrl" | "control" => Some(Key::Control),
-                "alt" => Some(Key::Alt),
-                "meta" | "cmd" | "command" => Some(Key::Meta),
-                _ => None,
-            })
-            .collect();
-
-        let action_keys: Vec<enigo::Key> = split
-            .clone()
-            .filter_map(|key| match key.to_lowercase().as_str() {
-                "shift" => Some(Key::Shift),
-                "backspace" => Some(Key::Backspace),
-                "delete" => Some(Key::Delete),
-                "arrowdown" | "down" => Some(Key::DownArrow),
-                "arrowup" | "up" => Some(Key::UpArrow),
-                "arrowleft" | "left" => Some(Key::LeftArrow),
-                "arrowright" | "right" => Some(Key::RightArrow),
-                "return" | "enter" => Some(Key::Return),
-                "space" | "spacebar" => Some(Key::Space),
-                "tab" => Some(Key::Tab),
-                "escape" | "esc" => Some(Key::Escape),
-                "capslock" => Some(Key::CapsLock),
-                "f1" => Some(Key::F1),
-                "f2" => Some(Key::F2),
-                "f3" => Some(Key::F3),
-                "f4" => Some(Key::F4),
-                "f5" => Some(Key::F5),
-                "f6" => Some(Key::F6),
-                "f7" => Some(Key::F7),
-                "f8" => Some(Key::F8),
-                "f9" => Some(Key::F9),
-                "f10" => Some(Key::F10),
-                "f11" => Some(Key::F11),
-                "f12" => Some(Key::F12),
-                "pageup" => Some(Key::PageUp),
-                "pagedown" => Some(Key::PageDown),
-                "home" => Some(Key::Home),
-                "end" => Some(Key::End),
-                "insert" => Some(Key::Insert),
-                key if key.len() == 1 => {
-                    let char = key.chars().next().unwrap();
-                    Some(Key::Unicode(char))
-                }
-                _ => None,
-            })
-            .collect();
+        let key_names: Vec<ActionSequencerKeyName> = input.as_ref().split('+').filter_map(ActionSequencerKeyName::find).collect();
+        let modifier_keys = key_names.iter().filter(|key_name| key_name.modifier).map(|key_name| key_name.key).collect();
+        let action_keys = key_names.iter().filter(|key_name| !key_name.modifier).map(|key_name| key_name.key).collect();
 
         (modifier_keys, action_keys)
     }
 
+    /**
+     * Same as parse_keys but fails on unknown key names instead of ignoring them
+     */
+    pub fn try_parse_keys<T: AsRef<str>>(input: T) -> Result<(Vec<enigo::Key>, Vec<enigo::Key>), String> {
+        if input.as_ref().trim().is_empty() {
+            return Err(String::from("No keys specified"));
+        }
+        for key in input.as_ref().split('+') {
+            if ActionSequencerKeyName::find(key).is_none() {
+                return Err(format!("Unknown key \"{}\" in \"{}\" - run the keys command to list the supported key names", key, input.as_ref()));
+            }
+        }
+        Ok(ActionSequencer::parse_keys(input))
+    }
+
     pub async fn press_or_release_keys<T: AsRef<str>>(
-        enigo: Arc<Mutex<enigo::Enigo>>,
+        input: Arc<Mutex<ActionSequencerInput>>,
         keys: T,
         direction: enigo::Direction,
     ) {
-        let mut enigo_lock = enigo.lock().await;
+        let mut input_lock = input.lock().await;
         let (modifier_keys, action_keys) = ActionSequencer::parse_keys(keys.as_ref());
 
         if direction == enigo::Direction::Press {
             for key in modifier_keys.iter() {
-                enigo_lock.key(*key, enigo::Direction::Press).unwrap();
+                input_lock.key(*key, enigo::Direction::Press);
             }
             if modifier_keys.is_empty() != true {
                 sleep(Duration::from_millis(30)).await;
             }
             for key in action_keys.iter() {
-                enigo_lock.key(*key, enigo::Direction::Press).unwrap();
+                input_lock.key(*key, enigo::Direction::Press);
             }
         } else {
             for key in action_keys.iter() {
-                enigo_lock.key(*key, enigo::Direction::Release).unwrap();
+                input_lock.key(*key, enigo::Direction::Release);
             }
             if modifier_keys.is_empty() != true {
                 sleep(Duration::from_millis(30)).await;
             }
             for key in modifier_keys.iter() {
-                enigo_lock.key(*key, enigo::Direction::Release).unwrap();
+                input_lock.key(*key, enigo::Direction::Release);
             }
         }
     }
 
     pub fn run(&self, cancel_token: CancellationToken) -> task::JoinHandle<()> {
-        let enigo_arc: Arc<Mutex<enigo::Enigo>> = Arc::clone(&self.enigo);
+        let input_arc = Arc::clone(&self.input);
         let actions_queue = Arc::clone(&self.actions);
+        let actions_notify = Arc::clone(&self.actions_notify);
         let thread = task::spawn(async move {
             loop {
                 tokio::select! {
                   _ = cancel_token.cancelled() => {
+                      /* don't leave keys held down in the game when the app exits */
+                      input_arc.lock().await.release_all();
                       break;
                   }
                   _ = async {
-                    while let Some(action) = actions_queue.lock().await.pop_front() {
+                    actions_notify.notified().await;
+                    loop {
+                      /* the lock is only held while popping so actions can be queued while the keys are pressed */
+                      let action = match actions_queue.lock().await.pop() {
+                          Some(ActionSequencerStep::Keys(action)) => action,
+                          Some(ActionSequencerStep::Mouse(action)) => {
+                            input_arc.lock().await.mouse(&action);
+                            continue;
+                          },
+                          None => break,
+                      };
                       match action.release {
                           Some(true) => {
-                            ActionSequencer::press_or_release_keys(Arc::clone(&enigo_arc), &action.keys, enigo::Direction::Release).await;
+                            ActionSequencer::press_or_release_keys(Arc::clone(&input_arc), &action.keys, enigo::Direction::Release).await;
                           },
                           Some(false) | None => match action.press_time {
                               Some(press_time) => {
-                                ActionSequencer::press_or_release_keys(Arc::clone(&enigo_arc), &action.keys, enigo::Direction::Press).await;
+                                ActionSequencer::press_or_release_keys(Arc::clone(&input_arc), &action.keys, enigo::Direction::Press).await;
                                 sleep(Duration::from_millis((press_time * 1000.0).abs() as u64)).await;
-                                ActionSequencer::press_or_release_keys(Arc::clone(&enigo_arc), &action.keys, enigo::Direction::Release).await;
+                                ActionSequencer::press_or_release_keys(Arc::clone(&input_arc), &action.keys, enigo::Direction::Release).await;
                                 sleep(Duration::from_millis((action.wait_time.unwrap_or(0.1) * 1000.0).abs() as u64)).await;
                               },
                               None =>{
-                                ActionSequencer::press_or_release_keys(Arc::clone(&enigo_arc), &action.keys, enigo::Direction::Press).await;
+                                ActionSequencer::press_or_release_keys(Arc::clone(&input_arc), &action.keys, enigo::Direction::Press).await;
                               },
                           },
                       }
@@ -155,3 +388,129 @@ impl ActionSequencer {
         thread
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::action_sequencer_output::{RecordedOutputEvent, RecordingOutput};
+    use enigo::{Direction, Key};
+
+    fn tap(keys: &str) -> ActionSequencerAction {
+        ActionSequencerAction {
+            keys: String::from(keys),
+            press_time: Some(0.01),
+            wait_time: Some(0.0),
+            release: None,
+            priority: ActionSequencerPriority::Normal,
+        }
+    }
+
+    fn hold(keys: &str) -> ActionSequencerAction {
+        ActionSequencerAction { press_time: None, ..tap(keys) }
+    }
+
+    fn release(keys: &str) -> ActionSequencerAction {
+        ActionSequencerAction {
+            press_time: None,
+            release: Some(true),
+            ..tap(keys)
+        }
+    }
+
+    fn queued_keys(queue: &ActionSequencerQueue) -> Vec<&str> {
+        queue.actions.iter().filter_map(|queued| queued.keys_action()).map(|action| action.keys.as_str()).collect()
+    }
+
+    #[test]
+    fn queued_taps_of_a_control_are_all_kept() {
+        /* every step of a quickly moved lever has to reach the game or the in-game lever drifts away */
+        let mut queue = ActionSequencerQueue::default();
+        queue.push(tap("a"), Some(String::from("Throttle")));
+        queue.push(tap("b"), Some(String::from("Brake")));
+        queue.push(tap("a"), Some(String::from("Throttle")));
+        queue.push(tap("a"), Some(String::from("Throttle")));
+
+        assert_eq!(queued_keys(&queue), vec!["a", "b", "a", "a"]);
+    }
+
+    #[test]
+    fn release_cancels_a_queued_hold_of_the_same_control() {
+        let mut queue = ActionSequencerQueue::default();
+        queue.push(hold("h"), Some(String::from("Horn")));
+        queue.push(tap("a"), Some(String::from("Wipers")));
+
+        assert!(!queue.push(release("h"), Some(String::from("Horn"))));
+        assert_eq!(queued_keys(&queue), vec!["a"]);
+    }
+
+    #[test]
+    fn high_priority_skips_ahead_of_normal_actions() {
+        let mut queue = ActionSequencerQueue::default();
+        queue.push(tap("a"), None);
+        queue.push(tap("b"), None);
+        queue.push(
+            ActionSequencerAction {
+                priority: ActionSequencerPriority::High,
+                ..tap("backspace")
+            },
+            None,
+        );
+
+        assert_eq!(queued_keys(&queue), vec!["backspace", "a", "b"]);
+    }
+
+    /* the run loop sends the events in the background */
+    async fn wait_for_events(output: &RecordingOutput, count: usize) -> Vec<RecordedOutputEvent> {
+        for _ in 0..100 {
+            if output.events().len() >= count {
+                break;
+            }
+            sleep(Duration::from_millis(10)).await;
+        }
+        output.events()
+    }
+
+    #[tokio::test]
+    async fn tap_presses_modifiers_first_and_releases_them_last() {
+        let output = RecordingOutput::new();
+        let sequencer = ActionSequencer::new(Box::new(output.clone()));
+        let cancel_token = CancellationToken::new();
+        sequencer.run(cancel_token.clone());
+
+        sequencer.add_action(tap("ctrl+a")).await;
+
+        assert_eq!(
+            wait_for_events(&output, 4).await,
+            vec![
+                RecordedOutputEvent::Key(Key::Control, Direction::Press),
+                RecordedOutputEvent::Key(Key::Unicode('a'), Direction::Press),
+                RecordedOutputEvent::Key(Key::Unicode('a'), Direction::Release),
+                RecordedOutputEvent::Key(Key::Control, Direction::Release),
+            ]
+        );
+        cancel_token.cancel();
+    }
+
+    #[tokio::test]
+    async fn release_all_lets_go_of_held_keys_in_reverse_order() {
+        let output = RecordingOutput::new();
+        let sequencer = ActionSequencer::new(Box::new(output.clone()));
+        let cancel_token = CancellationToken::new();
+        sequencer.run(cancel_token.clone());
+
+        sequencer.add_action(hold("shift+w")).await;
+        wait_for_events(&output, 2).await;
+        sequencer.release_all().await;
+
+        assert_eq!(
+            output.events(),
+            vec![
+                RecordedOutputEvent::Key(Key::Shift, Direction::Press),
+                RecordedOutputEvent::Key(Key::Unicode('w'), Direction::Press),
+                RecordedOutputEvent::Key(Key::Unicode('w'), Direction::Release),
+                RecordedOutputEvent::Key(Key::Shift, Direction::Release),
+            ]
+        );
+        cancel_token.cancel();
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::OnceLock;
+
+use enigo::Key;
+
+/* X11 keysyms of the keys enigo only has variants for on windows */
+#[cfg(not(target_os = "windows"))]
+pub mod keysym {
+    pub const KP_0: u32 = 0xffb0;
+    pub const KP_1: u32 = 0xffb1;
+    pub const KP_2: u32 = 0xffb2;
+    pub const KP_3: u32 = 0xffb3;
+    pub const KP_4: u32 = 0xffb4;
+    pub const KP_5: u32 = 0xffb5;
+    pub const KP_6: u32 = 0xffb6;
+    pub const KP_7: u32 = 0xffb7;
+    pub const KP_8: u32 = 0xffb8;
+    pub const KP_9: u32 = 0xffb9;
+    pub const KP_ADD: u32 = 0xffab;
+    pub const KP_SUBTRACT: u32 = 0xffad;
+    pub const KP_MULTIPLY: u32 = 0xffaa;
+    pub const KP_DIVIDE: u32 = 0xffaf;
+    pub const KP_DECIMAL: u32 = 0xffae;
+    pub const KP_ENTER: u32 = 0xff8d;
+    pub const ALT_R: u32 = 0xffea;
+    pub const SUPER_L: u32 = 0xffeb;
+    pub const SUPER_R: u32 = 0xffec;
+    pub const PRINT: u32 = 0xff61;
+    pub const SCROLL_LOCK: u32 = 0xff14;
+    pub const MENU: u32 = 0xff67;
+}
+
+/* picks the windows enigo variant or falls back to the X11 keysym */
+macro_rules! windows_key_or_keysym {
+    ($windows_key:expr, $keysym:expr) => {{
+        #[cfg(target_os = "windows")]
+        let key = $windows_key;
+        #[cfg(not(target_os = "windows"))]
+        let key = Key::Other($keysym);
+        key
+    }};
+}
+
+/**
+ * A key which can be used in the keys of an action
+ * eg: "ctrl+shift+f13", "ralt+numpad5"
+ */
+#[derive(Debug, Clone)]
+pub struct ActionSequencerKeyName {
+    /* the first name is the canonical one - the others are aliases */
+    pub names: Vec<&'static str>,
+    pub key: Key,
+    /* modifiers are pressed before and released after the other keys */
+    pub modifier: bool,
+}
+
+impl ActionSequencerKeyName {
+    fn new(names: &[&'static str], key: Key, modifier: bool) -> ActionSequencerKeyName {
+        ActionSequencerKeyName { names: names.to_vec(), key, modifier }
+    }
+
+    /**
+     * Looks up a key by any of its names - any other single character is typed as is
+     */
+    pub fn find(name: &str) -> Option<ActionSequencerKeyName> {
+        let lowercase_name = name.to_lowercase();
+        if let Some(key_name) = ActionSequencerKeyName::all().iter().find(|key_name| key_name.names.contains(&lowercase_name.as_str())) {
+            return Some(key_name.clone());
+        }
+        let mut chars = name.chars();
+        match (chars.next(), chars.next()) {
+            (Some(char), None) => Some(ActionSequencerKeyName {
+                names: vec![],
+                key: Key::Unicode(char.to_ascii_lowercase()),
+                modifier: false,
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn all() -> &'static Vec<ActionSequencerKeyName> {
+        static KEY_NAMES: OnceLock<Vec<ActionSequencerKeyName>> = OnceLock::new();
+        KEY_NAMES.get_or_init(|| {
+            let modifier = |names: &[&'static str], key: Key| ActionSequencerKeyName::new(names, key, true);
+            let key = |names: &[&'static str], key: Key| ActionSequencerKeyName::new(names, key, false);
+            vec![
+                /* modifiers */
+                modifier(&["ctrl", "control"], Key::Control),
+                modifier(&["lctrl", "leftctrl", "lcontrol"], Key::LControl),
+                modifier(&["rctrl", "rightctrl", "rcontrol"], Key::RControl),
+                modifier(&["shift"], Key::Shift),
+                modifier(&["lshift", "leftshift"], Key::LShift),
+                modifier(&["rshift", "rightshift"], Key::RShift),
+                modifier(&["alt"], Key::Alt),
+                modifier(&["lalt", "leftalt"], Key::LMenu),
+                modifier(&["ralt", "rightalt", "altgr"], windows_key_or_keysym!(Key::RMenu, keysym::ALT_R)),
+                modifier(&["meta", "cmd", "command", "win", "super"], Key::Meta),
+                modifier(&["lmeta", "leftmeta", "lwin"], windows_key_or_keysym!(Key::LWin, keysym::SUPER_L)),
+                modifier(&["rmeta", "rightmeta", "rwin"], windows_key_or_keysym!(Key::RWin, keysym::SUPER_R)),
+                /* editing and navigation */
+                key(&["backspace"], Key::Backspace),
+                key(&["delete", "del"], Key::Delete),
+                key(&["insert", "ins"], Key::Insert),
+                key(&["home"], Key::Home),
+                key(&["end"], Key::End),
+                key(&["pageup", "pgup"], Key::PageUp),
+                key(&["pagedown", "pgdn"], Key::PageDown),
+                key(&["arrowup", "up"], Key::UpArrow),
+                key(&["arrowdown", "down"], Key::DownArrow),
+                key(&["arrowleft", "left"], Key::LeftArrow),
+                key(&["arrowright", "right"], Key::RightArrow),
+                key(&["return", "enter"], Key::Return),
+                key(&["space", "spacebar"], Key::Space),
+                key(&["tab"], Key::Tab),
+                key(&["escape", "esc"], Key::Escape),
+                key(&["capslock"], Key::CapsLock),
+                key(&["numlock"], Key::Numlock),
+                key(&["scrolllock"], windows_key_or_keysym!(Key::Scroll, keysym::SCROLL_LOCK)),
+                key(&["printscreen", "print", "prtsc"], windows_key_or_keysym!(Key::Snapshot, keysym::PRINT)),
+                key(&["pause", "break"], Key::Pause),
+                key(&["menu", "apps"], windows_key_or_keysym!(Key::Apps, keysym::MENU)),
+                /* function keys */
+                key(&["f1"], Key::F1),
+                key(&["f2"], Key::F2),
+                key(&["f3"], Key::F3),
+                key(&["f4"], Key::F4),
+                key(&["f5"], Key::F5),
+                key(&["f6"], Key::F6),
+                key(&["f7"], Key::F7),
+                key(&["f8"], Key::F8),
+                key(&["f9"], Key::F9),
+                key(&["f10"], Key::F10),
+                key(&["f11"], Key::F11),
+                key(&["f12"], Key::F12),
+                key(&["f13"], Key::F13),
+                key(&["f14"], Key::F14),
+                key(&["f15"], Key::F15),
+                key(&["f16"], Key::F16),
+                key(&["f17"], Key::F17),
+                key(&["f18"], Key::F18),
+                key(&["f19"], Key::F19),
+                key(&["f20"], Key::F20),
+                key(&["f21"], Key::F21),
+                key(&["f22"], Key::F22),
+                key(&["f23"], Key::F23),
+                key(&["f24"], Key::F24),
+                /* keypad */
+                key(&["numpad0", "kp0"], windows_key_or_keysym!(Key::Numpad0, keysym::KP_0)),
+                key(&["numpad1", "kp1"], windows_key_or_keysym!(Key::Numpad1, keysym::KP_1)),
+                key(&["numpad2", "kp2"], windows_key_or_keysym!(Key::Numpad2, keysym::KP_2)),
+                key(&["numpad3", "kp3"], windows_key_or_keysym!(Key::Numpad3, keysym::KP_3)),
+                key(&["numpad4", "kp4"], windows_key_or_keysym!(Key::Numpad4, keysym::KP_4)),
+                key(&["numpad5", "kp5"], windows_key_or_keysym!(Key::Numpad5, keysym::KP_5)),
+                key(&["numpad6", "kp6"], windows_key_or_keysym!(Key::Numpad6, keysym::KP_6)),
+                key(&["numpad7", "kp7"], windows_key_or_keysym!(Key::Numpad7, keysym::KP_7)),
+                key(&["numpad8", "kp8"], windows_key_or_keysym!(Key::Numpad8, keysym::KP_8)),
+                key(&["numpad9", "kp9"], windows_key_or_keysym!(Key::Numpad9, keysym::KP_9)),
+                key(&["numpadadd", "numpadplus", "kpplus"], windows_key_or_keysym!(Key::Add, keysym::KP_ADD)),
+                key(&["numpadsubtract", "numpadminus", "kpminus"], windows_key_or_keysym!(Key::Subtract, keysym::KP_SUBTRACT)),
+                key(&["numpadmultiply", "kpmultiply"], windows_key_or_keysym!(Key::Multiply, keysym::KP_MULTIPLY)),
+                key(&["numpaddivide", "kpdivide"], windows_key_or_keysym!(Key::Divide, keysym::KP_DIVIDE)),
+                key(&["numpaddecimal", "numpaddot", "kpdecimal"], windows_key_or_keysym!(Key::Decimal, keysym::KP_DECIMAL)),
+                /* windows has no separate virtual key for the keypad enter */
+                key(&["numpadenter", "kpenter"], windows_key_or_keysym!(Key::Return, keysym::KP_ENTER)),
+                /* punctuation which can't be written as a single character in the keys */
+                key(&["plus"], Key::Unicode('+')),
+                key(&["minus"], Key::Unicode('-')),
+                key(&["equals", "equal"], Key::Unicode('=')),
+                key(&["comma"], Key::Unicode(',')),
+                key(&["period", "dot"], Key::Unicode('.')),
+                key(&["slash"], Key::Unicode('/')),
+                key(&["backslash"], Key::Unicode('\\')),
+                key(&["semicolon"], Key::Unicode(';')),
+                key(&["apostrophe", "quote"], Key::Unicode('\'')),
+                key(&["grave", "backtick"], Key::Unicode('`')),
+                key(&["leftbracket"], Key::Unicode('[')),
+                key(&["rightbracket"], Key::Unicode(']')),
+                /* media */
+                key(&["volumeup"], Key::VolumeUp),
+                key(&["volumedown"], Key::VolumeDown),
+                key(&["volumemute", "mute"], Key::VolumeMute),
+                key(&["medianext"], Key::MediaNextTrack),
+                key(&["mediaprev"], Key::MediaPrevTrack),
+                key(&["mediaplaypause"], Key::MediaPlayPause),
+            ]
+        })
+    }
+}
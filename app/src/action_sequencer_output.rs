@@ -0,0 +1,369 @@
+use std::sync::{Arc, Mutex};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/**
+ * Where the ActionSequencer sends its key presses to
+ */
+pub trait ActionSequencerOutput: Send {
+    fn key(&mut self, key: enigo::Key, direction: enigo::Direction) -> Result<(), String>;
+    fn button(&mut self, button: enigo::Button, direction: enigo::Direction) -> Result<(), String>;
+    /* in wheel notches - positive values scroll down/right */
+    fn scroll(&mut self, amount: i32, axis: enigo::Axis) -> Result<(), String>;
+    /* relative to the current position in pixels */
+    fn move_mouse(&mut self, x: i32, y: i32) -> Result<(), String>;
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ActionSequencerOutputBackend {
+    /* simulates key presses through the OS (SendInput on windows, X11/libei on linux) */
+    #[default]
+    Enigo,
+    /* creates a virtual keyboard and mouse through /dev/uinput - linux only, also works on wayland */
+    Uinput,
+    /* does not press any keys or mouse buttons - only records and logs them */
+    Recording,
+}
+
+impl ActionSequencerOutputBackend {
+    pub fn create(&self) -> Result<Box<dyn ActionSequencerOutput>, String> {
+        match self {
+            ActionSequencerOutputBackend::Enigo => Ok(Box::new(EnigoOutput::new()?)),
+            #[cfg(target_os = "linux")]
+            ActionSequencerOutputBackend::Uinput => Ok(Box::new(UinputOutput::new()?)),
+            #[cfg(not(target_os = "linux"))]
+            ActionSequencerOutputBackend::Uinput => Err(String::from("The uinput output backend is only available on linux")),
+            ActionSequencerOutputBackend::Recording => Ok(Box::new(RecordingOutput::new())),
+        }
+    }
+}
+
+pub struct EnigoOutput {
+    enigo: enigo::Enigo,
+}
+
+impl EnigoOutput {
+    pub fn new() -> Result<EnigoOutput, String> {
+        let enigo = enigo::Enigo::new(&enigo::Settings::default()).map_err(|e| format!("Could not create enigo output: {}", e))?;
+        Ok(EnigoOutput { enigo })
+    }
+}
+
+impl ActionSequencerOutput for EnigoOutput {
+    fn key(&mut self, key: enigo::Key, direction: enigo::Direction) -> Result<(), String> {
+        use enigo::Keyboard;
+        self.enigo.key(key, direction).map_err(|e| format!("Could not send {:?} {:?}: {}", direction, key, e))
+    }
+
+    fn button(&mut self, button: enigo::Button, direction: enigo::Direction) -> Result<(), String> {
+        use enigo::Mouse;
+        self.enigo
+            .button(button, direction)
+            .map_err(|e| format!("Could not send {:?} {:?} mouse button: {}", direction, button, e))
+    }
+
+    fn scroll(&mut self, amount: i32, axis: enigo::Axis) -> Result<(), String> {
+        use enigo::Mouse;
+        self.enigo.scroll(amount, axis).map_err(|e| format!("Could not scroll {} {:?}: {}", amount, axis, e))
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32) -> Result<(), String> {
+        use enigo::Mouse;
+        self.enigo
+            .move_mouse(x, y, enigo::Coordinate::Rel)
+            .map_err(|e| format!("Could not move the mouse by {},{}: {}", x, y, e))
+    }
+}
+
+#[cfg(target_os = "linux")]
+pub struct UinputOutput {
+    device: evdev::uinput::VirtualDevice,
+}
+
+#[cfg(target_os = "linux")]
+impl UinputOutput {
+    const DEVICE_NAME: &'static str = "TSW Gamepad Virtual Input";
+
+    pub fn new() -> Result<UinputOutput, String> {
+        let keys: evdev::AttributeSet<evdev::KeyCode> = UinputOutput::supported_keys().into_iter().collect();
+        let relative_axes: evdev::AttributeSet<evdev::RelativeAxisCode> = [
+            evdev::RelativeAxisCode::REL_X,
+            evdev::RelativeAxisCode::REL_Y,
+            evdev::RelativeAxisCode::REL_WHEEL,
+            evdev::RelativeAxisCode::REL_HWHEEL,
+        ]
+        .into_iter()
+        .collect();
+        let device = evdev::uinput::VirtualDevice::builder()
+            .and_then(|builder| builder.name(UinputOutput::DEVICE_NAME).with_keys(&keys))
+            .and_then(|builder| builder.with_relative_axes(&relative_axes))
+            .and_then(|builder| builder.build())
+            .map_err(|e| format!("Could not create uinput device (is /dev/uinput writable?): {}", e))?;
+        Ok(UinputOutput { device })
+    }
+
+    fn supported_keys() -> Vec<evdev::KeyCode> {
+        use crate::action_sequencer_keys::ActionSequencerKeyName;
+        use evdev::KeyCode;
+        let mut keys = vec![KeyCode::BTN_LEFT, KeyCode::BTN_MIDDLE, KeyCode::BTN_RIGHT];
+        keys.extend(ActionSequencerKeyName::all().iter().filter_map(|key_name| UinputOutput::key_code(key_name.key)));
+        keys.extend("abcdefghijklmnopqrstuvwxyz0123456789-=[];'`\\,./".chars().filter_map(UinputOutput::char_key_code));
+        keys
+    }
+
+    /* characters are mapped to their position on a US layout */
+    fn char_key_code(char: char) -> Option<evdev::KeyCode> {
+        use evdev::KeyCode;
+        let key_code = match char.to_ascii_lowercase() {
+            'a' => KeyCode::KEY_A,
+            'b' => KeyCode::KEY_B,
+            'c' => KeyCode::KEY_C,
+            'd' => KeyCode::KEY_D,
+            'e' => KeyCode::KEY_E,
+            'f' => KeyCode::KEY_F,
+            'g' => KeyCode::KEY_G,
+            'h' => KeyCode::KEY_H,
+            'i' => KeyCode::KEY_I,
+            'j' => KeyCode::KEY_J,
+            'k' => KeyCode::KEY_K,
+            'l' => KeyCode::KEY_L,
+            'm' => KeyCode::KEY_M,
+            'n' => KeyCode::KEY_N,
+            'o' => KeyCode::KEY_O,
+            'p' => KeyCode::KEY_P,
+            'q' => KeyCode::KEY_Q,
+            'r' => KeyCode::KEY_R,
+            's' => KeyCode::KEY_S,
+            't' => KeyCode::KEY_T,
+            'u' => KeyCode::KEY_U,
+            'v' => KeyCode::KEY_V,
+            'w' => KeyCode::KEY_W,
+            'x' => KeyCode::KEY_X,
+            'y' => KeyCode::KEY_Y,
+            'z' => KeyCode::KEY_Z,
+            '1' => KeyCode::KEY_1,
+            '2' => KeyCode::KEY_2,
+            '3' => KeyCode::KEY_3,
+            '4' => KeyCode::KEY_4,
+            '5' => KeyCode::KEY_5,
+            '6' => KeyCode::KEY_6,
+            '7' => KeyCode::KEY_7,
+            '8' => KeyCode::KEY_8,
+            '9' => KeyCode::KEY_9,
+            '0' => KeyCode::KEY_0,
+            '-' => KeyCode::KEY_MINUS,
+            '=' => KeyCode::KEY_EQUAL,
+            '[' => KeyCode::KEY_LEFTBRACE,
+            ']' => KeyCode::KEY_RIGHTBRACE,
+            ';' => KeyCode::KEY_SEMICOLON,
+            '\'' => KeyCode::KEY_APOSTROPHE,
+            '`' => KeyCode::KEY_GRAVE,
+            '\\' => KeyCode::KEY_BACKSLASH,
+            ',' => KeyCode::KEY_COMMA,
+            '.' => KeyCode::KEY_DOT,
+            '/' => KeyCode::KEY_SLASH,
+            ' ' => KeyCode::KEY_SPACE,
+            _ => return None,
+        };
+        Some(key_code)
+    }
+
+    fn key_code(key: enigo::Key) -> Option<evdev::KeyCode> {
+        use crate::action_sequencer_keys::keysym;
+        use enigo::Key;
+        use evdev::KeyCode;
+        let key_code = match key {
+            Key::Control | Key::LControl => KeyCode::KEY_LEFTCTRL,
+            Key::RControl => KeyCode::KEY_RIGHTCTRL,
+            Key::Alt | Key::LMenu => KeyCode::KEY_LEFTALT,
+            Key::Other(keysym::ALT_R) => KeyCode::KEY_RIGHTALT,
+            Key::Meta | Key::Other(keysym::SUPER_L) => KeyCode::KEY_LEFTMETA,
+            Key::Other(keysym::SUPER_R) => KeyCode::KEY_RIGHTMETA,
+            Key::Shift | Key::LShift => KeyCode::KEY_LEFTSHIFT,
+            Key::RShift => KeyCode::KEY_RIGHTSHIFT,
+            Key::Backspace => KeyCode::KEY_BACKSPACE,
+            Key::Delete => KeyCode::KEY_DELETE,
+            Key::DownArrow => KeyCode::KEY_DOWN,
+            Key::UpArrow => KeyCode::KEY_UP,
+            Key::LeftArrow => KeyCode::KEY_LEFT,
+            Key::RightArrow => KeyCode::KEY_RIGHT,
+            Key::Return => KeyCode::KEY_ENTER,
+            Key::Space => KeyCode::KEY_SPACE,
+            Key::Tab => KeyCode::KEY_TAB,
+            Key::Escape => KeyCode::KEY_ESC,
+            Key::CapsLock => KeyCode::KEY_CAPSLOCK,
+            Key::PageUp => KeyCode::KEY_PAGEUP,
+            Key::PageDown => KeyCode::KEY_PAGEDOWN,
+            Key::Home => KeyCode::KEY_HOME,
+            Key::End => KeyCode::KEY_END,
+            Key::Insert => KeyCode::KEY_INSERT,
+            Key::Numlock => KeyCode::KEY_NUMLOCK,
+            Key::Other(keysym::SCROLL_LOCK) => KeyCode::KEY_SCROLLLOCK,
+            Key::Other(keysym::PRINT) => KeyCode::KEY_SYSRQ,
+            Key::Pause => KeyCode::KEY_PAUSE,
+            Key::Other(keysym::MENU) => KeyCode::KEY_COMPOSE,
+            Key::F1 => KeyCode::KEY_F1,
+            Key::F2 => KeyCode::KEY_F2,
+            Key::F3 => KeyCode::KEY_F3,
+            Key::F4 => KeyCode::KEY_F4,
+            Key::F5 => KeyCode::KEY_F5,
+            Key::F6 => KeyCode::KEY_F6,
+            Key::F7 => KeyCode::KEY_F7,
+            Key::F8 => KeyCode::KEY_F8,
+            Key::F9 => KeyCode::KEY_F9,
+            Key::F10 => KeyCode::KEY_F10,
+            Key::F11 => KeyCode::KEY_F11,
+            Key::F12 => KeyCode::KEY_F12,
+            Key::F13 => KeyCode::KEY_F13,
+            Key::F14 => KeyCode::KEY_F14,
+            Key::F15 => KeyCode::KEY_F15,
+            Key::F16 => KeyCode::KEY_F16,
+            Key::F17 => KeyCode::KEY_F17,
+            Key::F18 => KeyCode::KEY_F18,
+            Key::F19 => KeyCode::KEY_F19,
+            Key::F20 => KeyCode::KEY_F20,
+            Key::F21 => KeyCode::KEY_F21,
+            Key::F22 => KeyCode::KEY_F22,
+            Key::F23 => KeyCode::KEY_F23,
+            Key::F24 => KeyCode::KEY_F24,
+            Key::Other(keysym::KP_ADD) => KeyCode::KEY_KPPLUS,
+            Key::Other(keysym::KP_SUBTRACT) => KeyCode::KEY_KPMINUS,
+            Key::Other(keysym::KP_MULTIPLY) => KeyCode::KEY_KPASTERISK,
+            Key::Other(keysym::KP_DIVIDE) => KeyCode::KEY_KPSLASH,
+            Key::Other(keysym::KP_DECIMAL) => KeyCode::KEY_KPDOT,
+            Key::Other(keysym::KP_ENTER) => KeyCode::KEY_KPENTER,
+            Key::Other(keysym::KP_0) => KeyCode::KEY_KP0,
+            Key::Other(keysym::KP_1) => KeyCode::KEY_KP1,
+            Key::Other(keysym::KP_2) => KeyCode::KEY_KP2,
+            Key::Other(keysym::KP_3) => KeyCode::KEY_KP3,
+            Key::Other(keysym::KP_4) => KeyCode::KEY_KP4,
+            Key::Other(keysym::KP_5) => KeyCode::KEY_KP5,
+            Key::Other(keysym::KP_6) => KeyCode::KEY_KP6,
+            Key::Other(keysym::KP_7) => KeyCode::KEY_KP7,
+            Key::Other(keysym::KP_8) => KeyCode::KEY_KP8,
+            Key::Other(keysym::KP_9) => KeyCode::KEY_KP9,
+            Key::VolumeUp => KeyCode::KEY_VOLUMEUP,
+            Key::VolumeDown => KeyCode::KEY_VOLUMEDOWN,
+            Key::VolumeMute => KeyCode::KEY_MUTE,
+            Key::MediaNextTrack => KeyCode::KEY_NEXTSONG,
+            Key::MediaPrevTrack => KeyCode::KEY_PREVIOUSSONG,
+            Key::MediaPlayPause => KeyCode::KEY_PLAYPAUSE,
+            Key::Unicode(char) => return UinputOutput::char_key_code(char),
+            _ => return None,
+        };
+        Some(key_code)
+    }
+}
+
+#[cfg(target_os = "linux")]
+impl ActionSequencerOutput for UinputOutput {
+    fn key(&mut self, key: enigo::Key, direction: enigo::Direction) -> Result<(), String> {
+        let key_code = UinputOutput::key_code(key).ok_or_else(|| format!("{:?} is not supported by the uinput output", key))?;
+        let values: &[i32] = match direction {
+            enigo::Direction::Press => &[1],
+            enigo::Direction::Release => &[0],
+            enigo::Direction::Click => &[1, 0],
+        };
+        for value in values {
+            /* emit appends the SYN_REPORT */
+            self.device
+                .emit(&[evdev::InputEvent::new(evdev::EventType::KEY.0, key_code.0, *value)])
+                .map_err(|e| format!("Could not send {:?} {:?}: {}", direction, key, e))?;
+        }
+        Ok(())
+    }
+
+    fn button(&mut self, button: enigo::Button, direction: enigo::Direction) -> Result<(), String> {
+        let key_code = match button {
+            enigo::Button::Left => evdev::KeyCode::BTN_LEFT,
+            enigo::Button::Middle => evdev::KeyCode::BTN_MIDDLE,
+            enigo::Button::Right => evdev::KeyCode::BTN_RIGHT,
+            _ => return Err(format!("The {:?} mouse button is not supported by the uinput output", button)),
+        };
+        let values: &[i32] = match direction {
+            enigo::Direction::Press => &[1],
+            enigo::Direction::Release => &[0],
+            enigo::Direction::Click => &[1, 0],
+        };
+        for value in values {
+            self.device
+                .emit(&[evdev::InputEvent::new(evdev::EventType::KEY.0, key_code.0, *value)])
+                .map_err(|e| format!("Could not send {:?} {:?} mouse button: {}", direction, button, e))?;
+        }
+        Ok(())
+    }
+
+    fn scroll(&mut self, amount: i32, axis: enigo::Axis) -> Result<(), String> {
+        /* REL_WHEEL scrolls up for positive values */
+        let (axis_code, value) = match axis {
+            enigo::Axis::Vertical => (evdev::RelativeAxisCode::REL_WHEEL, -amount),
+            enigo::Axis::Horizontal => (evdev::RelativeAxisCode::REL_HWHEEL, amount),
+        };
+        self.device
+            .emit(&[evdev::InputEvent::new(evdev::EventType::RELATIVE.0, axis_code.0, value)])
+            .map_err(|e| format!("Could not scroll {} {:?}: {}", amount, axis, e))
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32) -> Result<(), String> {
+        self.device
+            .emit(&[
+                evdev::InputEvent::new(evdev::EventType::RELATIVE.0, evdev::RelativeAxisCode::REL_X.0, x),
+                evdev::InputEvent::new(evdev::EventType::RELATIVE.0, evdev::RelativeAxisCode::REL_Y.0, y),
+            ])
+            .map_err(|e| format!("Could not move the mouse by {},{}: {}", x, y, e))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum RecordedOutputEvent {
+    Key(enigo::Key, enigo::Direction),
+    Button(enigo::Button, enigo::Direction),
+    Scroll(i32, enigo::Axis),
+    MoveMouse(i32, i32),
+}
+
+/**
+ * Keeps the key and mouse events in memory instead of sending them - clones share the same recording
+ */
+#[derive(Debug, Clone, Default)]
+pub struct RecordingOutput {
+    events: Arc<Mutex<Vec<RecordedOutputEvent>>>,
+}
+
+impl RecordingOutput {
+    pub fn new() -> RecordingOutput {
+        RecordingOutput::default()
+    }
+
+    pub fn events(&self) -> Vec<RecordedOutputEvent> {
+        self.events.lock().unwrap().clone()
+    }
+
+    fn record(&self, event: RecordedOutputEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+}
+
+impl ActionSequencerOutput for RecordingOutput {
+    fn key(&mut self, key: enigo::Key, direction: enigo::Direction) -> Result<(), String> {
+        self.record(RecordedOutputEvent::Key(key, direction));
+        Ok(())
+    }
+
+    fn button(&mut self, button: enigo::Button, direction: enigo::Direction) -> Result<(), String> {
+        self.record(RecordedOutputEvent::Button(button, direction));
+        Ok(())
+    }
+
+    fn scroll(&mut self, amount: i32, axis: enigo::Axis) -> Result<(), String> {
+        self.record(RecordedOutputEvent::Scroll(amount, axis));
+        Ok(())
+    }
+
+    fn move_mouse(&mut self, x: i32, y: i32) -> Result<(), String> {
+        self.record(RecordedOutputEvent::MoveMouse(x, y));
+        Ok(())
+    }
+}
@@ -0,0 +1,365 @@
+use std::collections::HashMap;
+
+use bezier_easing::bezier_easing;
+use eframe::egui;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tokio::sync::watch;
+
+use crate::{
+    config_defs::{
+        controller_calibration::{ControllerCalibration, ControllerCalibrationData},
+        controller_sdl_map::SDLControlKind,
+        usb_id::UsbId,
+    },
+    config_loader::ConfigLoader,
+    controller_manager::{ControllerManagerChangeEvent, ControllerManagerConnectedController},
+};
+
+const RAW_AXIS_MIN: f32 = i16::MIN as f32;
+const RAW_AXIS_MAX: f32 = i16::MAX as f32;
+const CURVE_EDITOR_SIZE: f32 = 200.0;
+const CURVE_HANDLE_RADIUS: f32 = 6.0;
+
+/**
+ * Calibration panel of the UI - shows the raw and normalised values of the connected controllers
+ * and edits a working copy of their calibration which is written through ConfigLoader::export
+ */
+pub struct CalibrationEditor {
+    config_dir: String,
+    connected_controllers: watch::Receiver<Vec<ControllerManagerConnectedController>>,
+    controller_events: broadcast::Receiver<ControllerManagerChangeEvent>,
+
+    /* latest raw value by usb_id, device alias and control name */
+    raw_values: HashMap<(UsbId, Option<String>, String), i16>,
+    selected_controller: Option<ControllerManagerConnectedController>,
+    selected_control: Option<String>,
+    /* working copy of the selected controller's calibration */
+    calibration: Option<ControllerCalibration>,
+    status: Option<String>,
+}
+
+impl CalibrationEditor {
+    pub fn new<T: AsRef<str>>(
+        config_dir: T,
+        connected_controllers: watch::Receiver<Vec<ControllerManagerConnectedController>>,
+        controller_events: broadcast::Receiver<ControllerManagerChangeEvent>,
+    ) -> CalibrationEditor {
+        CalibrationEditor {
+            config_dir: String::from(config_dir.as_ref()),
+            connected_controllers,
+            controller_events,
+            raw_values: HashMap::new(),
+            selected_controller: None,
+            selected_control: None,
+            calibration: None,
+            status: None,
+        }
+    }
+
+    fn receive_controller_events(&mut self) {
+        loop {
+            match self.controller_events.try_recv() {
+                Ok(event) => {
+                    self.raw_values.insert((event.usb_id, event.device_alias, event.control_name), event.control_state.raw_value);
+                }
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+
+    fn raw_value(&self, control_name: &String) -> Option<i16> {
+        let controller = self.selected_controller.as_ref()?;
+        self.raw_values.get(&(controller.identity.usb_id, controller.device_alias.clone(), control_name.clone())).cloned()
+    }
+
+    fn select_controller(&mut self, controller: Option<ControllerManagerConnectedController>, config: &ConfigLoader) {
+        self.calibration = controller.as_ref().map(|controller| {
+            let calibration = config.find_controller_calibration_for_device(&controller.identity.usb_id, controller.device_alias.as_ref());
+            calibration.cloned().unwrap_or_else(|| ControllerCalibration {
+                usb_id: controller.identity.usb_id,
+                device: controller.device_alias.clone(),
+                data: vec![],
+            })
+        });
+        self.selected_controller = controller;
+        self.selected_control = None;
+        self.status = None;
+    }
+
+    fn save(&mut self) {
+        if let Some(calibration) = &self.calibration {
+            let mut config = ConfigLoader::new();
+            config.register_calibration(calibration.clone());
+            config.export(&self.config_dir);
+            self.status = Some(String::from("Saved - the calibration is applied once the config has been reloaded"));
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, config: &ConfigLoader) {
+        self.receive_controller_events();
+        let connected_controllers = self.connected_controllers.borrow().clone();
+
+        /* the selected controller was disconnected */
+        if let Some(selected_controller) = &self.selected_controller {
+            if !connected_controllers.iter().any(|c| c.identity == selected_controller.identity) {
+                self.select_controller(None, config);
+            }
+        }
+
+        let mut selected_controller = self.selected_controller.clone();
+        egui::ComboBox::from_label("Controller")
+            .selected_text(selected_controller.as_ref().map(controller_label).unwrap_or_default())
+            .show_ui(ui, |ui| {
+                for controller in connected_controllers.iter() {
+                    let is_selected = selected_controller.as_ref().map_or(false, |c| c.identity == controller.identity);
+                    if ui.selectable_label(is_selected, controller_label(controller)).clicked() {
+                        selected_controller = Some(controller.clone());
+                    }
+                }
+            });
+        if selected_controller.as_ref().map(|c| &c.identity) != self.selected_controller.as_ref().map(|c| &c.identity) {
+            self.select_controller(selected_controller, config);
+        }
+
+        let controller = match &self.selected_controller {
+            Some(controller) => controller.clone(),
+            None => {
+                if connected_controllers.is_empty() {
+                    ui.label("No controllers connected");
+                }
+                return;
+            }
+        };
+        let sdl_mapping = match config.find_sdl_mapping(&controller.identity.usb_id) {
+            Some(sdl_mapping) => sdl_mapping,
+            None => {
+                ui.label(format!(
+                    "There is no SDL mapping for {} - run the calibrate command to map its controls first",
+                    controller.identity.usb_id
+                ));
+                return;
+            }
+        };
+
+        ui.separator();
+        egui::Grid::new("calibration_controls").striped(true).show(ui, |ui| {
+            ui.strong("Control");
+            ui.strong("Raw");
+            ui.strong("Normalised");
+            ui.end_row();
+            for control in sdl_mapping.data.iter() {
+                let raw_value = self.raw_value(&control.name);
+                match control.kind {
+                    SDLControlKind::Axis => {
+                        let is_selected = self.selected_control.as_ref() == Some(&control.name);
+                        if ui.selectable_label(is_selected, &control.name).clicked() {
+                            self.selected_control = Some(control.name.clone());
+                        }
+                    }
+                    _ => {
+                        ui.label(&control.name);
+                    }
+                }
+                ui.label(raw_value.map(|value| value.to_string()).unwrap_or(String::from("-")));
+                let calibration_data = self.calibration.as_ref().and_then(|calibration| calibration.control_data(&control.name));
+                ui.label(match (&control.kind, raw_value, calibration_data) {
+                    (SDLControlKind::Axis, Some(raw_value), Some(calibration_data)) => match calibration_data.normalize(raw_value) {
+                        Some(value) => format!("{:.3}", value),
+                        None => String::from("deadzone"),
+                    },
+                    (SDLControlKind::Axis, Some(_), None) => String::from("not calibrated"),
+                    _ => String::from("-"),
+                });
+                ui.end_row();
+            }
+        });
+
+        let selected_control = match &self.selected_control {
+            Some(selected_control) => selected_control.clone(),
+            None => {
+                ui.label("Select an axis to edit its calibration");
+                return;
+            }
+        };
+        let raw_value = self.raw_value(&selected_control);
+
+        ui.separator();
+        let calibration = self.calibration.as_mut().unwrap();
+        let control_index = match calibration.data.iter().position(|c| c.id == selected_control) {
+            Some(control_index) => control_index,
+            None => {
+                if ui.button(format!("Add calibration for {}", selected_control)).clicked() {
+                    let mut control_calibration = ControllerCalibrationData::new(&selected_control);
+                    control_calibration.min = RAW_AXIS_MIN;
+                    control_calibration.max = RAW_AXIS_MAX;
+                    calibration.data.push(control_calibration);
+                }
+                return;
+            }
+        };
+        let control_calibration = &mut calibration.data[control_index];
+
+        ui.horizontal(|ui| {
+            ui.vertical(|ui| {
+                edit_calibration_values(ui, control_calibration, raw_value);
+            });
+            ui.vertical(|ui| {
+                edit_easing_curve(ui, control_calibration, raw_value);
+            });
+        });
+
+        ui.separator();
+        ui.horizontal(|ui| {
+            if ui.button("Save").clicked() {
+                self.save();
+            }
+            if ui.button("Revert").clicked() {
+                self.select_controller(Some(controller.clone()), config);
+                self.selected_control = Some(selected_control.clone());
+            }
+            if let Some(status) = &self.status {
+                ui.label(status);
+            }
+        });
+    }
+}
+
+pub fn controller_label(controller: &ControllerManagerConnectedController) -> String {
+    match &controller.device_alias {
+        Some(alias) => format!("{} ({})", controller.name, alias),
+        None => format!("{} ({})", controller.name, controller.identity.usb_id),
+    }
+}
+
+fn raw_value_slider<'a>(value: &'a mut f32, text: &str) -> egui::Slider<'a> {
+    egui::Slider::new(value, RAW_AXIS_MIN..=RAW_AXIS_MAX).text(text).step_by(1.0)
+}
+
+fn edit_calibration_values(ui: &mut egui::Ui, control_calibration: &mut ControllerCalibrationData, raw_value: Option<i16>) {
+    /* min, idle and max are compared against the inverted value */
+    let value = raw_value.map(|raw_value| match control_calibration.invert {
+        Some(true) => -(raw_value as f32),
+        _ => raw_value as f32,
+    });
+
+    for (text, calibration_value) in [("min", &mut control_calibration.min), ("idle", &mut control_calibration.idle), ("max", &mut control_calibration.max)] {
+        ui.horizontal(|ui| {
+            ui.add(raw_value_slider(calibration_value, text));
+            if let Some(value) = value {
+                if ui.small_button("use current").clicked() {
+                    *calibration_value = value;
+                }
+            }
+        });
+    }
+
+    let mut deadzone = control_calibration.deadzone.unwrap_or(0.0);
+    ui.add(egui::Slider::new(&mut deadzone, 0.0..=8192.0).text("deadzone").step_by(1.0));
+    control_calibration.deadzone = match deadzone > 0.0 {
+        true => Some(deadzone),
+        false => None,
+    };
+
+    let mut invert = control_calibration.invert.unwrap_or(false);
+    ui.checkbox(&mut invert, "invert");
+    control_calibration.invert = Some(invert);
+
+    paint_raw_range(ui, control_calibration, value);
+    paint_normalized_value(ui, raw_value.and_then(|raw_value| control_calibration.normalize(raw_value)));
+}
+
+/**
+ * Raw axis range with the calibrated min..max range, the deadzone around idle, the detents and the current value
+ */
+fn paint_raw_range(ui: &mut egui::Ui, control_calibration: &ControllerCalibrationData, value: Option<f32>) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width().max(200.0), 24.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let x_for = |value: f32| rect.left() + (value - RAW_AXIS_MIN) / (RAW_AXIS_MAX - RAW_AXIS_MIN) * rect.width();
+    let visuals = ui.visuals();
+
+    painter.rect_filled(rect, 2.0, visuals.extreme_bg_color);
+    let range_rect = egui::Rect::from_x_y_ranges(x_for(control_calibration.min)..=x_for(control_calibration.max), rect.y_range());
+    painter.rect_filled(range_rect, 2.0, visuals.selection.bg_fill.linear_multiply(0.5));
+    let deadzone = control_calibration.deadzone.unwrap_or(0.0);
+    let deadzone_rect = egui::Rect::from_x_y_ranges(x_for(control_calibration.idle - deadzone)..=x_for(control_calibration.idle + deadzone), rect.y_range());
+    painter.rect_filled(deadzone_rect, 0.0, egui::Color32::from_gray(128).linear_multiply(0.5));
+    painter.vline(x_for(control_calibration.idle), rect.y_range(), egui::Stroke::new(1.0, visuals.text_color()));
+    for detent in control_calibration.detents.iter().flatten() {
+        painter.vline(x_for(detent.position), rect.y_range(), egui::Stroke::new(1.0, visuals.warn_fg_color));
+    }
+    if let Some(value) = value {
+        painter.vline(x_for(value), rect.y_range(), egui::Stroke::new(2.0, egui::Color32::RED));
+    }
+}
+
+fn paint_normalized_value(ui: &mut egui::Ui, normalized_value: Option<f32>) {
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(ui.available_width().max(200.0), 12.0), egui::Sense::hover());
+    let painter = ui.painter_at(rect);
+    let x_for = |value: f32| rect.center().x + value.clamp(-1.0, 1.0) * rect.width() / 2.0;
+
+    painter.rect_filled(rect, 2.0, ui.visuals().extreme_bg_color);
+    if let Some(value) = normalized_value {
+        let value_rect = egui::Rect::from_x_y_ranges(x_for(value.min(0.0))..=x_for(value.max(0.0)), rect.y_range());
+        painter.rect_filled(value_rect, 0.0, ui.visuals().selection.bg_fill);
+    }
+    painter.vline(rect.center().x, rect.y_range(), egui::Stroke::new(1.0, ui.visuals().text_color()));
+}
+
+/**
+ * Bézier curve editor - the handles can be dragged, x is limited to 0..1 as required by the easing function
+ */
+fn edit_easing_curve(ui: &mut egui::Ui, control_calibration: &mut ControllerCalibrationData, raw_value: Option<i16>) {
+    let mut has_easing_curve = control_calibration.easing_curve.is_some();
+    ui.checkbox(&mut has_easing_curve, "easing curve");
+    control_calibration.easing_curve = match has_easing_curve {
+        true => Some(control_calibration.easing_curve.unwrap_or([0.0, 0.0, 1.0, 1.0])),
+        false => None,
+    };
+    let mut curve = match control_calibration.easing_curve {
+        Some(curve) => curve,
+        None => return,
+    };
+
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(CURVE_EDITOR_SIZE, CURVE_EDITOR_SIZE), egui::Sense::hover());
+    let to_screen = |x: f32, y: f32| egui::pos2(rect.left() + x * rect.width(), rect.bottom() - y * rect.height());
+    let stroke = egui::Stroke::new(1.0, ui.visuals().text_color());
+
+    for (handle_index, (anchor_x, anchor_y)) in [(0.0, 0.0), (1.0, 1.0)].into_iter().enumerate() {
+        let handle_pos = to_screen(curve[handle_index * 2], curve[handle_index * 2 + 1]);
+        let handle_rect = egui::Rect::from_center_size(handle_pos, egui::Vec2::splat(CURVE_HANDLE_RADIUS * 3.0));
+        let response = ui.interact(handle_rect, ui.id().with(("easing_curve_handle", handle_index)), egui::Sense::drag());
+        if response.dragged() {
+            let delta = response.drag_delta();
+            curve[handle_index * 2] = (curve[handle_index * 2] + delta.x / rect.width()).clamp(0.0, 1.0);
+            curve[handle_index * 2 + 1] = (curve[handle_index * 2 + 1] - delta.y / rect.height()).clamp(0.0, 1.0);
+        }
+        let painter = ui.painter_at(rect.expand(CURVE_HANDLE_RADIUS));
+        painter.line_segment([to_screen(anchor_x, anchor_y), to_screen(curve[handle_index * 2], curve[handle_index * 2 + 1])], stroke);
+        painter.circle_filled(to_screen(curve[handle_index * 2], curve[handle_index * 2 + 1]), CURVE_HANDLE_RADIUS, ui.visuals().selection.bg_fill);
+    }
+
+    ui.horizontal(|ui| {
+        for value in curve.iter_mut() {
+            ui.add(egui::DragValue::new(value).speed(0.01).range(0.0..=1.0).max_decimals(2));
+        }
+    });
+    control_calibration.easing_curve = Some(curve);
+
+    let painter = ui.painter_at(rect);
+    painter.rect_stroke(rect, 0.0, egui::Stroke::new(1.0, ui.visuals().weak_text_color()));
+    if let Ok(ease) = bezier_easing(curve[0], curve[1], curve[2], curve[3]) {
+        let points: Vec<egui::Pos2> = (0..=50).map(|step| step as f32 / 50.0).map(|x| to_screen(x, ease(x))).collect();
+        painter.add(egui::Shape::line(points, egui::Stroke::new(2.0, ui.visuals().text_color())));
+
+        /* the linear input is the normalised value without the curve */
+        let linear_calibration = ControllerCalibrationData {
+            easing_curve: None,
+            ..control_calibration.clone()
+        };
+        if let Some(linear_value) = raw_value.and_then(|raw_value| linear_calibration.normalize(raw_value)) {
+            let x = linear_value.abs().clamp(0.0, 1.0);
+            painter.circle_filled(to_screen(x, ease(x)), 4.0, egui::Color32::RED);
+        }
+    }
+}
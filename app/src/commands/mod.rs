@@ -1 +1,5 @@
 pub mod run_calibration_mode;
+pub mod run_headless_mode;
+pub mod run_list_keys_mode;
+pub mod run_schema_export_mode;
+pub mod run_validation_mode;
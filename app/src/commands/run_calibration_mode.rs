@@ -1,194 +1,419 @@
-use std::{collections::HashMap, sync::Arc};
-use tokio::io::{self, AsyncBufReadExt, AsyncWriteExt, BufReader};
-use tokio::sync::Mutex;
+use std::{
+    collections::{HashMap, HashSet},
+    io::Write,
+    sync::Arc,
+};
+use tokio::io::{self, AsyncBufReadExt, BufReader};
+use tokio::sync::broadcast::{error::RecvError, Receiver};
+use tokio::sync::{mpsc, Mutex};
+use tokio::time::{sleep_until, Duration, Instant};
 use tokio_util::sync::CancellationToken;
 
+use sdl2::{event::Event, joystick::HatState};
+
 use crate::{
     config_defs::{
-        controller_calibration::{ControllerCalibration, ControllerCalibrationData},
+        controller_calibration::{ControllerCalibration, ControllerCalibrationData, ControllerCalibrationDetent},
         controller_sdl_map::{ControllerSdlMap, ControllerSdlMapControl, SDLControlKind},
+        usb_id::UsbId,
     },
     config_loader,
     controller_manager::{self, ControllerManagerRawEvent},
 };
 
-pub async fn run_calibration_mode<T: AsRef<str>>(config_dir: T) {
-    println!("Running calibration mode; press Q and hit enter to stop and write config files.");
+/* an axis is selected once it moved this far away from where it was when the wizard started waiting (a quarter of the half range) */
+const AXIS_SELECT_THRESHOLD: i32 = 8192;
+const REST_SAMPLE_DURATION: Duration = Duration::from_secs(2);
+/* the deadzone covers the noise at rest with some margin but is at least a fraction of the full range */
+const DEADZONE_NOISE_FACTOR: f32 = 2.0;
+const MIN_DEADZONE_RANGE_FRACTION: f32 = 0.01;
+/* sweeps covering less than a quarter of the i16 range are repeated */
+const MIN_SWEEP_RANGE: i32 = 16384;
+/* rest positions this close to an end of the range are levers resting at that end rather than centre-sprung axes */
+const REST_AT_END_RANGE_FRACTION: f32 = 0.1;
 
-    let config = config_loader::ConfigLoader::new();
-    let config_arc = Arc::new(config);
-    let mut controller_manager = controller_manager::ControllerManager::new(Arc::clone(&config_arc));
+#[derive(Clone)]
+struct CalibrationWizardControl {
+    joystick_index: u32,
+    joystick_usb_id: UsbId,
+    joystick_device_alias: Option<String>,
+    joystick_name: String,
+    kind: SDLControlKind,
+    index: u8,
+}
 
-    let cancel_token = CancellationToken::new();
-    let receiver = controller_manager.raw_receiver();
+impl CalibrationWizardControl {
+    fn kind_name(&self) -> &'static str {
+        match self.kind {
+            SDLControlKind::Axis => "Axis",
+            SDLControlKind::Button => "Button",
+            SDLControlKind::Hat => "Hat",
+        }
+    }
 
-    let controller_sdl_mappings = Arc::new(Mutex::new(HashMap::<String, ControllerSdlMap>::new()));
-    let controller_calibrations = Arc::new(Mutex::new(HashMap::<String, ControllerCalibration>::new()));
+    fn default_name(&self) -> String {
+        format!("{}{}", self.kind_name(), self.index)
+    }
 
-    let cancel_token_clone = cancel_token.clone();
-    let controller_sdl_mappings_task_arc = Arc::clone(&controller_sdl_mappings);
-    let controller_calibrations_task_arc = Arc::clone(&controller_calibrations);
-    let event_listener_task = tokio::task::spawn(async move {
-        let stdin = io::stdin();
-        let mut stdout = io::stdout();
-        let mut stdin_reader = BufReader::new(stdin);
-        let (stdin_read_channel_tx, mut stdin_read_channel_rx) = tokio::sync::mpsc::channel::<Result<String, String>>(10);
+    /* identical controllers with an alias are calibrated separately */
+    fn calibration_key(&self) -> String {
+        self.joystick_device_alias.clone().unwrap_or(self.joystick_usb_id.to_string())
+    }
+}
 
+enum CalibrationWizardInput {
+    Line(String),
+    Event(ControllerManagerRawEvent),
+    Closed,
+}
+
+/**
+ * Walks through the controls one by one: the control is selected by moving it, then named, and axes are calibrated
+ * by measuring the rest position, sweeping the full range and optionally marking detents.
+ */
+struct CalibrationWizard {
+    receiver: Arc<Mutex<Receiver<ControllerManagerRawEvent>>>,
+    input_lines: mpsc::Receiver<String>,
+    /* last known value of every axis by joystick and axis index */
+    axis_values: HashMap<(u32, u8), i16>,
+    sdl_mappings: HashMap<UsbId, ControllerSdlMap>,
+    calibrations: HashMap<String, ControllerCalibration>,
+    changed_sdl_mappings: HashSet<UsbId>,
+    changed_calibrations: HashSet<String>,
+}
+
+impl CalibrationWizard {
+    async fn run(&mut self) {
         loop {
-            let mut last_line_input = String::new();
+            println!();
+            println!("Move or press the control you want to calibrate (type Q and hit enter to finish)");
+            let control = match self.wait_for_control().await {
+                Some(control) => control,
+                None => break,
+            };
+            if self.calibrate_control(&control).await.is_none() {
+                break;
+            }
+        }
+    }
+
+    async fn next_input(&mut self) -> CalibrationWizardInput {
+        let receiver = Arc::clone(&self.receiver);
+        let mut receiver_lock = receiver.lock().await;
+        let input = loop {
             tokio::select! {
-                _ = cancel_token_clone.cancelled() => {
-                        break;
-                }
-              read_result = stdin_reader.read_line(&mut last_line_input) => {
-                match read_result {
-                  Ok(_) => {
-                    if last_line_input.to_lowercase().trim() == "q" {
-                      cancel_token_clone.cancel();
-                      break;
-                    }
-                    stdin_read_channel_tx.send(Ok(last_line_input.clone())).await.unwrap();
-                  },
-                  Err(_) => {
-                    stdin_read_channel_tx.send(Err("Could not read line".to_string())).await.unwrap();
-                  }
-                };
-              },
-                _ = async {
-                  use sdl2::event::Event;
-
-                  let raw_event: ControllerManagerRawEvent = receiver.lock().await.recv().await.unwrap();
-
-                  let mut sdl_mapping_lock = controller_sdl_mappings_task_arc.lock().await;
-                  let mut controller_calibrations_lock = controller_calibrations_task_arc.lock().await;
-                  let existing_sdl_map = sdl_mapping_lock.get_mut(&raw_event.joystick_usb_id);
-                  let existing_calibration = controller_calibrations_lock.get_mut(&raw_event.joystick_usb_id);
-
-                  let vendor_id = unsafe { sdl2_sys::SDL_JoystickGetDeviceVendor(raw_event.joystick_index as i32) };
-                  let product_id = unsafe { sdl2_sys::SDL_JoystickGetDeviceProduct(raw_event.joystick_index as i32) };
-
-                  let mut controller_sdl_map: ControllerSdlMap = match &existing_sdl_map {
-                    Some(sdl_map) => (*sdl_map).clone(),
-                    None => ControllerSdlMap {
-                      usb_id: format!("{:04x}:{:04x}", vendor_id, product_id),
-                      name: "Unknown".to_string(),
-                      data: vec![],
-                    }
-                  };
-                  let mut controller_calibration: ControllerCalibration = match &existing_calibration {
-                    Some(calibration) => (*calibration).clone(),
-                    None => ControllerCalibration {
-                      usb_id: format!("{:04x}:{:04x}", vendor_id, product_id),
-                      data: vec![],
-                    }
-                  };
-
-                  match raw_event.event {
-                    Event::JoyAxisMotion { axis_idx, value, .. } => {
-                      stdout.write_all(format!("[{}][{}] Axis {} moved to {}\n", raw_event.joystick_usb_id, raw_event.joystick_name, axis_idx, value).as_bytes()).await.unwrap();
-                      stdout.flush().await.unwrap();
-
-                      let control_name = format!("Axis{}", axis_idx);
-                      if !controller_sdl_map.data.iter().any(|c| c.kind == SDLControlKind::Axis && c.index == axis_idx) {
-                        stdout.write_all(b"Enter common name for this axis: ").await.unwrap();
-                        stdout.flush().await.unwrap();
-                        let input = stdin_read_channel_rx.recv().await.unwrap().expect("Could not read input");
-
-                        controller_sdl_map.data.push(ControllerSdlMapControl {
-                          kind: SDLControlKind::Axis,
-                          index: axis_idx,
-                          name: match input.trim().to_string() {
-                            s if s.is_empty() => control_name.clone(),
-                            s => s,
-                          },
-                        });
-                      }
-
-                      let existing_calibration_index = controller_calibration.data.iter().position(|c| c.id == control_name).unwrap_or(controller_calibration.data.len());
-                      let mut control_calibration: ControllerCalibrationData = match &controller_calibration.data.get(existing_calibration_index) {
-                        Some(calibration) => (*calibration).clone(),
-                        None => {
-                          ControllerCalibrationData {
-                            id: control_name.clone(),
-                            min: 0.0f32,
-                            max: 1.0f32,
-                            deadzone: Some(0.0f32),
-                            idle: 0.0f32,
-                            easing_curve: None,
-                            invert: Some(false),
-                          }
-                        }
-                      };
-                      control_calibration.min = control_calibration.min.min(value as f32);
-                      control_calibration.idle = control_calibration.idle.min(value as f32);
-                      control_calibration.max = control_calibration.max.max(value as f32);
-                      if existing_calibration_index < controller_calibration.data.len() {
-                          controller_calibration.data[existing_calibration_index] = control_calibration;
-                      } else {
-                          controller_calibration.data.push(control_calibration);
-                      }
-                    },
-                    Event::JoyButtonDown {button_idx, ..} | Event::JoyButtonUp {button_idx, ..} => {
-                      stdout.write_all(format!("[{}][{}] Button {} triggered\n",  raw_event.joystick_usb_id, raw_event.joystick_name, button_idx).as_bytes()).await.unwrap();
-                      stdout.flush().await.unwrap();
-
-                      if !controller_sdl_map.data.iter().any(|c| c.kind == SDLControlKind::Button && c.index == button_idx) {
-                        stdout.write_all(b"Enter common name for this button: ").await.unwrap();
-                        stdout.flush().await.unwrap();
-                        let input = stdin_read_channel_rx.recv().await.unwrap().expect("Could not read input");
-
-                        controller_sdl_map.data.push(ControllerSdlMapControl {
-                          kind: SDLControlKind::Button,
-                          index: button_idx,
-                          name: match input.trim().to_string() {
-                            s if s.is_empty() => format!("Button{}", button_idx),
-                            s => s,
-                          },
-                        });
-                      }
+                line = self.input_lines.recv() => break match line {
+                    Some(line) => CalibrationWizardInput::Line(line),
+                    None => CalibrationWizardInput::Closed,
+                },
+                event = receiver_lock.recv() => match event {
+                    Ok(event) => break CalibrationWizardInput::Event(event),
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => break CalibrationWizardInput::Closed,
+                },
+            }
+        };
+
+        if let CalibrationWizardInput::Event(raw_event) = &input {
+            if let Event::JoyAxisMotion { axis_idx, value, .. } = raw_event.event {
+                self.axis_values.insert((raw_event.joystick_index, axis_idx), value);
+            }
+        }
+        input
+    }
+
+    async fn prompt<T: AsRef<str>>(&mut self, text: T) -> Option<String> {
+        print!("{}", text.as_ref());
+        std::io::stdout().flush().unwrap();
+        self.read_line(None).await.map(|(line, _)| line)
+    }
+
+    /**
+     * Waits for the next input line while collecting the values of the given axis
+     */
+    async fn read_line(&mut self, axis: Option<(u32, u8)>) -> Option<(String, Vec<i16>)> {
+        let mut values = Vec::new();
+        loop {
+            match self.next_input().await {
+                CalibrationWizardInput::Line(line) => return Some((line.trim().to_string(), values)),
+                CalibrationWizardInput::Closed => return None,
+                CalibrationWizardInput::Event(raw_event) => match raw_event.event {
+                    Event::JoyAxisMotion { axis_idx, value, .. } if axis == Some((raw_event.joystick_index, axis_idx)) => values.push(value),
+                    _ => {}
+                },
+            }
+        }
+    }
+
+    async fn sample_axis(&mut self, axis: (u32, u8), duration: Duration) -> Option<Vec<i16>> {
+        /* SDL only reports changes - an axis at rest without noise doesn't send any events */
+        let mut values: Vec<i16> = self.axis_values.get(&axis).into_iter().cloned().collect();
+        let deadline = Instant::now() + duration;
+        loop {
+            tokio::select! {
+                _ = sleep_until(deadline) => break,
+                input = self.next_input() => match input {
+                    CalibrationWizardInput::Closed => return None,
+                    CalibrationWizardInput::Event(raw_event) => match raw_event.event {
+                        Event::JoyAxisMotion { axis_idx, value, .. } if axis == (raw_event.joystick_index, axis_idx) => values.push(value),
+                        _ => {}
                     },
-                    Event::JoyHatMotion {hat_idx, ..}  => {
-                      stdout.write_all(format!("[{}][{}] Hat {} triggered\n", raw_event.joystick_usb_id, raw_event.joystick_name, hat_idx).as_bytes()).await.unwrap();
-                      stdout.flush().await.unwrap();
-
-                      if !controller_sdl_map.data.iter().any(|c| c.kind == SDLControlKind::Hat && c.index == hat_idx) {
-                        stdout.write_all(b"Enter common name for this hat: ").await.unwrap();
-                        stdout.flush().await.unwrap();
-                        let input = stdin_read_channel_rx.recv().await.unwrap().expect("Could not read input");
-
-                        controller_sdl_map.data.push(ControllerSdlMapControl {
-                          kind: SDLControlKind::Hat,
-                          index: hat_idx,
-                          name: match input.trim().to_string() {
-                            s if s.is_empty() => format!("Hat{}", hat_idx),
-                            s => s,
-                          },
-                        });
-                      }
+                    CalibrationWizardInput::Line(_) => {}
+                },
+            }
+        }
+        Some(values)
+    }
+
+    async fn wait_for_control(&mut self) -> Option<CalibrationWizardControl> {
+        let mut axis_start_values = self.axis_values.clone();
+        loop {
+            let raw_event = match self.next_input().await {
+                CalibrationWizardInput::Line(line) if line.trim().eq_ignore_ascii_case("q") => return None,
+                CalibrationWizardInput::Line(_) => continue,
+                CalibrationWizardInput::Closed => return None,
+                CalibrationWizardInput::Event(raw_event) => raw_event,
+            };
+
+            let (kind, index) = match raw_event.event {
+                Event::JoyAxisMotion { axis_idx, value, .. } => {
+                    let start_value = *axis_start_values.entry((raw_event.joystick_index, axis_idx)).or_insert(value);
+                    if (value as i32 - start_value as i32).abs() < AXIS_SELECT_THRESHOLD {
+                        continue;
                     }
-                    _ => {},
-                  };
+                    (SDLControlKind::Axis, axis_idx)
+                }
+                Event::JoyButtonDown { button_idx, .. } => (SDLControlKind::Button, button_idx),
+                Event::JoyHatMotion { hat_idx, state, .. } if state != HatState::Centered => (SDLControlKind::Hat, hat_idx),
+                _ => continue,
+            };
+            return Some(CalibrationWizardControl {
+                joystick_index: raw_event.joystick_index,
+                joystick_usb_id: raw_event.joystick_usb_id,
+                joystick_device_alias: raw_event.joystick_device_alias,
+                joystick_name: raw_event.joystick_name,
+                kind,
+                index,
+            });
+        }
+    }
+
+    async fn calibrate_control(&mut self, control: &CalibrationWizardControl) -> Option<()> {
+        let existing_name = self
+            .sdl_mappings
+            .get(&control.joystick_usb_id)
+            .and_then(|sdl_map| sdl_map.data.iter().find(|c| c.kind == control.kind && c.index == control.index))
+            .map(|c| c.name.clone());
+        let default_name = existing_name.clone().unwrap_or(control.default_name());
+
+        println!("[{}][{}] {} {} selected", control.joystick_usb_id, control.joystick_name, control.kind_name(), control.index);
+        let name = match self.prompt(format!("Enter common name for this {} [{}]: ", control.kind_name().to_lowercase(), default_name)).await? {
+            input if input.is_empty() => default_name,
+            input => input,
+        };
+
+        let sdl_map = self.sdl_mappings.entry(control.joystick_usb_id).or_insert_with(|| ControllerSdlMap {
+            usb_id: control.joystick_usb_id,
+            name: control.joystick_name.clone(),
+            data: vec![],
+        });
+        sdl_map.data.retain(|c| !(c.kind == control.kind && c.index == control.index));
+        sdl_map.data.push(ControllerSdlMapControl {
+            kind: control.kind.clone(),
+            index: control.index,
+            name: name.clone(),
+        });
+        self.changed_sdl_mappings.insert(control.joystick_usb_id);
 
-                  sdl_mapping_lock.insert(raw_event.joystick_usb_id.clone(), controller_sdl_map);
-                  controller_calibrations_lock.insert(raw_event.joystick_usb_id.clone(), controller_calibration);
-                } => {}
+        if control.kind != SDLControlKind::Axis {
+            println!("Mapped {}", name);
+            return Some(());
+        }
+
+        let mut control_calibration = self.calibrate_axis(control, &name).await?;
+        let calibration = self.calibrations.entry(control.calibration_key()).or_insert_with(|| ControllerCalibration {
+            usb_id: control.joystick_usb_id,
+            device: control.joystick_device_alias.clone(),
+            data: vec![],
+        });
+        /* the calibration is matched by the name in the SDL mapping - drop the entry of the previous name */
+        if let Some(existing_name) = existing_name.as_ref().filter(|existing_name| *existing_name != &name) {
+            calibration.data.retain(|c| &c.id != existing_name);
+        }
+        match calibration.data.iter().position(|c| c.id == name) {
+            Some(existing_index) => {
+                control_calibration.easing_curve = calibration.data[existing_index].easing_curve;
+                calibration.data[existing_index] = control_calibration.clone();
+            }
+            None => calibration.data.push(control_calibration.clone()),
+        }
+        self.changed_calibrations.insert(control.calibration_key());
+
+        println!(
+            "Calibrated {}: min={} idle={} max={} deadzone={} invert={} detents={}",
+            name,
+            control_calibration.min,
+            control_calibration.idle,
+            control_calibration.max,
+            control_calibration.deadzone.unwrap_or(0.0),
+            control_calibration.invert.unwrap_or(false),
+            control_calibration.detents.as_ref().map_or(0, |detents| detents.len())
+        );
+        Some(())
+    }
+
+    async fn calibrate_axis(&mut self, control: &CalibrationWizardControl, name: &String) -> Option<ControllerCalibrationData> {
+        let axis = (control.joystick_index, control.index);
+
+        self.prompt(format!("Step 1/3: leave {} at rest and hit enter", name)).await?;
+        println!("Measuring the rest position..");
+        let rest_values = self.sample_axis(axis, REST_SAMPLE_DURATION).await?;
+
+        let sweep_values = loop {
+            print!("Step 2/3: move {} through its full range a few times and hit enter", name);
+            std::io::stdout().flush().unwrap();
+            let (_, sweep_values) = self.read_line(Some(axis)).await?;
+            let sweep_range = axis_sweep_range(&rest_values, &sweep_values);
+            if sweep_range >= MIN_SWEEP_RANGE {
+                break sweep_values;
+            }
+            println!("{} only moved by {} - move it from one end of its range to the other and try again", name, sweep_range);
+        };
+
+        println!(
+            "Step 3/3 (optional): move {} into a detent and enter a name for it - hit enter on an empty line when done. The output snaps to the value of the notch while the lever sits in the detent",
+            name
+        );
+        let mut detents = Vec::new();
+        loop {
+            let detent_name = self.prompt("Detent name: ").await?;
+            if detent_name.is_empty() {
+                break;
+            }
+            let position = *self.axis_values.get(&axis).unwrap_or(&0) as f32;
+            println!("Marked {} at {}", detent_name, position);
+            let value = loop {
+                let question = format!("Value of the {} notch in game from -1 to 1 (leave empty to only mark the position): ", detent_name);
+                match self.prompt(question).await? {
+                    input if input.is_empty() => break None,
+                    input => match input.parse::<f32>() {
+                        Ok(value) if (-1.0..=1.0).contains(&value) => break Some(value),
+                        _ => println!("Enter a number from -1 to 1"),
+                    },
+                }
+            };
+            detents.push(ControllerCalibrationDetent {
+                name: detent_name,
+                position,
+                value,
+                width: None,
+            });
+        }
+
+        Some(axis_calibration_from_samples(name, &rest_values, &sweep_values, detents))
+    }
+}
+
+/* the values the axis reported at rest and while sweeping span this far - a sweep without moving the lever would make it always read 0 */
+fn axis_sweep_range(rest_values: &Vec<i16>, sweep_values: &Vec<i16>) -> i32 {
+    let values = rest_values.iter().chain(sweep_values.iter()).map(|value| *value as i32);
+    values.clone().max().unwrap_or(0) - values.min().unwrap_or(0)
+}
+
+/**
+ * Derives the calibration from the measured values. Levers resting at the top of their range are inverted so they read 0 at rest.
+ */
+fn axis_calibration_from_samples(name: &String, rest_values: &Vec<i16>, sweep_values: &Vec<i16>, detents: Vec<ControllerCalibrationDetent>) -> ControllerCalibrationData {
+    let idle = rest_values.iter().map(|value| *value as f32).sum::<f32>() / rest_values.len().max(1) as f32;
+    let noise = rest_values.iter().map(|value| (*value as f32 - idle).abs()).fold(0.0f32, f32::max);
+    let (min, max) = rest_values
+        .iter()
+        .chain(sweep_values.iter())
+        .fold((idle, idle), |(min, max), value| (min.min(*value as f32), max.max(*value as f32)));
+    let range = max - min;
+    let deadzone = (noise * DEADZONE_NOISE_FACTOR).max(range * MIN_DEADZONE_RANGE_FRACTION).ceil();
+    let idle = idle.round();
+
+    let (min, idle, max, invert, detents) = if idle - min <= range * REST_AT_END_RANGE_FRACTION {
+        (idle, idle, max, false, detents)
+    } else if max - idle <= range * REST_AT_END_RANGE_FRACTION {
+        let inverted_detents = detents.into_iter().map(|detent| ControllerCalibrationDetent { position: -detent.position, ..detent }).collect();
+        (-idle, -idle, -min, true, inverted_detents)
+    } else {
+        (min, idle, max, false, detents)
+    };
+
+    ControllerCalibrationData {
+        id: name.clone(),
+        deadzone: Some(deadzone),
+        invert: Some(invert),
+        min,
+        max,
+        idle,
+        easing_curve: None,
+        detents: match detents.is_empty() {
+            true => None,
+            false => Some(detents),
+        },
+    }
+}
+
+pub async fn run_calibration_mode<T: AsRef<str>>(config_dir: T) {
+    println!("Running calibration mode; the controls are calibrated one by one. Type Q and hit enter to stop and write config files.");
+
+    /* existing mappings and calibrations are extended - re-calibrating a control replaces its entry */
+    let mut config = config_loader::ConfigLoader::new();
+    config.load_from_dir(Some(config_dir.as_ref()));
+    let sdl_mappings: HashMap<UsbId, ControllerSdlMap> = config.controller_sdl_mappings.iter().map(|sdl_map| (sdl_map.usb_id, sdl_map.clone())).collect();
+    let calibrations: HashMap<String, ControllerCalibration> = config
+        .controller_calibrations
+        .iter()
+        .map(|calibration| (calibration.device.clone().unwrap_or(calibration.usb_id.to_string()), calibration.clone()))
+        .collect();
+    let config_arc = Arc::new(config);
+    let mut controller_manager = controller_manager::ControllerManager::new(Arc::clone(&config_arc));
+
+    let (input_sender, input_lines) = mpsc::channel::<String>(10);
+    tokio::task::spawn(async move {
+        let mut lines = BufReader::new(io::stdin()).lines();
+        while let Ok(Some(line)) = lines.next_line().await {
+            if input_sender.send(line).await.is_err() {
+                break;
             }
         }
     });
 
+    let mut wizard = CalibrationWizard {
+        receiver: controller_manager.raw_receiver(),
+        input_lines,
+        axis_values: HashMap::new(),
+        sdl_mappings,
+        calibrations,
+        changed_sdl_mappings: HashSet::new(),
+        changed_calibrations: HashSet::new(),
+    };
+    let cancel_token = CancellationToken::new();
+    let wizard_cancel_token = cancel_token.clone();
+    let wizard_task = tokio::task::spawn(async move {
+        wizard.run().await;
+        wizard_cancel_token.cancel();
+        wizard
+    });
+
     controller_manager.attach(cancel_token.clone());
-    cancel_token.cancel();
-    event_listener_task.await.unwrap();
+    let wizard = wizard_task.await.unwrap();
 
     println!("Writing new config files..");
     let mut config = config_loader::ConfigLoader::new();
-    let sdl_mappings_lock = controller_sdl_mappings.lock().await;
-    let calibrations_lock = controller_calibrations.lock().await;
-    for (_, sdl_map) in sdl_mappings_lock.iter() {
-        config.register_sdl_mapping(sdl_map.clone());
+    for (usb_id, sdl_map) in wizard.sdl_mappings.into_iter() {
+        if wizard.changed_sdl_mappings.contains(&usb_id) {
+            config.register_sdl_mapping(sdl_map);
+        }
     }
-    for (_, calibration) in calibrations_lock.iter() {
-        config.register_calibration(calibration.clone());
+    for (key, calibration) in wizard.calibrations.into_iter() {
+        if wizard.changed_calibrations.contains(&key) {
+            config.register_calibration(calibration);
+        }
     }
     config.export(config_dir.as_ref());
 }
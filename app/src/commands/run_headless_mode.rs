@@ -0,0 +1,46 @@
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    config_defs::{app_settings::AppSettings, controller_profile::PreferredControlMode},
+    pipeline::Pipeline,
+};
+
+pub async fn run_headless_mode<T: AsRef<str>>(config_dir: T, profile: Option<String>, control_mode: PreferredControlMode, settings_overrides: AppSettings) -> Result<(), String> {
+    println!("Running headless mode; press Ctrl+C to stop.");
+
+    let cancel_token = CancellationToken::new();
+    let pipeline = Pipeline::start(config_dir, settings_overrides, cancel_token.clone()).await?;
+
+    if let Some(profile) = &profile {
+        if pipeline.config.borrow().find_controller_profile(profile, None).is_none() {
+            cancel_token.cancel();
+            pipeline.wait().await;
+            return Err(format!("Profile {} not found", profile));
+        }
+    }
+
+    pipeline.on_preferred_control_mode_change_sender.send(control_mode).unwrap();
+    pipeline.on_selected_profile_change_sender.send(profile).unwrap();
+
+    wait_for_shutdown_signal().await;
+    println!("Shutting down..");
+    cancel_token.cancel();
+    pipeline.wait().await;
+    Ok(())
+}
+
+#[cfg(unix)]
+async fn wait_for_shutdown_signal() {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sigterm = signal(SignalKind::terminate()).expect("Could not register SIGTERM handler");
+    tokio::select! {
+        _ = tokio::signal::ctrl_c() => {},
+        _ = sigterm.recv() => {},
+    }
+}
+
+#[cfg(not(unix))]
+async fn wait_for_shutdown_signal() {
+    tokio::signal::ctrl_c().await.expect("Could not register Ctrl+C handler");
+}
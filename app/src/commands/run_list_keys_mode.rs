@@ -0,0 +1,16 @@
+use crate::action_sequencer_keys::ActionSequencerKeyName;
+
+/**
+ * Prints the key names which can be combined with + in the keys of an action
+ */
+pub fn run_list_keys_mode() {
+    println!("Modifier keys (pressed before and released after the other keys):");
+    for key_name in ActionSequencerKeyName::all().iter().filter(|key_name| key_name.modifier) {
+        println!("  {}", key_name.names.join(", "));
+    }
+    println!("Keys:");
+    for key_name in ActionSequencerKeyName::all().iter().filter(|key_name| !key_name.modifier) {
+        println!("  {}", key_name.names.join(", "));
+    }
+    println!("Any other single character is typed as is - eg: a, 1, [");
+}
@@ -0,0 +1,35 @@
+use std::{fs, path::Path};
+
+use schemars::schema_for;
+
+use crate::config_defs::{
+    app_settings::AppSettings,
+    controller_calibration::ControllerCalibration,
+    controller_device::ControllerDevice,
+    controller_profile::{ControllerProfile, ControllerProfileFragment},
+    controller_sdl_map::ControllerSdlMap,
+};
+
+/**
+ * Writes the JSON schemas for the profile, fragment, device, calibration, SDL mapping and settings files
+ */
+pub fn run_schema_export_mode<T: AsRef<str>>(output_dir: T) -> Result<(), String> {
+    let output_dir = Path::new(output_dir.as_ref());
+    fs::create_dir_all(output_dir).map_err(|e| format!("Could not create schema directory {:?}: {}", output_dir, e))?;
+
+    let schemas = [
+        ("controller_profile.schema.json", schema_for!(ControllerProfile)),
+        ("controller_profile_fragment.schema.json", schema_for!(ControllerProfileFragment)),
+        ("controller_device.schema.json", schema_for!(ControllerDevice)),
+        ("controller_calibration.schema.json", schema_for!(ControllerCalibration)),
+        ("controller_sdl_map.schema.json", schema_for!(ControllerSdlMap)),
+        ("app_settings.schema.json", schema_for!(AppSettings)),
+    ];
+    for (file_name, schema) in schemas.iter() {
+        let file_path = output_dir.join(file_name);
+        let json = serde_json::to_string_pretty(schema).unwrap();
+        fs::write(&file_path, json).map_err(|e| format!("Could not write schema {:?}: {}", file_path, e))?;
+        println!("Wrote {:?}", file_path);
+    }
+    Ok(())
+}
@@ -0,0 +1,20 @@
+use crate::config_validator::ConfigValidator;
+
+/**
+ * Validates the config dir and prints all issues; returns false if any issues were found
+ */
+pub fn run_validation_mode<T: AsRef<str>>(config_dir: T) -> bool {
+    println!("Validating config in {}", config_dir.as_ref());
+
+    let issues = ConfigValidator::new().validate_dir(config_dir.as_ref());
+    for issue in issues.iter() {
+        eprintln!("{}", issue);
+    }
+
+    if issues.is_empty() {
+        println!("No issues found");
+        return true;
+    }
+    eprintln!("Found {} issue(s)", issues.len());
+    false
+}
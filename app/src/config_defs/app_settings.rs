@@ -0,0 +1,59 @@
+use std::net::{IpAddr, SocketAddr};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::action_sequencer_output::ActionSequencerOutputBackend;
+
+pub const DEFAULT_BIND_ADDRESS: &str = "127.0.0.1";
+pub const DEFAULT_DIRECT_CONTROL_PORT: u16 = 63241;
+pub const DEFAULT_SYNC_CONTROL_PORT: u16 = 63242;
+pub const DEFAULT_CONTROL_API_PORT: u16 = 63243;
+
+/**
+ * App wide settings from the settings.json file in the config dir - every field can be left out to use the default
+ */
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct AppSettings {
+    /* address the direct control, sync control and control API servers listen on - 0.0.0.0 accepts connections from other machines */
+    pub bind_address: Option<String>,
+    pub direct_control_port: Option<u16>,
+    pub sync_control_port: Option<u16>,
+    pub control_api_port: Option<u16>,
+    /* how key presses are sent to the game - defaults to enigo */
+    pub output_backend: Option<ActionSequencerOutputBackend>,
+}
+
+impl AppSettings {
+    /**
+     * Settings which are set in the overrides (eg: from the command line) take precedence
+     */
+    pub fn merge(self, overrides: AppSettings) -> AppSettings {
+        AppSettings {
+            bind_address: overrides.bind_address.or(self.bind_address),
+            direct_control_port: overrides.direct_control_port.or(self.direct_control_port),
+            sync_control_port: overrides.sync_control_port.or(self.sync_control_port),
+            control_api_port: overrides.control_api_port.or(self.control_api_port),
+            output_backend: overrides.output_backend.or(self.output_backend),
+        }
+    }
+
+    fn bind_ip(&self) -> Result<IpAddr, String> {
+        let bind_address = self.bind_address.as_deref().unwrap_or(DEFAULT_BIND_ADDRESS);
+        bind_address
+            .parse::<IpAddr>()
+            .map_err(|_| format!("Invalid bind_address \"{}\", expected an IP address (eg: 127.0.0.1)", bind_address))
+    }
+
+    pub fn direct_control_addr(&self) -> Result<SocketAddr, String> {
+        Ok(SocketAddr::new(self.bind_ip()?, self.direct_control_port.unwrap_or(DEFAULT_DIRECT_CONTROL_PORT)))
+    }
+
+    pub fn sync_control_addr(&self) -> Result<SocketAddr, String> {
+        Ok(SocketAddr::new(self.bind_ip()?, self.sync_control_port.unwrap_or(DEFAULT_SYNC_CONTROL_PORT)))
+    }
+
+    pub fn control_api_addr(&self) -> Result<SocketAddr, String> {
+        Ok(SocketAddr::new(self.bind_ip()?, self.control_api_port.unwrap_or(DEFAULT_CONTROL_API_PORT)))
+    }
+}
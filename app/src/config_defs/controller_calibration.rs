@@ -1,7 +1,10 @@
 use bezier_easing::bezier_easing;
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+use super::usb_id::UsbId;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerCalibrationData {
     /** the ID of the controller button or trigger as named in the controller mapping config (see other file - eg: "throttle1", "throttle2", "button1") */
     pub id: String,
@@ -11,16 +14,32 @@ pub struct ControllerCalibrationData {
     pub max: f32,
     pub idle: f32,
     pub easing_curve: Option<[f32; 4]>,
+    /** physical notches of the lever - detents with a value turn the calibration into piecewise-linear segments between min, idle, the detents and max */
+    pub detents: Option<Vec<ControllerCalibrationDetent>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerCalibrationDetent {
+    /** eg: "idle", "lap" */
+    pub name: String,
+    /** raw axis value of the detent - in the same units as min, idle and max */
+    pub position: f32,
+    /** normalised value of the matching notch in game; the output snaps to it while the lever sits in the detent */
+    pub value: Option<f32>,
+    /** raw distance from the position within which the output snaps to the value - defaults to the deadzone */
+    pub width: Option<f32>,
 }
 
 /**
  * This struct defines the controller calibration data that is stored in the config file.
  * Will match by ID first name second
  */
-#[derive(Clone, Serialize, Deserialize)]
+#[derive(Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerCalibration {
     /* {0xVENDOR_ID}:{0xPRODUCT_ID} */
-    pub usb_id: String,
+    pub usb_id: UsbId,
+    /* alias of a specific device (see config/devices) - takes precedence over calibrations which only match by usb_id */
+    pub device: Option<String>,
     pub data: Vec<ControllerCalibrationData>,
 }
 
@@ -34,6 +53,7 @@ impl ControllerCalibrationData {
             max: 0.0,
             idle: 0.0,
             easing_curve: None,
+            detents: None,
         }
     }
 
@@ -57,6 +77,10 @@ impl ControllerCalibrationData {
             return None;
         }
 
+        if let Some(normalized_value) = self.normalize_piecewise(value, idle_range) {
+            return Some(normalized_value);
+        }
+
         let easing_curve = match self.easing_curve {
             Some(curve) => curve,
             None => [0.0, 0.0, 1.0, 1.0],
@@ -82,6 +106,44 @@ impl ControllerCalibrationData {
             .abs();
         return Some(ease(abs_value));
     }
+
+    /**
+     * Maps the value through the linear segments between min (-1), the idle range (0), the detents and max (1).
+     * Returns None if no detent defines a value; the easing curve doesn't apply to piecewise calibrations.
+     */
+    fn normalize_piecewise(&self, value: f32, idle_range: [f32; 2]) -> Option<f32> {
+        let detents: Vec<(&ControllerCalibrationDetent, f32)> = self.detents.iter().flatten().filter_map(|detent| detent.value.map(|detent_value| (detent, detent_value))).collect();
+        if detents.is_empty() {
+            return None;
+        }
+
+        let mut points: Vec<(f32, f32)> = vec![(idle_range[0], 0.0), (idle_range[1], 0.0), (self.max, 1.0)];
+        if self.min != self.idle {
+            points.push((self.min, -1.0));
+        }
+        /* each detent is a flat segment so the output snaps to the notch while the lever sits in the detent */
+        for (detent, detent_value) in detents.iter() {
+            let width = detent.width.or(self.deadzone).unwrap_or(0.0);
+            points.push((detent.position - width, *detent_value));
+            points.push((detent.position + width, *detent_value));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+        let first_point = points[0];
+        let last_point = points[points.len() - 1];
+        if value <= first_point.0 {
+            return Some(first_point.1);
+        }
+        if value >= last_point.0 {
+            return Some(last_point.1);
+        }
+        let segment = points.windows(2).find(|segment| value >= segment[0].0 && value <= segment[1].0)?;
+        let (start, end) = (segment[0], segment[1]);
+        if end.0 == start.0 {
+            return Some(end.1);
+        }
+        Some(start.1 + (value - start.0) / (end.0 - start.0) * (end.1 - start.1))
+    }
 }
 
 impl ControllerCalibration {
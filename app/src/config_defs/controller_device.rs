@@ -0,0 +1,50 @@
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::usb_id::UsbId;
+
+/**
+ * Identifies a connected controller - the usb_id alone can't tell two identical controllers apart
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct ControllerDeviceIdentity {
+    /* {0xVENDOR_ID}:{0xPRODUCT_ID} */
+    pub usb_id: UsbId,
+    /* SDL joystick GUID */
+    pub guid: String,
+    /* not every controller reports a serial */
+    pub serial: Option<String>,
+    /* OS device path - stays the same as long as the controller is plugged into the same port */
+    pub path: Option<String>,
+}
+
+/**
+ * Assigns an alias to a controller which can be targeted by profiles and calibrations.
+ * All of the specified identity fields need to match.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerDevice {
+    /* eg: "left_quadrant" */
+    pub alias: String,
+    pub usb_id: Option<UsbId>,
+    pub guid: Option<String>,
+    pub serial: Option<String>,
+    pub path: Option<String>,
+}
+
+impl ControllerDevice {
+    pub fn has_identity(&self) -> bool {
+        self.usb_id.is_some() || self.guid.is_some() || self.serial.is_some() || self.path.is_some()
+    }
+
+    pub fn matches(&self, identity: &ControllerDeviceIdentity) -> bool {
+        if !self.has_identity() {
+            return false;
+        }
+        let usb_id_matches = self.usb_id.map_or(true, |usb_id| usb_id == identity.usb_id);
+        let guid_matches = self.guid.as_ref().map_or(true, |guid| guid.eq_ignore_ascii_case(&identity.guid));
+        let serial_matches = self.serial.as_ref().map_or(true, |serial| Some(serial) == identity.serial.as_ref());
+        let path_matches = self.path.as_ref().map_or(true, |path| Some(path) == identity.path.as_ref());
+        usb_id_matches && guid_matches && serial_matches && path_matches
+    }
+}
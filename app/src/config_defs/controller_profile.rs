@@ -1,14 +1,20 @@
 use core::fmt;
+use std::collections::HashMap;
 
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(PartialEq, Clone, Copy)]
+use super::usb_id::UsbId;
+use crate::action_sequencer::ActionSequencerPriority;
+
+#[derive(Debug, PartialEq, Clone, Copy, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum PreferredControlMode {
     DirectControl,
     SyncControl,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(tag = "type", rename_all = "snake_case")]
 pub enum ControllerProfileControlAssignment {
     Momentary(ControllerProfileControlMomentaryAssignment),
@@ -16,16 +22,53 @@ pub enum ControllerProfileControlAssignment {
     Toggle(ControllerProfileControlToggleAssignment),
     DirectControl(ControllerProfileDirectControlAssignment),
     SyncControl(ControllerProfileDirectControAssignmentSyncMode),
+    Hold(ControllerProfileControlHoldAssignment),
+    MultiTap(ControllerProfileControlMultiTapAssignment),
+    Chord(ControllerProfileControlChordAssignment),
+    MouseScroll(ControllerProfileControlMouseScrollAssignment),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ControllerProfileControlAssignmentKeysAction {
     pub keys: String,
     pub press_time: Option<f32>,
     pub wait_time: Option<f32>,
+    /* high priority keys skip ahead of the queued key presses */
+    pub priority: Option<ActionSequencerPriority>,
+}
+
+#[derive(Debug, PartialEq, Clone, Copy, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ControllerProfileMouseButton {
+    #[default]
+    Left,
+    Middle,
+    Right,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControllerProfileMouseAction {
+    /* pressed and released right away */
+    Click { button: Option<ControllerProfileMouseButton> },
+    /* held until the assignment is deactivated - eg: to drag a control */
+    Press { button: Option<ControllerProfileMouseButton> },
+    Release { button: Option<ControllerProfileMouseButton> },
+    /* in wheel notches - positive values scroll down (or right) */
+    Scroll { amount: i32, horizontal: Option<bool> },
+    /* relative to the current position in pixels */
+    Move { x: i32, y: i32 },
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/* eg: {"mouse": {"type": "scroll", "amount": -1}} */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ControllerProfileControlAssignmentMouseAction {
+    pub mouse: ControllerProfileMouseAction,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileDirectControlAssignmentInputValue {
     pub min: f32,
     pub max: f32,
@@ -34,7 +77,7 @@ pub struct ControllerProfileDirectControlAssignmentInputValue {
     pub invert: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileDirectControAssignmentSyncMode {
     /** this is the VHID Identifier Name - differs from the direct control name */
     pub identifier: String,
@@ -44,14 +87,15 @@ pub struct ControllerProfileDirectControAssignmentSyncMode {
 }
 
 /* defines a direct UE4ss control -> through websockets */
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileDirectControlAssignment {
     pub controls: String,   /* the HID control component as per the UE4SS API */
     pub hold: Option<bool>, /* will hold the control in changing */
     pub input_value: ControllerProfileDirectControlAssignmentInputValue,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
 pub struct ControllerProfileControlAssignmentDirectControlAction {
     pub controls: String,
     pub value: f32,
@@ -59,27 +103,97 @@ pub struct ControllerProfileControlAssignmentDirectControlAction {
     pub hold: Option<bool>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(deny_unknown_fields)]
+pub struct ControllerProfileControlAssignmentSequenceWaitStep {
+    /* in seconds */
+    pub wait: f32,
+}
+
+/* the steps deny unknown fields - otherwise {"keys": "a", "wait": 1} would run as a keys step and drop the wait */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ControllerProfileControlAssignmentSequenceStep {
+    Keys(ControllerProfileControlAssignmentKeysAction),
+    DirectControl(ControllerProfileControlAssignmentDirectControlAction),
+    Mouse(ControllerProfileControlAssignmentMouseAction),
+    Wait(ControllerProfileControlAssignmentSequenceWaitStep),
+}
+
+/* runs the steps in order - activating it again while it is still running cancels it */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileControlAssignmentSequenceAction {
+    pub sequence: Vec<ControllerProfileControlAssignmentSequenceStep>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 #[serde(untagged)]
 pub enum ControllerProfileControlAssignmentAction {
     Keys(ControllerProfileControlAssignmentKeysAction),
     DirectControl(ControllerProfileControlAssignmentDirectControlAction),
+    Mouse(ControllerProfileControlAssignmentMouseAction),
+    Sequence(ControllerProfileControlAssignmentSequenceAction),
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileControlToggleAssignment {
     pub threshold: f32,
     pub action_activate: ControllerProfileControlAssignmentAction,
     pub action_deactivate: ControllerProfileControlAssignmentAction,
 }
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileControlMomentaryAssignment {
     pub threshold: f32,
     pub action_activate: ControllerProfileControlAssignmentAction,
     pub action_deactivate: Option<ControllerProfileControlAssignmentAction>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+/* activates once the control has been held above the threshold for the given duration */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileControlHoldAssignment {
+    pub threshold: f32,
+    /* in seconds */
+    pub duration: f32,
+    pub action_activate: ControllerProfileControlAssignmentAction,
+    pub action_deactivate: Option<ControllerProfileControlAssignmentAction>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileControlMultiTapActions {
+    pub action_activate: ControllerProfileControlAssignmentAction,
+    pub action_deactivate: Option<ControllerProfileControlAssignmentAction>,
+}
+
+/* distinguishes single, double and triple presses - the presses need to follow each other within the window */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileControlMultiTapAssignment {
+    pub threshold: f32,
+    /* in seconds - defaults to 0.3 */
+    pub window: Option<f32>,
+    pub single: Option<ControllerProfileControlMultiTapActions>,
+    pub double: Option<ControllerProfileControlMultiTapActions>,
+    pub triple: Option<ControllerProfileControlMultiTapActions>,
+}
+
+/* activates when this control and all of the listed controls are above the threshold at the same time */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileControlChordAssignment {
+    pub threshold: f32,
+    pub controls: Vec<String>,
+    pub action_activate: ControllerProfileControlAssignmentAction,
+    pub action_deactivate: Option<ControllerProfileControlAssignmentAction>,
+}
+
+/* scrolls the mouse wheel while the control moves - moving it through its whole range (0 to 1) scrolls the given number of notches */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileControlMouseScrollAssignment {
+    pub notches: f32,
+    pub horizontal: Option<bool>,
+    /* increasing values scroll down (or right) unless inverted */
+    pub invert: Option<bool>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileControlLinearAssignmentThreshold {
     pub value: f32,
     pub value_end: Option<f32>,
@@ -88,25 +202,73 @@ pub struct ControllerProfileControlLinearAssignmentThreshold {
     pub action_deactivate: Option<ControllerProfileControlAssignmentAction>,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileControlLinearAssignment {
     pub neutral: Option<f32>,
     pub thresholds: Vec<ControllerProfileControlLinearAssignmentThreshold>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfileControl {
     pub name: String,
+    /* the shift layer this definition applies to - controls without a layer apply when no layer is active or the layer doesn't redefine them */
+    pub layer: Option<String>,
     pub assignment: Option<ControllerProfileControlAssignment>,
     pub assignments: Option<Vec<ControllerProfileControlAssignment>>,
+    /* replaces individual assignments (by index) of the control with the same name in the extended profile or included fragments */
+    pub override_assignments: Option<HashMap<usize, ControllerProfileControlAssignment>>,
+}
+
+/* activates a shift layer while the control is held */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileShiftControl {
+    pub control: String,
+    pub layer: String,
+    /* defaults to 0.5 */
+    pub threshold: Option<f32>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileRenamedFragmentInclude {
+    pub fragment: String,
+    /* renames the fragment controls - {"Lever1": "Lever2"} */
+    pub rename: HashMap<String, String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+#[serde(untagged)]
+pub enum ControllerProfileInclude {
+    Fragment(String),
+    RenamedFragment(ControllerProfileRenamedFragmentInclude),
+}
+
+/**
+ * Fragments are stored in the config/fragments directory and contain controls which can be shared between profiles.
+ * They are referenced by their file name without the extension.
+ */
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct ControllerProfileFragment {
+    pub controls: Vec<ControllerProfileControl>,
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerProfile {
     pub name: String,
+    /* name of the profile to inherit the controls from */
+    pub extends: Option<String>,
+    /* fragments to include - applied after the extended profile and before the own controls */
+    pub include: Option<Vec<ControllerProfileInclude>>,
+    /* when multiple shift controls are held the first one in the list wins */
+    pub shift_controls: Option<Vec<ControllerProfileShiftControl>>,
     pub controls: Vec<ControllerProfileControl>,
     /* can be used to set a specific controller for this configuration */
-    pub usb_id: Option<String>,
+    pub usb_id: Option<UsbId>,
+    /* can be used to set a specific device by its alias (see config/devices) - takes precedence over usb_id */
+    pub device: Option<String>,
+    /* aliases of the devices (see config/devices) combined in this profile - their controls can be addressed as {alias}.{control_name} */
+    pub devices: Option<Vec<String>>,
+    /* vehicle class names (supports * wildcards) for which this profile is selected automatically */
+    pub vehicle_classes: Option<Vec<String>>,
 }
 
 impl fmt::Display for ControllerProfileControlAssignmentDirectControlAction {
@@ -124,17 +286,117 @@ impl fmt::Display for ControllerProfileControlAssignmentDirectControlAction {
     }
 }
 
+impl ControllerProfileControlAssignment {
+    /**
+     * The type as written in the profile
+     */
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            ControllerProfileControlAssignment::Momentary(_) => "momentary",
+            ControllerProfileControlAssignment::Linear(_) => "linear",
+            ControllerProfileControlAssignment::Toggle(_) => "toggle",
+            ControllerProfileControlAssignment::DirectControl(_) => "direct_control",
+            ControllerProfileControlAssignment::SyncControl(_) => "sync_control",
+            ControllerProfileControlAssignment::Hold(_) => "hold",
+            ControllerProfileControlAssignment::MultiTap(_) => "multi_tap",
+            ControllerProfileControlAssignment::Chord(_) => "chord",
+            ControllerProfileControlAssignment::MouseScroll(_) => "mouse_scroll",
+        }
+    }
+
+    /**
+     * All keys actions of the assignment - including the ones in sequences
+     */
+    pub fn keys_actions(&self) -> Vec<&ControllerProfileControlAssignmentKeysAction> {
+        let actions: Vec<&ControllerProfileControlAssignmentAction> = match self {
+            ControllerProfileControlAssignment::Momentary(assignment) => vec![Some(&assignment.action_activate), assignment.action_deactivate.as_ref()].into_iter().flatten().collect(),
+            ControllerProfileControlAssignment::Linear(assignment) => assignment
+                .thresholds
+                .iter()
+                .flat_map(|threshold| vec![Some(&threshold.action_activate), threshold.action_deactivate.as_ref()])
+                .flatten()
+                .collect(),
+            ControllerProfileControlAssignment::Toggle(assignment) => vec![&assignment.action_activate, &assignment.action_deactivate],
+            ControllerProfileControlAssignment::DirectControl(_) => vec![],
+            ControllerProfileControlAssignment::SyncControl(assignment) => return vec![&assignment.action_increase, &assignment.action_decrease],
+            ControllerProfileControlAssignment::Hold(assignment) => vec![Some(&assignment.action_activate), assignment.action_deactivate.as_ref()].into_iter().flatten().collect(),
+            ControllerProfileControlAssignment::MultiTap(assignment) => vec![&assignment.single, &assignment.double, &assignment.triple]
+                .into_iter()
+                .flatten()
+                .flat_map(|actions| vec![Some(&actions.action_activate), actions.action_deactivate.as_ref()])
+                .flatten()
+                .collect(),
+            ControllerProfileControlAssignment::Chord(assignment) => vec![Some(&assignment.action_activate), assignment.action_deactivate.as_ref()].into_iter().flatten().collect(),
+            ControllerProfileControlAssignment::MouseScroll(_) => vec![],
+        };
+        actions.into_iter().flat_map(|action| action.keys_actions()).collect()
+    }
+}
+
 impl ControllerProfileControlAssignmentAction {
+    pub fn keys_actions(&self) -> Vec<&ControllerProfileControlAssignmentKeysAction> {
+        match self {
+            ControllerProfileControlAssignmentAction::Keys(action) => vec![action],
+            ControllerProfileControlAssignmentAction::Sequence(action) => action
+                .sequence
+                .iter()
+                .filter_map(|step| match step {
+                    ControllerProfileControlAssignmentSequenceStep::Keys(action) => Some(action),
+                    _ => None,
+                })
+                .collect(),
+            _ => vec![],
+        }
+    }
+
     pub fn get_compare_value(&self) -> String {
         match self {
             ControllerProfileControlAssignmentAction::Keys(action) => format!("{}", action.keys),
             ControllerProfileControlAssignmentAction::DirectControl(action) => {
                 format!("{}", action)
             }
+            ControllerProfileControlAssignmentAction::Mouse(action) => action.get_compare_value(),
+            ControllerProfileControlAssignmentAction::Sequence(action) => action.get_compare_value(),
         }
     }
 }
 
+impl ControllerProfileControlAssignmentMouseAction {
+    pub fn get_compare_value(&self) -> String {
+        format!("mouse:{:?}", self.mouse)
+    }
+}
+
+impl ControllerProfileControlMouseScrollAssignment {
+    /**
+     * The notches to scroll when the control moves from one value to the other
+     */
+    pub fn scroll_amount(&self, from: f32, to: f32) -> i32 {
+        let notch = |value: f32| (value * self.notches).round() as i32;
+        let amount = notch(to) - notch(from);
+        match self.invert {
+            Some(true) => -amount,
+            _ => amount,
+        }
+    }
+}
+
+impl ControllerProfileControlAssignmentSequenceAction {
+    pub fn get_compare_value(&self) -> String {
+        let steps: Vec<String> = self
+            .sequence
+            .iter()
+            .map(|step| match step {
+                ControllerProfileControlAssignmentSequenceStep::Keys(action) => format!("{}", action.keys),
+                ControllerProfileControlAssignmentSequenceStep::DirectControl(action) => format!("{}", action),
+                ControllerProfileControlAssignmentSequenceStep::Mouse(action) => action.get_compare_value(),
+                ControllerProfileControlAssignmentSequenceStep::Wait(step) => format!("wait:{}", step.wait),
+            })
+            .collect();
+        format!("sequence:{}", steps.join(";"))
+    }
+}
+
 impl ControllerProfileControlLinearAssignmentThreshold {
     pub fn is_exceeding_threshold(&self, value: f32) -> bool {
         if self.value < 0.0 {
@@ -142,30 +404,29 @@ impl ControllerProfileControlLinearAssignmentThreshold {
         }
         return value >= self.value;
     }
-}
 
-impl ControllerProfileControlLinearAssignment {
+    /**
+     * Expands value..value_end in steps of value_step - a threshold without a valid step is used as is
+     */
     pub fn generated_thresholds(&self) -> Vec<ControllerProfileControlLinearAssignmentThreshold> {
+        let (value_end, value_step) = match (self.value_end, self.value_step) {
+            (Some(value_end), Some(value_step)) if value_step > 0.0 => (value_end, value_step),
+            _ => return vec![self.clone()],
+        };
         let mut thresholds: Vec<ControllerProfileControlLinearAssignmentThreshold> = Vec::new();
-        for threshold in self.thresholds.iter() {
-            if threshold.value_end.is_none() || threshold.value_step.is_none() {
-                thresholds.push(threshold.clone());
-            } else {
-                let mut current_value = threshold.value;
-                while current_value <= threshold.value_end.unwrap() {
-                    thresholds.push(ControllerProfileControlLinearAssignmentThreshold {
-                        value: current_value,
-                        value_end: threshold.value_end,
-                        value_step: threshold.value_step,
-                        action_activate: threshold.action_activate.clone(),
-                        action_deactivate: threshold.action_deactivate.clone(),
-                    });
-                    current_value = ((current_value + threshold.value_step.unwrap()) * 10000.0).round() / 10000.0;
-                }
-            }
+        let mut current_value = self.value;
+        while current_value <= value_end {
+            thresholds.push(ControllerProfileControlLinearAssignmentThreshold { value: current_value, ..self.clone() });
+            current_value = ((current_value + value_step) * 10000.0).round() / 10000.0;
         }
         thresholds
     }
+}
+
+impl ControllerProfileControlLinearAssignment {
+    pub fn generated_thresholds(&self) -> Vec<ControllerProfileControlLinearAssignmentThreshold> {
+        self.thresholds.iter().flat_map(|threshold| threshold.generated_thresholds()).collect()
+    }
 
     pub fn calculate_neutralized_value(&self, value: f32) -> f32 {
         if self.neutral.is_some() && self.neutral.unwrap() > 0.0 {
@@ -225,15 +486,98 @@ impl ControllerProfileDirectControlAssignmentInputValue {
     }
 }
 
+impl ControllerProfileControlMultiTapAssignment {
+    pub fn window(&self) -> f32 {
+        self.window.unwrap_or(0.3)
+    }
+
+    pub fn actions_for_taps(&self, taps: usize) -> Option<&ControllerProfileControlMultiTapActions> {
+        match taps {
+            1 => self.single.as_ref(),
+            2 => self.double.as_ref(),
+            3 => self.triple.as_ref(),
+            _ => None,
+        }
+    }
+
+    /**
+     * The highest configured tap count - reaching it activates immediately instead of waiting for the window to pass
+     */
+    pub fn max_taps(&self) -> usize {
+        match (&self.single, &self.double, &self.triple) {
+            (_, _, Some(_)) => 3,
+            (_, Some(_), None) => 2,
+            (Some(_), None, None) => 1,
+            (None, None, None) => 0,
+        }
+    }
+}
+
+impl ControllerProfileInclude {
+    pub fn fragment_name(&self) -> &String {
+        match self {
+            ControllerProfileInclude::Fragment(name) => name,
+            ControllerProfileInclude::RenamedFragment(include) => &include.fragment,
+        }
+    }
+
+    /**
+     * Returns the fragment controls with the rename map applied
+     */
+    pub fn included_controls(&self, fragment: &ControllerProfileFragment) -> Vec<ControllerProfileControl> {
+        match self {
+            ControllerProfileInclude::Fragment(_) => fragment.controls.clone(),
+            ControllerProfileInclude::RenamedFragment(include) => fragment
+                .controls
+                .iter()
+                .map(|control| ControllerProfileControl {
+                    name: include.rename.get(&control.name).unwrap_or(&control.name).clone(),
+                    ..control.clone()
+                })
+                .collect(),
+        }
+    }
+}
+
 impl ControllerProfileControl {
-    pub fn get_assignments(&self, preferred_control_mode: PreferredControlMode) -> Vec<ControllerProfileControlAssignment> {
-        let assignments = match &self.assignment {
-            Some(assignment) => vec![assignment.clone()],
-            None => match &self.assignments {
-                Some(assignments) => assignments.clone(),
-                None => Vec::new(),
-            },
+    fn own_assignments(&self) -> Option<Vec<ControllerProfileControlAssignment>> {
+        match &self.assignment {
+            Some(assignment) => Some(vec![assignment.clone()]),
+            None => self.assignments.clone(),
+        }
+    }
+
+    /**
+     * Applies this control on top of the control with the same name from an extended profile or included fragment.
+     * Defined assignments replace the base assignments; override_assignments replace individual base assignments.
+     */
+    pub fn merged_onto(&self, base: Option<&ControllerProfileControl>) -> Result<ControllerProfileControl, String> {
+        let mut assignments = match (self.own_assignments(), base) {
+            (Some(assignments), _) => assignments,
+            (None, Some(base)) => base.own_assignments().unwrap_or(Vec::new()),
+            (None, None) => Vec::new(),
         };
+
+        if let Some(override_assignments) = &self.override_assignments {
+            for (index, assignment) in override_assignments.iter() {
+                if *index >= assignments.len() {
+                    return Err(format!("Control {} has no assignment at index {} to override", self.name, index));
+                }
+                assignments[*index] = assignment.clone();
+            }
+        }
+
+        Ok(ControllerProfileControl {
+            name: self.name.clone(),
+            layer: self.layer.clone(),
+            assignment: None,
+            assignments: Some(assignments),
+            override_assignments: None,
+        })
+    }
+
+    pub fn get_assignments(&self, preferred_control_mode: PreferredControlMode) -> Vec<ControllerProfileControlAssignment> {
+        let assignments = self.own_assignments().unwrap_or(Vec::new());
         let has_direct_control = assignments.iter().any(|a| match a {
             ControllerProfileControlAssignment::DirectControl(_) => true,
             _ => false,
@@ -268,7 +612,130 @@ impl ControllerProfileControl {
 }
 
 impl ControllerProfile {
+    /**
+     * Merges controls by name and layer - controls in the overrides replace or extend the base controls with the same name and layer
+     */
+    pub fn merge_controls(base_controls: Vec<ControllerProfileControl>, overrides: &Vec<ControllerProfileControl>) -> Result<Vec<ControllerProfileControl>, String> {
+        let mut controls = base_controls;
+        for control in overrides.iter() {
+            match controls.iter().position(|c| c.name == control.name && c.layer == control.layer) {
+                Some(index) => controls[index] = control.merged_onto(Some(&controls[index]))?,
+                None => controls.push(control.merged_onto(None)?),
+            }
+        }
+        Ok(controls)
+    }
+
+    /**
+     * All keys actions of the profile controls
+     */
+    pub fn keys_actions(&self) -> Vec<&ControllerProfileControlAssignmentKeysAction> {
+        self.controls
+            .iter()
+            .flat_map(|control| {
+                control
+                    .assignment
+                    .iter()
+                    .chain(control.assignments.iter().flatten())
+                    .chain(control.override_assignments.iter().flat_map(|override_assignments| override_assignments.values()))
+            })
+            .flat_map(|assignment| assignment.keys_actions())
+            .collect()
+    }
+
     pub fn find_control<T: AsRef<str>>(&self, name: T) -> Option<&ControllerProfileControl> {
-        self.controls.iter().find(|c| c.name == name.as_ref())
+        self.controls.iter().find(|c| c.name == name.as_ref() && c.layer.is_none())
+    }
+
+    /**
+     * Finds the control definition for the active shift layer - falls back to the definition without a layer.
+     * Device controls ({alias}.{control_name}) fall back to the definition shared by all devices of the profile.
+     */
+    pub fn find_layer_control<T: AsRef<str>>(&self, name: T, layer: Option<&String>) -> Option<&ControllerProfileControl> {
+        let layer_control = match layer {
+            Some(layer) => self.controls.iter().find(|c| c.name == name.as_ref() && c.layer.as_ref() == Some(layer)),
+            None => None,
+        };
+        match layer_control.or_else(|| self.find_control(name.as_ref())) {
+            Some(control) => Some(control),
+            None => match self.shared_control_name(name.as_ref()) {
+                Some(shared_name) => self.find_layer_control(shared_name, layer),
+                None => None,
+            },
+        }
+    }
+
+    pub fn find_shift_control<T: AsRef<str>>(&self, name: T) -> Option<&ControllerProfileShiftControl> {
+        let shift_control = match &self.shift_controls {
+            Some(shift_controls) => shift_controls.iter().find(|s| s.control == name.as_ref()),
+            None => None,
+        };
+        match shift_control {
+            Some(shift_control) => Some(shift_control),
+            None => match self.shared_control_name(name.as_ref()) {
+                Some(shared_name) => self.find_shift_control(shared_name),
+                None => None,
+            },
+        }
+    }
+
+    /**
+     * Maps a control of a connected device to the name it has in this profile.
+     * Profiles combining multiple devices use {alias}.{control_name}; returns None if the device is not part of such a profile.
+     */
+    pub fn device_control_name<T: AsRef<str>>(&self, device_alias: Option<&String>, control_name: T) -> Option<String> {
+        match (&self.devices, device_alias) {
+            (None, _) => Some(String::from(control_name.as_ref())),
+            (Some(devices), Some(alias)) if devices.contains(alias) => Some(format!("{}.{}", alias, control_name.as_ref())),
+            (Some(_), _) => None,
+        }
+    }
+
+    /**
+     * Strips the device alias from a device control name - eg: "left_quadrant.Button1" -> "Button1"
+     */
+    fn shared_control_name<'a>(&self, name: &'a str) -> Option<&'a str> {
+        let (alias, control_name) = name.split_once('.')?;
+        match &self.devices {
+            Some(devices) if devices.iter().any(|device| device == alias) => Some(control_name),
+            _ => None,
+        }
+    }
+
+    /**
+     * Returns the layer of the first held shift control
+     */
+    pub fn resolve_active_layer(&self, shift_control_states: &HashMap<String, bool>) -> Option<String> {
+        match &self.shift_controls {
+            Some(shift_controls) => shift_controls.iter().find(|s| *shift_control_states.get(&s.control).unwrap_or(&false)).map(|s| s.layer.clone()),
+            None => None,
+        }
+    }
+
+    /**
+     * Matches the vehicle class reported by the game mod against the vehicle_classes patterns (case insensitive)
+     * eg: "RVM_DTG_Class158*" matches "RVM_DTG_Class158_DMSL_C"
+     */
+    pub fn matches_vehicle_class<T: AsRef<str>>(&self, vehicle_class: T) -> bool {
+        let vehicle_class = vehicle_class.as_ref().to_lowercase();
+        match &self.vehicle_classes {
+            Some(patterns) => patterns.iter().any(|pattern| matches_wildcard_pattern(&pattern.to_lowercase(), &vehicle_class)),
+            None => false,
+        }
+    }
+}
+
+fn matches_wildcard_pattern(pattern: &str, value: &str) -> bool {
+    match pattern.split_once('*') {
+        None => pattern == value,
+        Some((prefix, rest)) => {
+            if !value.starts_with(prefix) {
+                return false;
+            }
+            let remaining_value = &value[prefix.len()..];
+            (0..=remaining_value.len())
+                .filter(|i| remaining_value.is_char_boundary(*i))
+                .any(|i| matches_wildcard_pattern(rest, &remaining_value[i..]))
+        }
     }
 }
@@ -1,6 +1,9 @@
+use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq, Hash)]
+use super::usb_id::UsbId;
+
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema, PartialEq, Eq, Hash)]
 #[serde(rename_all = "snake_case")]
 pub enum SDLControlKind {
     Button,
@@ -8,7 +11,7 @@ pub enum SDLControlKind {
     Axis,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerSdlMapControl {
     /** matches the SDL kinds like b, h and a - this mapping will be similar to the gamepad mapping ie: a:b0 -> map "a" to button index 0 */
     pub kind: SDLControlKind,
@@ -17,10 +20,10 @@ pub struct ControllerSdlMapControl {
     pub name: String,
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
 pub struct ControllerSdlMap {
     pub name: String,
     /* {0xVENDOR_ID}:{0xPRODUCT_ID} */
-    pub usb_id: String,
+    pub usb_id: UsbId,
     pub data: Vec<ControllerSdlMapControl>,
 }
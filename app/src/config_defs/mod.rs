@@ -1,3 +1,6 @@
+pub mod app_settings;
 pub mod controller_calibration;
+pub mod controller_device;
 pub mod controller_profile;
 pub mod controller_sdl_map;
+pub mod usb_id;
@@ -0,0 +1,82 @@
+use schemars::gen::SchemaGenerator;
+use schemars::schema::{InstanceType, Schema, SchemaObject, StringValidation};
+use schemars::JsonSchema;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt;
+use std::str::FromStr;
+
+/**
+ * USB vendor and product id of a controller.
+ * Parsed from "{VENDOR_ID}:{PRODUCT_ID}" in hex (case-insensitive) and always written as uppercase "0738:A221".
+ */
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct UsbId {
+    pub vendor_id: u16,
+    pub product_id: u16,
+}
+
+impl UsbId {
+    pub fn new(vendor_id: u16, product_id: u16) -> UsbId {
+        UsbId { vendor_id, product_id }
+    }
+}
+
+impl fmt::Display for UsbId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:04X}:{:04X}", self.vendor_id, self.product_id)
+    }
+}
+
+impl FromStr for UsbId {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse_part = |part: &str| {
+            let part = part.trim();
+            let part = part.strip_prefix("0x").or_else(|| part.strip_prefix("0X")).unwrap_or(part);
+            if part.is_empty() || part.len() > 4 {
+                return None;
+            }
+            u16::from_str_radix(part, 16).ok()
+        };
+
+        match s.split_once(':') {
+            Some((vendor_id, product_id)) => match (parse_part(vendor_id), parse_part(product_id)) {
+                (Some(vendor_id), Some(product_id)) => Ok(UsbId::new(vendor_id, product_id)),
+                _ => Err(format!("Invalid usb_id \"{}\", expected VENDOR_ID:PRODUCT_ID in hex (eg: 0738:A221)", s)),
+            },
+            None => Err(format!("Invalid usb_id \"{}\", expected VENDOR_ID:PRODUCT_ID in hex (eg: 0738:A221)", s)),
+        }
+    }
+}
+
+impl Serialize for UsbId {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_string())
+    }
+}
+
+impl<'de> Deserialize<'de> for UsbId {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        value.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+impl JsonSchema for UsbId {
+    fn schema_name() -> String {
+        String::from("UsbId")
+    }
+
+    fn json_schema(_gen: &mut SchemaGenerator) -> Schema {
+        SchemaObject {
+            instance_type: Some(InstanceType::String.into()),
+            string: Some(Box::new(StringValidation {
+                pattern: Some(String::from("^(0[xX])?[0-9a-fA-F]{1,4}:(0[xX])?[0-9a-fA-F]{1,4}$")),
+                ..Default::default()
+            })),
+            ..Default::default()
+        }
+        .into()
+    }
+}
@@ -1,11 +1,43 @@
-use std::{fs, path::Path};
+use std::{
+    collections::HashMap,
+    fmt, fs,
+    path::{Path, PathBuf},
+};
 
 use log::{info, warn};
+use serde::{de::DeserializeOwned, Serialize};
 use slug::slugify;
 
-use super::config_defs::{controller_calibration::ControllerCalibration, controller_profile::ControllerProfile, controller_sdl_map::ControllerSdlMap};
+use super::action_sequencer::ActionSequencer;
+use super::config_defs::{
+    app_settings::AppSettings,
+    controller_calibration::ControllerCalibration,
+    controller_device::{ControllerDevice, ControllerDeviceIdentity},
+    controller_profile::{ControllerProfile, ControllerProfileFragment},
+    controller_sdl_map::ControllerSdlMap,
+    usb_id::UsbId,
+};
+
+/**
+ * A config file which could not be read or parsed
+ */
+#[derive(Debug, Clone)]
+pub struct ConfigFileError {
+    pub file: PathBuf,
+    /* eg: "Could not parse profile file" */
+    pub description: String,
+    /* serde errors include the line and column */
+    pub reason: String,
+}
+
+impl fmt::Display for ConfigFileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {:?}: {}", self.description, self.file, self.reason)
+    }
+}
 
 pub struct ConfigLoader {
+    pub controller_devices: Vec<ControllerDevice>,
     pub controller_sdl_mappings: Vec<ControllerSdlMap>,
     pub controller_calibrations: Vec<ControllerCalibration>,
     pub controller_profiles: Vec<ControllerProfile>,
@@ -14,6 +46,7 @@ pub struct ConfigLoader {
 impl ConfigLoader {
     pub fn new() -> ConfigLoader {
         ConfigLoader {
+            controller_devices: Vec::new(),
             controller_sdl_mappings: Vec::new(),
             controller_calibrations: Vec::new(),
             controller_profiles: Vec::new(),
@@ -34,127 +67,317 @@ impl ConfigLoader {
             Some(dir) => dir.as_ref(),
             None => "config",
         };
-        // read the calibration, sdl mappings and profile files from the provided config dir
-        let sdl_mappings_path = Path::new(config_dir).join("sdl_mappings");
-        let calibration_path = Path::new(config_dir).join("calibration");
-        let profiles_path = Path::new(config_dir).join("profiles");
 
-        let sdl_mapping_files = match fs::read_dir(sdl_mappings_path) {
-            Ok(files) => files.into_iter().filter_map(Result::ok).collect(),
-            Err(_) => Vec::new(),
-        };
-        let calibration_files = match fs::read_dir(calibration_path) {
-            Ok(files) => files.into_iter().filter_map(Result::ok).collect(),
-            Err(_) => Vec::new(),
-        };
-        let profile_files = match fs::read_dir(profiles_path) {
-            Ok(files) => files.into_iter().filter_map(Result::ok).collect(),
-            Err(_) => Vec::new(),
+        let (config, errors) = ConfigLoader::read_from_dir(config_dir);
+        /* not only logged - broken files have to be visible without RUST_LOG */
+        for error in errors.iter() {
+            eprintln!("[Config] {}", error);
+        }
+
+        self.controller_devices.extend(config.controller_devices);
+        self.controller_sdl_mappings.extend(config.controller_sdl_mappings);
+        self.controller_calibrations.extend(config.controller_calibrations);
+        self.controller_profiles.extend(config.controller_profiles);
+        /* sort */
+        self.controller_profiles.sort_by(|a, b| a.name.cmp(&b.name));
+    }
+
+    /**
+     * Only loads the device aliases - used by the calibration mode to tell identical controllers apart
+     */
+    pub fn load_devices_from_dir<T: AsRef<str>>(&mut self, config_dir: T) {
+        let (devices, device_errors) = ConfigLoader::read_config_files::<ControllerDevice>(&Path::new(config_dir.as_ref()).join("devices"), "device");
+        for error in device_errors.iter() {
+            warn!("{}", error);
+        }
+        self.controller_devices.extend(devices.into_iter().map(|(_, device)| device));
+    }
+
+    /**
+     * Loads a fresh config; unlike load_from_dir this fails if any of the files can't be read or parsed
+     */
+    pub fn try_load_from_dir<T: AsRef<str>>(config_dir: T) -> Result<ConfigLoader, Vec<String>> {
+        let (config, errors) = ConfigLoader::read_from_dir(config_dir.as_ref());
+        if !errors.is_empty() {
+            return Err(errors);
+        }
+        Ok(config)
+    }
+
+    /**
+     * Reads the device, calibration, sdl mapping, profile and fragment files from the config dir.
+     * Returns the configs which could be loaded together with the errors of the others.
+     */
+    fn read_from_dir(config_dir: &str) -> (ConfigLoader, Vec<String>) {
+        let (controller_devices, device_errors) = ConfigLoader::read_config_files::<ControllerDevice>(&Path::new(config_dir).join("devices"), "device");
+        let (controller_sdl_mappings, sdl_mapping_errors) = ConfigLoader::read_config_files::<ControllerSdlMap>(&Path::new(config_dir).join("sdl_mappings"), "SDL mapping");
+        let (controller_calibrations, calibration_errors) = ConfigLoader::read_config_files::<ControllerCalibration>(&Path::new(config_dir).join("calibration"), "calibration");
+        let (controller_profiles, profile_errors) = ConfigLoader::read_config_files::<ControllerProfile>(&Path::new(config_dir).join("profiles"), "profile");
+        let (controller_profile_fragments, fragment_errors) = ConfigLoader::read_config_files::<ControllerProfileFragment>(&Path::new(config_dir).join("fragments"), "fragment");
+        let (mut controller_profiles, resolve_errors) = ConfigLoader::resolve_profiles(controller_profiles, &ConfigLoader::fragments_by_name(controller_profile_fragments));
+        /* unknown keys are only ignored when pressing them - they don't stop the initial load or a reload */
+        for error in ConfigLoader::check_profile_keys(&controller_profiles).iter() {
+            eprintln!("[Config] {}", error);
+        }
+
+        let errors: Vec<String> = device_errors
+            .into_iter()
+            .chain(sdl_mapping_errors)
+            .chain(calibration_errors)
+            .chain(profile_errors)
+            .chain(fragment_errors)
+            .map(|error| error.to_string())
+            .chain(resolve_errors)
+            .collect();
+
+        controller_profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        let config = ConfigLoader {
+            controller_devices: controller_devices.into_iter().map(|(_, device)| device).collect(),
+            controller_sdl_mappings: controller_sdl_mappings.into_iter().map(|(_, mapping)| mapping).collect(),
+            controller_calibrations: controller_calibrations.into_iter().map(|(_, calibration)| calibration).collect(),
+            controller_profiles,
         };
+        (config, errors)
+    }
 
-        info!("Found {} SDL mapping files", sdl_mapping_files.len());
-        for file in sdl_mapping_files.iter() {
-            match fs::read_to_string(file.path()) {
-                Ok(contents) => match serde_json::from_str(&contents) {
-                    Ok(sdl_map) => {
-                        info!("Successfully read SDL mapping file: {:?}", file.path());
-                        self.controller_sdl_mappings.push(sdl_map);
-                    }
-                    Err(e) => {
-                        warn!("Could not parse SDL mapping file {:?}: {}", file.path(), e);
+    /**
+     * Fragments are referenced by their file name without the extension
+     */
+    pub fn fragments_by_name(fragments: Vec<(PathBuf, ControllerProfileFragment)>) -> HashMap<String, ControllerProfileFragment> {
+        fragments
+            .into_iter()
+            .filter_map(|(file, fragment)| file.file_stem().map(|stem| (stem.to_string_lossy().to_string(), fragment)))
+            .collect()
+    }
+
+    /**
+     * Resolves the extends and include references of the profiles into plain profiles.
+     * Profiles which can't be resolved (missing references or cycles) are left out and reported as errors.
+     */
+    pub fn resolve_profiles(profiles: Vec<(PathBuf, ControllerProfile)>, fragments: &HashMap<String, ControllerProfileFragment>) -> (Vec<ControllerProfile>, Vec<String>) {
+        let raw_profiles: Vec<&ControllerProfile> = profiles.iter().map(|(_, profile)| profile).collect();
+        let mut resolved_profiles: Vec<ControllerProfile> = Vec::new();
+        let mut errors: Vec<String> = Vec::new();
+        for (file, profile) in profiles.iter() {
+            match ConfigLoader::resolve_profile(profile, &raw_profiles, fragments) {
+                Ok(resolved_profile) => resolved_profiles.push(resolved_profile),
+                Err(e) => errors.push(format!("Could not resolve profile file {:?}: {}", file, e)),
+            }
+        }
+        (resolved_profiles, errors)
+    }
+
+    /**
+     * Reports the keys of the resolved profiles which the action sequencer can't parse - these would be ignored when pressing them
+     */
+    pub fn check_profile_keys(profiles: &Vec<ControllerProfile>) -> Vec<String> {
+        let mut errors: Vec<String> = Vec::new();
+        for profile in profiles.iter() {
+            for action in profile.keys_actions() {
+                if let Err(e) = ActionSequencer::try_parse_keys(&action.keys) {
+                    let error = format!("Profile {}: {}", profile.name, e);
+                    /* profiles extending the same profile share its errors */
+                    if !errors.contains(&error) {
+                        errors.push(error);
                     }
-                },
-                Err(e) => {
-                    warn!("Could not read SDL mapping file {:?}: {}", file.path(), e);
                 }
             }
         }
+        errors
+    }
 
-        info!("Found {} calibration files", calibration_files.len());
-        for file in calibration_files.iter() {
-            match fs::read_to_string(file.path()) {
-                Ok(contents) => match serde_json::from_str(&contents) {
-                    Ok(sdl_map) => {
-                        info!("Successfully read calibration file: {:?}", file.path());
-                        self.controller_calibrations.push(sdl_map);
-                    }
-                    Err(e) => {
-                        warn!("Could not parse calibration file {:?}: {}", file.path(), e);
-                    }
-                },
-                Err(e) => {
-                    warn!("Could not read calibration file {:?}: {}", file.path(), e);
+    pub fn resolve_profile(profile: &ControllerProfile, profiles: &Vec<&ControllerProfile>, fragments: &HashMap<String, ControllerProfileFragment>) -> Result<ControllerProfile, String> {
+        ConfigLoader::resolve_profile_chain(profile, profiles, fragments, &mut Vec::new())
+    }
+
+    fn resolve_profile_chain(
+        profile: &ControllerProfile,
+        profiles: &Vec<&ControllerProfile>,
+        fragments: &HashMap<String, ControllerProfileFragment>,
+        chain: &mut Vec<String>,
+    ) -> Result<ControllerProfile, String> {
+        let profile_key = match (&profile.device, &profile.usb_id) {
+            (Some(device), _) => format!("{} ({})", profile.name, device),
+            (None, Some(usb_id)) => format!("{} ({})", profile.name, usb_id),
+            (None, None) => profile.name.clone(),
+        };
+        if chain.contains(&profile_key) {
+            chain.push(profile_key);
+            return Err(format!("Profile inheritance cycle: {}", chain.join(" -> ")));
+        }
+        chain.push(profile_key);
+
+        /* a profile always extends the generic profile - this allows controller specific profiles to extend the generic profile of the same name */
+        let base_profile = match &profile.extends {
+            Some(base_name) => match profiles.iter().find(|p| &p.name == base_name && p.usb_id.is_none() && p.device.is_none()) {
+                Some(base_profile) => Some(ConfigLoader::resolve_profile_chain(base_profile, profiles, fragments, chain)?),
+                None => return Err(format!("Profile {} extends unknown profile {}", profile.name, base_name)),
+            },
+            None => None,
+        };
+        let (mut controls, base_shift_controls, base_devices) = match base_profile {
+            Some(base_profile) => (base_profile.controls, base_profile.shift_controls, base_profile.devices),
+            None => (Vec::new(), None, None),
+        };
+
+        if let Some(includes) = &profile.include {
+            for include in includes.iter() {
+                match fragments.get(include.fragment_name()) {
+                    Some(fragment) => controls = ControllerProfile::merge_controls(controls, &include.included_controls(fragment))?,
+                    None => return Err(format!("Profile {} includes unknown fragment {}", profile.name, include.fragment_name())),
                 }
             }
         }
 
-        info!("Found {} profile files", profile_files.len());
-        for file in profile_files.iter() {
+        chain.pop();
+        Ok(ControllerProfile {
+            extends: None,
+            include: None,
+            shift_controls: profile.shift_controls.clone().or(base_shift_controls),
+            devices: profile.devices.clone().or(base_devices),
+            controls: ControllerProfile::merge_controls(controls, &profile.controls)?,
+            ..profile.clone()
+        })
+    }
+
+    /**
+     * Reads settings.json from the config dir - the defaults are used when there is no settings file
+     */
+    pub fn load_app_settings<T: AsRef<str>>(config_dir: T) -> Result<AppSettings, String> {
+        let settings_path = Path::new(config_dir.as_ref()).join("settings.json");
+        if !settings_path.exists() {
+            return Ok(AppSettings::default());
+        }
+        let contents = fs::read_to_string(&settings_path).map_err(|e| format!("Could not read settings file {:?}: {}", settings_path, e))?;
+        let settings = serde_json::from_str(&contents).map_err(|e| format!("Could not parse settings file {:?}: {}", settings_path, e))?;
+        info!("Successfully read settings file: {:?}", settings_path);
+        Ok(settings)
+    }
+
+    /**
+     * Reads and parses all files in a config sub directory; returns the parsed configs and the errors for files which failed
+     */
+    pub fn read_config_files<C: DeserializeOwned>(dir: &Path, kind: &str) -> (Vec<(PathBuf, C)>, Vec<ConfigFileError>) {
+        let files: Vec<fs::DirEntry> = match fs::read_dir(dir) {
+            Ok(files) => files.into_iter().filter_map(Result::ok).collect(),
+            Err(_) => Vec::new(),
+        };
+
+        info!("Found {} {} files", files.len(), kind);
+        let mut configs: Vec<(PathBuf, C)> = Vec::new();
+        let mut errors: Vec<ConfigFileError> = Vec::new();
+        for file in files.iter() {
             match fs::read_to_string(file.path()) {
                 Ok(contents) => match serde_json::from_str(&contents) {
-                    Ok(sdl_map) => {
-                        info!("Successfully read profile file: {:?}", file.path());
-                        self.controller_profiles.push(sdl_map);
-                    }
-                    Err(e) => {
-                        warn!("Could not parse profile file {:?}: {}", file.path(), e);
+                    Ok(config) => {
+                        info!("Successfully read {} file: {:?}", kind, file.path());
+                        configs.push((file.path(), config));
                     }
+                    Err(e) => errors.push(ConfigFileError {
+                        file: file.path(),
+                        description: format!("Could not parse {} file", kind),
+                        reason: e.to_string(),
+                    }),
                 },
-                Err(e) => {
-                    warn!("Could not read profile file {:?}: {}", file.path(), e);
-                }
+                Err(e) => errors.push(ConfigFileError {
+                    file: file.path(),
+                    description: format!("Could not read {} file", kind),
+                    reason: e.to_string(),
+                }),
             }
         }
-        /* sort */
-        self.controller_profiles.sort_by(|a, b| a.name.cmp(&b.name));
+        (configs, errors)
     }
 
     pub fn export<T: AsRef<str>>(&self, config_dir: T) {
         let config_dir = config_dir.as_ref();
+        let devices_path = Path::new(config_dir).join("devices");
         let sdl_mappings_path = Path::new(config_dir).join("sdl_mappings");
         let calibration_path = Path::new(config_dir).join("calibration");
         let profiles_path = Path::new(config_dir).join("profiles");
 
+        if !self.controller_devices.is_empty() {
+            fs::create_dir_all(&devices_path).unwrap();
+        }
         fs::create_dir_all(&sdl_mappings_path).unwrap();
         fs::create_dir_all(&calibration_path).unwrap();
         fs::create_dir_all(&profiles_path).unwrap();
 
+        for device in self.controller_devices.iter() {
+            ConfigLoader::export_file(&devices_path, slugify(device.alias.to_string()), device, |existing: &ControllerDevice| existing.alias == device.alias);
+        }
+
         for mapping in self.controller_sdl_mappings.iter() {
-            let file_path = sdl_mappings_path.join(format!("{}.json", slugify(mapping.name.to_string())));
-            let json = serde_json::to_string_pretty(mapping).unwrap();
-            fs::write(file_path, json).unwrap();
+            ConfigLoader::export_file(&sdl_mappings_path, slugify(mapping.name.to_string()), mapping, |existing: &ControllerSdlMap| {
+                existing.usb_id == mapping.usb_id
+            });
         }
 
         for calibration in self.controller_calibrations.iter() {
-            let file_path = calibration_path.join(format!("{}.json", slugify(calibration.usb_id.to_string())));
-            let json = serde_json::to_string_pretty(calibration).unwrap();
-            fs::write(file_path, json).unwrap();
+            let file_name = match &calibration.device {
+                Some(device) => slugify(device.to_string()),
+                None => slugify(calibration.usb_id.to_string()),
+            };
+            ConfigLoader::export_file(&calibration_path, file_name, calibration, |existing: &ControllerCalibration| {
+                existing.usb_id == calibration.usb_id && existing.device == calibration.device
+            });
         }
 
         for profile in self.controller_profiles.iter() {
-            let file_path = profiles_path.join(format!("{}.json", slugify(profile.name.to_string())));
-            let json = serde_json::to_string_pretty(profile).unwrap();
-            fs::write(file_path, json).unwrap();
+            ConfigLoader::export_file(&profiles_path, slugify(profile.name.to_string()), profile, |existing: &ControllerProfile| {
+                existing.name == profile.name && existing.usb_id == profile.usb_id && existing.device == profile.device
+            });
         }
     }
 
-    pub fn find_sdl_mapping(&self, usb_id: &String) -> Option<&ControllerSdlMap> {
+    /**
+     * Overwrites the existing file of the same config (eg: the calibration of the same controller) so exporting doesn't leave duplicates behind
+     */
+    fn export_file<C: Serialize + DeserializeOwned>(dir: &Path, file_name: String, config: &C, is_same_config: impl Fn(&C) -> bool) {
+        let (existing_configs, _) = ConfigLoader::read_config_files::<C>(dir, "existing");
+        let file_path = match existing_configs.into_iter().find(|(_, existing)| is_same_config(existing)) {
+            Some((existing_file_path, _)) => existing_file_path,
+            None => dir.join(format!("{}.json", file_name)),
+        };
+        let json = serde_json::to_string_pretty(config).unwrap();
+        fs::write(file_path, json).unwrap();
+    }
+
+    pub fn find_sdl_mapping(&self, usb_id: &UsbId) -> Option<&ControllerSdlMap> {
         self.controller_sdl_mappings.iter().find(|m| &m.usb_id == usb_id)
     }
 
-    pub fn find_controller_calibration(&self, usb_id: &String) -> Option<&ControllerCalibration> {
-        self.controller_calibrations.iter().find(|m| &m.usb_id == usb_id)
+    pub fn find_controller_calibration(&self, usb_id: &UsbId) -> Option<&ControllerCalibration> {
+        self.controller_calibrations.iter().find(|m| &m.usb_id == usb_id && m.device.is_none())
     }
 
-    pub fn find_controller_profile<T: AsRef<str>>(&self, name: T, controller_usb_id: Option<String>) -> Option<&ControllerProfile> {
-        let fallback_profile = self.controller_profiles.iter().find(|m| m.name == name.as_ref() && m.usb_id.is_none());
+    /**
+     * Prefers the calibration of the specific device over the one matching the usb_id
+     */
+    pub fn find_controller_calibration_for_device(&self, usb_id: &UsbId, device_alias: Option<&String>) -> Option<&ControllerCalibration> {
+        let device_calibration = match device_alias {
+            Some(alias) => self.controller_calibrations.iter().find(|m| m.device.as_ref() == Some(alias)),
+            None => None,
+        };
+        match device_calibration {
+            Some(calibration) => Some(calibration),
+            None => self.find_controller_calibration(usb_id),
+        }
+    }
+
+    pub fn find_controller_device(&self, identity: &ControllerDeviceIdentity) -> Option<&ControllerDevice> {
+        self.controller_devices.iter().find(|d| d.matches(identity))
+    }
+
+    pub fn find_controller_profile<T: AsRef<str>>(&self, name: T, controller_usb_id: Option<UsbId>) -> Option<&ControllerProfile> {
+        let fallback_profile = self.controller_profiles.iter().find(|m| m.name == name.as_ref() && m.usb_id.is_none() && m.device.is_none());
 
         match controller_usb_id {
             Some(usb_id) => {
                 let override_profile = self
                     .controller_profiles
                     .iter()
-                    .find(|m| m.name == name.as_ref() && m.usb_id.is_some() && m.usb_id.as_ref().unwrap() == &usb_id);
+                    .find(|m| m.name == name.as_ref() && m.device.is_none() && m.usb_id == Some(usb_id));
                 match override_profile {
                     Some(profile) => Some(profile),
                     None => fallback_profile,
@@ -163,4 +386,22 @@ impl ConfigLoader {
             None => fallback_profile,
         }
     }
+
+    /**
+     * Prefers the profile for the specific device, then the profile for the usb_id and finally the generic profile
+     */
+    pub fn find_controller_profile_for_device<T: AsRef<str>>(&self, name: T, controller_usb_id: &UsbId, device_alias: Option<&String>) -> Option<&ControllerProfile> {
+        let device_profile = match device_alias {
+            Some(alias) => self.controller_profiles.iter().find(|m| m.name == name.as_ref() && m.device.as_ref() == Some(alias)),
+            None => None,
+        };
+        match device_profile {
+            Some(profile) => Some(profile),
+            None => self.find_controller_profile(name, Some(*controller_usb_id)),
+        }
+    }
+
+    pub fn find_controller_profile_for_vehicle<T: AsRef<str>>(&self, vehicle_class: T) -> Option<&ControllerProfile> {
+        self.controller_profiles.iter().find(|m| m.matches_vehicle_class(vehicle_class.as_ref()))
+    }
 }
@@ -0,0 +1,670 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::{Path, PathBuf},
+};
+
+use serde::de::DeserializeOwned;
+
+use crate::{
+    action_sequencer::ActionSequencer,
+    config_defs::{
+        controller_calibration::ControllerCalibration,
+        controller_device::ControllerDevice,
+        controller_profile::{
+            ControllerProfile, ControllerProfileControl, ControllerProfileControlAssignment, ControllerProfileControlAssignmentAction, ControllerProfileControlAssignmentMouseAction,
+            ControllerProfileControlAssignmentSequenceStep, ControllerProfileControlLinearAssignment, ControllerProfileDirectControlAssignmentInputValue, ControllerProfileFragment,
+            ControllerProfileMouseAction,
+        },
+        controller_sdl_map::ControllerSdlMap,
+        usb_id::UsbId,
+    },
+    config_loader::ConfigLoader,
+};
+
+#[derive(Debug, Clone)]
+pub struct ConfigValidationIssue {
+    pub file: PathBuf,
+    /* JSON path of the offending value - eg: $.controls[2].assignments[0].thresholds[1] */
+    pub path: String,
+    pub message: String,
+}
+
+/**
+ * Validates the files of a config directory beyond what serde can check on its own
+ */
+pub struct ConfigValidator {
+    issues: Vec<ConfigValidationIssue>,
+}
+
+impl fmt::Display for ConfigValidationIssue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}: {}: {}", self.file.display(), self.path, self.message)
+    }
+}
+
+impl ConfigValidator {
+    pub fn new() -> ConfigValidator {
+        ConfigValidator { issues: Vec::new() }
+    }
+
+    fn report<T: AsRef<str>, M: AsRef<str>>(&mut self, file: &Path, path: T, message: M) {
+        self.issues.push(ConfigValidationIssue {
+            file: file.to_path_buf(),
+            path: String::from(path.as_ref()),
+            message: String::from(message.as_ref()),
+        });
+    }
+
+    fn read_config_files<C: DeserializeOwned>(&mut self, dir: &Path, kind: &str) -> Vec<(PathBuf, C)> {
+        let (configs, errors) = ConfigLoader::read_config_files::<C>(dir, kind);
+        for error in errors.iter() {
+            self.report(&error.file, "$", format!("{}: {}", error.description, error.reason));
+        }
+        configs
+    }
+
+    pub fn validate_dir<T: AsRef<str>>(mut self, config_dir: T) -> Vec<ConfigValidationIssue> {
+        let config_dir = Path::new(config_dir.as_ref());
+        let devices = self.read_config_files::<ControllerDevice>(&config_dir.join("devices"), "device");
+        let sdl_mappings = self.read_config_files::<ControllerSdlMap>(&config_dir.join("sdl_mappings"), "SDL mapping");
+        let calibrations = self.read_config_files::<ControllerCalibration>(&config_dir.join("calibration"), "calibration");
+        let profiles = self.read_config_files::<ControllerProfile>(&config_dir.join("profiles"), "profile");
+        let fragments = self.read_config_files::<ControllerProfileFragment>(&config_dir.join("fragments"), "fragment");
+
+        self.validate_devices(&devices);
+        for (file, calibration) in calibrations.iter() {
+            self.validate_device_reference(file, &calibration.device, &devices);
+            self.validate_calibration(file, calibration);
+        }
+
+        let sdl_maps: Vec<ControllerSdlMap> = sdl_mappings.into_iter().map(|(_, sdl_map)| sdl_map).collect();
+        for (file, fragment) in fragments.iter() {
+            self.validate_controls(file, &fragment.controls, &None, &HashMap::new(), &sdl_maps);
+        }
+
+        let raw_profiles: Vec<&ControllerProfile> = profiles.iter().map(|(_, profile)| profile).collect();
+        let fragments_by_name = ConfigLoader::fragments_by_name(fragments.clone());
+        for (file, profile) in profiles.iter() {
+            self.validate_device_reference(file, &profile.device, &devices);
+            /* device profiles can only use the controls of the device's usb_id if the device specifies one */
+            let device_usb_id = match &profile.device {
+                Some(alias) => devices.iter().find(|(_, device)| &device.alias == alias).and_then(|(_, device)| device.usb_id),
+                None => None,
+            };
+            for (device_index, alias) in profile.devices.iter().flatten().enumerate() {
+                if !devices.iter().any(|(_, device)| &device.alias == alias) {
+                    self.report(file, format!("$.devices[{}]", device_index), format!("Unknown device alias \"{}\"", alias));
+                }
+            }
+            let device_control_names = ConfigValidator::device_control_names(&profile.devices, &devices, &sdl_maps);
+            self.validate_controls(file, &profile.controls, &profile.usb_id.or(device_usb_id), &device_control_names, &sdl_maps);
+            match ConfigLoader::resolve_profile(profile, &raw_profiles, &fragments_by_name) {
+                Ok(resolved_profile) => {
+                    self.validate_keys(file, &resolved_profile);
+                    self.validate_layers(file, &resolved_profile);
+                }
+                Err(e) => self.report(file, "$", e),
+            }
+        }
+        self.issues
+    }
+
+    fn validate_devices(&mut self, devices: &Vec<(PathBuf, ControllerDevice)>) {
+        let mut aliases: HashSet<&String> = HashSet::new();
+        for (file, device) in devices.iter() {
+            if !device.has_identity() {
+                self.report(file, "$", "At least one of usb_id, guid, serial or path must be specified");
+            }
+            if !aliases.insert(&device.alias) {
+                self.report(file, "$.alias", format!("Alias \"{}\" is used by multiple devices", device.alias));
+            }
+        }
+    }
+
+    fn validate_calibration(&mut self, file: &Path, calibration: &ControllerCalibration) {
+        for (data_index, data) in calibration.data.iter().enumerate() {
+            let (range_min, range_max) = (data.min.min(data.max), data.min.max(data.max));
+            for (detent_index, detent) in data.detents.iter().flatten().enumerate() {
+                let detent_path = format!("$.data[{}].detents[{}]", data_index, detent_index);
+                if detent.position < range_min || detent.position > range_max {
+                    self.report(
+                        file,
+                        format!("{}.position", detent_path),
+                        format!("Detent position {} is outside of {}..{}", detent.position, data.min, data.max),
+                    );
+                }
+                if let Some(value) = detent.value {
+                    if !(-1.0..=1.0).contains(&value) {
+                        self.report(file, format!("{}.value", detent_path), format!("Detent value {} is outside of -1..1", value));
+                    }
+                }
+                if detent.width.map_or(false, |width| width < 0.0) {
+                    self.report(file, format!("{}.width", detent_path), "Detent width can't be negative");
+                }
+            }
+        }
+    }
+
+    fn validate_device_reference(&mut self, file: &Path, device: &Option<String>, devices: &Vec<(PathBuf, ControllerDevice)>) {
+        if let Some(alias) = device {
+            if !devices.iter().any(|(_, device)| &device.alias == alias) {
+                self.report(file, "$.device", format!("Unknown device alias \"{}\"", alias));
+            }
+        }
+    }
+
+    /**
+     * Collects the control names of each device of a multi device profile - devices without a usb_id can use the controls of all SDL mappings
+     */
+    fn device_control_names(profile_devices: &Option<Vec<String>>, devices: &Vec<(PathBuf, ControllerDevice)>, sdl_maps: &Vec<ControllerSdlMap>) -> HashMap<String, HashSet<String>> {
+        profile_devices
+            .iter()
+            .flatten()
+            .map(|alias| {
+                let device_usb_id = devices.iter().find(|(_, device)| &device.alias == alias).and_then(|(_, device)| device.usb_id);
+                let control_names = sdl_maps
+                    .iter()
+                    .filter(|sdl_map| device_usb_id.map_or(true, |usb_id| sdl_map.usb_id == usb_id))
+                    .flat_map(|sdl_map| sdl_map.data.iter().map(|control| control.name.clone()))
+                    .collect();
+                (alias.clone(), control_names)
+            })
+            .collect()
+    }
+
+    fn validate_controls(
+        &mut self,
+        file: &Path,
+        controls: &Vec<ControllerProfileControl>,
+        usb_id: &Option<UsbId>,
+        device_control_names: &HashMap<String, HashSet<String>>,
+        sdl_maps: &Vec<ControllerSdlMap>,
+    ) {
+        /* controller specific profiles can only use the controls of that controller */
+        let relevant_sdl_maps: Vec<&ControllerSdlMap> = sdl_maps
+            .iter()
+            .filter(|sdl_map| match usb_id {
+                Some(usb_id) => &sdl_map.usb_id == usb_id,
+                None => true,
+            })
+            .collect();
+        if usb_id.is_some() && relevant_sdl_maps.is_empty() {
+            self.report(file, "$.usb_id", format!("No SDL mapping found for usb_id {}", usb_id.as_ref().unwrap()));
+        }
+        let known_control_names: HashSet<&String> = relevant_sdl_maps.iter().flat_map(|sdl_map| sdl_map.data.iter().map(|control| &control.name)).collect();
+        /* device controls ({alias}.{control_name}) are checked against the SDL mapping of that device */
+        let is_known_control = |name: &String| {
+            let is_known_device_control = name
+                .split_once('.')
+                .and_then(|(alias, control_name)| device_control_names.get(alias).map(|names| names.contains(control_name)));
+            is_known_device_control.unwrap_or_else(|| relevant_sdl_maps.is_empty() || known_control_names.contains(name))
+        };
+
+        for (control_index, control) in controls.iter().enumerate() {
+            let control_path = format!("$.controls[{}]", control_index);
+            if !is_known_control(&control.name) {
+                self.report(
+                    file,
+                    format!("{}.name", control_path),
+                    format!("Unknown control \"{}\" - it is not defined in any SDL mapping", control.name),
+                );
+            }
+
+            if let Some(assignment) = &control.assignment {
+                self.validate_assignment(file, &format!("{}.assignment", control_path), assignment);
+            }
+            let assignments_with_path = control.assignment.iter().map(|assignment| (format!("{}.assignment", control_path), assignment)).chain(
+                control
+                    .assignments
+                    .iter()
+                    .flatten()
+                    .enumerate()
+                    .map(|(assignment_index, assignment)| (format!("{}.assignments[{}]", control_path, assignment_index), assignment)),
+            );
+            for (assignment_path, assignment) in assignments_with_path {
+                if let ControllerProfileControlAssignment::Chord(chord) = assignment {
+                    for chord_control in chord.controls.iter().filter(|name| !is_known_control(name)) {
+                        self.report(
+                            file,
+                            format!("{}.controls", assignment_path),
+                            format!("Unknown chord control \"{}\" - it is not defined in any SDL mapping", chord_control),
+                        );
+                    }
+                }
+            }
+            if let Some(assignments) = &control.assignments {
+                for (assignment_index, assignment) in assignments.iter().enumerate() {
+                    self.validate_assignment(file, &format!("{}.assignments[{}]", control_path, assignment_index), assignment);
+                }
+            }
+            if let Some(override_assignments) = &control.override_assignments {
+                for (assignment_index, assignment) in override_assignments.iter() {
+                    self.validate_assignment(file, &format!("{}.override_assignments.{}", control_path, assignment_index), assignment);
+                }
+            }
+        }
+    }
+
+    /* layers are checked on the resolved profile since the shift controls may be inherited */
+    fn validate_layers(&mut self, file: &Path, profile: &ControllerProfile) {
+        let declared_layers: HashSet<&String> = match &profile.shift_controls {
+            Some(shift_controls) => shift_controls.iter().map(|s| &s.layer).collect(),
+            None => HashSet::new(),
+        };
+        for control in profile.controls.iter() {
+            if let Some(layer) = &control.layer {
+                if !declared_layers.contains(layer) {
+                    self.report(file, "$", format!("Control {} uses layer \"{}\" which is not activated by any shift control", control.name, layer));
+                }
+            }
+        }
+    }
+
+    fn validate_assignment(&mut self, file: &Path, path: &String, assignment: &ControllerProfileControlAssignment) {
+        match assignment {
+            ControllerProfileControlAssignment::Momentary(assignment) => {
+                self.validate_action(file, &format!("{}.action_activate", path), &assignment.action_activate);
+                if let Some(action) = &assignment.action_deactivate {
+                    self.validate_action(file, &format!("{}.action_deactivate", path), action);
+                }
+            }
+            ControllerProfileControlAssignment::Toggle(assignment) => {
+                self.validate_action(file, &format!("{}.action_activate", path), &assignment.action_activate);
+                self.validate_action(file, &format!("{}.action_deactivate", path), &assignment.action_deactivate);
+            }
+            ControllerProfileControlAssignment::Linear(assignment) => {
+                self.validate_linear_assignment(file, path, assignment);
+            }
+            ControllerProfileControlAssignment::DirectControl(assignment) => {
+                self.validate_input_value(file, &format!("{}.input_value", path), &assignment.input_value);
+            }
+            ControllerProfileControlAssignment::SyncControl(assignment) => {
+                self.validate_input_value(file, &format!("{}.input_value", path), &assignment.input_value);
+            }
+            ControllerProfileControlAssignment::Hold(assignment) => {
+                if assignment.duration <= 0.0 {
+                    self.report(file, format!("{}.duration", path), "duration must be greater than 0");
+                }
+                self.validate_action(file, &format!("{}.action_activate", path), &assignment.action_activate);
+                if let Some(action) = &assignment.action_deactivate {
+                    self.validate_action(file, &format!("{}.action_deactivate", path), action);
+                }
+            }
+            ControllerProfileControlAssignment::MultiTap(assignment) => {
+                if assignment.window() <= 0.0 {
+                    self.report(file, format!("{}.window", path), "window must be greater than 0");
+                }
+                if assignment.max_taps() == 0 {
+                    self.report(file, path.clone(), "At least one of single, double or triple must be defined");
+                }
+                for (taps_name, actions) in [("single", &assignment.single), ("double", &assignment.double), ("triple", &assignment.triple)] {
+                    if let Some(actions) = actions {
+                        self.validate_action(file, &format!("{}.{}.action_activate", path, taps_name), &actions.action_activate);
+                        if let Some(action) = &actions.action_deactivate {
+                            self.validate_action(file, &format!("{}.{}.action_deactivate", path, taps_name), action);
+                        }
+                    }
+                }
+            }
+            ControllerProfileControlAssignment::Chord(assignment) => {
+                if assignment.controls.is_empty() {
+                    self.report(file, format!("{}.controls", path), "A chord needs at least one other control");
+                }
+                self.validate_action(file, &format!("{}.action_activate", path), &assignment.action_activate);
+                if let Some(action) = &assignment.action_deactivate {
+                    self.validate_action(file, &format!("{}.action_deactivate", path), action);
+                }
+            }
+            ControllerProfileControlAssignment::MouseScroll(assignment) => {
+                if assignment.notches <= 0.0 {
+                    self.report(file, format!("{}.notches", path), "notches must be greater than 0");
+                }
+            }
+        }
+    }
+
+    fn validate_action(&mut self, file: &Path, path: &String, action: &ControllerProfileControlAssignmentAction) {
+        match action {
+            ControllerProfileControlAssignmentAction::Keys(_) => {}
+            ControllerProfileControlAssignmentAction::DirectControl(_) => {}
+            ControllerProfileControlAssignmentAction::Mouse(action) => self.validate_mouse_action(file, path, action),
+            ControllerProfileControlAssignmentAction::Sequence(action) => {
+                if action.sequence.is_empty() {
+                    self.report(file, format!("{}.sequence", path), "sequence must not be empty");
+                }
+                for (step_index, step) in action.sequence.iter().enumerate() {
+                    let step_path = format!("{}.sequence[{}]", path, step_index);
+                    match step {
+                        ControllerProfileControlAssignmentSequenceStep::Keys(_) => {}
+                        ControllerProfileControlAssignmentSequenceStep::DirectControl(_) => {}
+                        ControllerProfileControlAssignmentSequenceStep::Mouse(action) => self.validate_mouse_action(file, &step_path, action),
+                        ControllerProfileControlAssignmentSequenceStep::Wait(step) => {
+                            if step.wait < 0.0 {
+                                self.report(file, format!("{}.wait", step_path), "wait must not be negative");
+                            }
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    /* the keys are checked on the resolved profile so the keys of its fragments are included */
+    fn validate_keys(&mut self, file: &Path, profile: &ControllerProfile) {
+        let mut errors: Vec<String> = Vec::new();
+        for action in profile.keys_actions() {
+            if let Err(e) = ActionSequencer::try_parse_keys(&action.keys) {
+                if !errors.contains(&e) {
+                    errors.push(e);
+                }
+            }
+        }
+        for error in errors.iter() {
+            self.report(file, "$.controls", error);
+        }
+    }
+
+    fn validate_mouse_action(&mut self, file: &Path, path: &String, action: &ControllerProfileControlAssignmentMouseAction) {
+        match &action.mouse {
+            ControllerProfileMouseAction::Scroll { amount: 0, .. } => self.report(file, format!("{}.mouse.amount", path), "amount must not be 0"),
+            ControllerProfileMouseAction::Move { x: 0, y: 0 } => self.report(file, format!("{}.mouse", path), "x and y must not both be 0"),
+            _ => {}
+        }
+    }
+
+    fn validate_linear_assignment(&mut self, file: &Path, path: &String, assignment: &ControllerProfileControlLinearAssignment) {
+        /* (threshold index, generated threshold value) */
+        let mut generated_values: Vec<(usize, f32)> = Vec::new();
+        for (threshold_index, threshold) in assignment.thresholds.iter().enumerate() {
+            let threshold_path = format!("{}.thresholds[{}]", path, threshold_index);
+            self.validate_action(file, &format!("{}.action_activate", threshold_path), &threshold.action_activate);
+            if let Some(action) = &threshold.action_deactivate {
+                self.validate_action(file, &format!("{}.action_deactivate", threshold_path), action);
+            }
+
+            match (threshold.value_end, threshold.value_step) {
+                (Some(_), Some(value_step)) if value_step <= 0.0 => self.report(file, format!("{}.value_step", threshold_path), "value_step must be greater than 0"),
+                (Some(value_end), Some(_)) if value_end < threshold.value => self.report(file, format!("{}.value_end", threshold_path), "value_end must not be lower than value"),
+                (Some(_), None) | (None, Some(_)) => self.report(file, threshold_path.clone(), "value_end and value_step must be used together"),
+                _ => {}
+            }
+            generated_values.extend(threshold.generated_thresholds().iter().map(|generated| (threshold_index, generated.value)));
+        }
+
+        /* positive thresholds are activated in ascending order, negative thresholds in descending order */
+        let positive_values: Vec<&(usize, f32)> = generated_values.iter().filter(|(_, value)| *value >= 0.0).collect();
+        let negative_values: Vec<&(usize, f32)> = generated_values.iter().filter(|(_, value)| *value < 0.0).collect();
+        for pair in positive_values.windows(2) {
+            let ((_, previous_value), (threshold_index, value)) = (pair[0], pair[1]);
+            if value <= previous_value {
+                self.report(
+                    file,
+                    format!("{}.thresholds[{}]", path, threshold_index),
+                    format!("Threshold {} overlaps or is not sorted after {} - positive thresholds must be ascending", value, previous_value),
+                );
+            }
+        }
+        for pair in negative_values.windows(2) {
+            let ((_, previous_value), (threshold_index, value)) = (pair[0], pair[1]);
+            if value >= previous_value {
+                self.report(
+                    file,
+                    format!("{}.thresholds[{}]", path, threshold_index),
+                    format!("Threshold {} overlaps or is not sorted after {} - negative thresholds must be descending", value, previous_value),
+                );
+            }
+        }
+    }
+
+    fn validate_input_value(&mut self, file: &Path, path: &String, input_value: &ControllerProfileDirectControlAssignmentInputValue) {
+        let lower_bound = input_value.min.min(input_value.max);
+        let upper_bound = input_value.min.max(input_value.max);
+        if let Some(step) = input_value.step {
+            if step <= 0.0 || step > upper_bound - lower_bound {
+                self.report(
+                    file,
+                    format!("{}.step", path),
+                    format!("step {} must be greater than 0 and within {}..{}", step, input_value.min, input_value.max),
+                );
+            }
+        }
+        if let Some(steps) = &input_value.steps {
+            if steps.is_empty() {
+                self.report(file, format!("{}.steps", path), "steps must not be empty");
+            }
+            for (step_index, step) in steps.iter().enumerate() {
+                if *step < lower_bound || *step > upper_bound {
+                    self.report(
+                        file,
+                        format!("{}.steps[{}]", path, step_index),
+                        format!("step {} is outside of {}..{}", step, input_value.min, input_value.max),
+                    );
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    const SDL_MAPPING: &str = r#"{"name": "Quadrant", "usb_id": "044F:040A", "data": [
+        {"kind": "axis", "index": 0, "name": "Lever1"},
+        {"kind": "button", "index": 1, "name": "Button1"}
+    ]}"#;
+
+    /* writes the files into a fresh config dir - eg: ("profiles/test.json", "{...}") */
+    fn validate(test_name: &str, files: &[(&str, &str)]) -> Vec<ConfigValidationIssue> {
+        let config_dir = std::env::temp_dir().join(format!("tsw5-gamepad-validator-{}-{}", test_name, std::process::id()));
+        let _ = fs::remove_dir_all(&config_dir);
+        for (file, contents) in files.iter().chain([("sdl_mappings/quadrant.json", SDL_MAPPING)].iter()) {
+            let file_path = config_dir.join(file);
+            fs::create_dir_all(file_path.parent().unwrap()).unwrap();
+            fs::write(file_path, contents).unwrap();
+        }
+        let issues = ConfigValidator::new().validate_dir(config_dir.to_str().unwrap());
+        fs::remove_dir_all(&config_dir).unwrap();
+        issues
+    }
+
+    /* a profile with a single Lever1 control */
+    fn validate_assignment(test_name: &str, assignment: &str) -> Vec<ConfigValidationIssue> {
+        let profile = format!(r#"{{"name": "test", "controls": [{{"name": "Lever1", "assignments": [{}]}}]}}"#, assignment);
+        validate(test_name, &[("profiles/test.json", &profile)])
+    }
+
+    fn assert_issue(issues: &[ConfigValidationIssue], file: &str, path: &str, message: &str) {
+        assert!(
+            issues.iter().any(|issue| issue.file.ends_with(file) && issue.path == path && issue.message.contains(message)),
+            "expected {} {}: \"{}\" in {:#?}",
+            file,
+            path,
+            message,
+            issues
+        );
+    }
+
+    #[test]
+    fn valid_profile_has_no_issues() {
+        let issues = validate_assignment(
+            "valid",
+            r#"{"type": "linear", "thresholds": [{"value": 0.1, "value_end": 0.5, "value_step": 0.1, "action_activate": {"keys": "ctrl+a", "press_time": 0.1}}]}"#,
+        );
+        assert!(issues.is_empty(), "{:#?}", issues);
+    }
+
+    #[test]
+    fn parse_errors_are_reported_against_the_file() {
+        let issues = validate("parse", &[("profiles/broken.json", "{\"name\": ")]);
+        assert_issue(&issues, "profiles/broken.json", "$", "Could not parse profile file");
+    }
+
+    #[test]
+    fn devices() {
+        let issues = validate(
+            "devices",
+            &[
+                ("devices/a.json", r#"{"alias": "left"}"#),
+                ("devices/b.json", r#"{"alias": "left", "usb_id": "044F:040A"}"#),
+                ("calibration/a.json", r#"{"usb_id": "044F:040A", "device": "right", "data": []}"#),
+                ("profiles/test.json", r#"{"name": "test", "device": "right", "devices": ["left", "middle"], "controls": []}"#),
+            ],
+        );
+        assert_issue(&issues, "devices/a.json", "$", "At least one of usb_id, guid, serial or path must be specified");
+        assert_issue(&issues, "devices/b.json", "$.alias", "Alias \"left\" is used by multiple devices");
+        assert_issue(&issues, "calibration/a.json", "$.device", "Unknown device alias \"right\"");
+        assert_issue(&issues, "profiles/test.json", "$.device", "Unknown device alias \"right\"");
+        assert_issue(&issues, "profiles/test.json", "$.devices[1]", "Unknown device alias \"middle\"");
+    }
+
+    #[test]
+    fn calibration_detents() {
+        let calibration = r#"{"usb_id": "044F:040A", "data": [{"id": "Lever1", "min": -100, "max": 100, "idle": -100, "detents": [
+            {"name": "outside", "position": 200},
+            {"name": "value", "position": 0, "value": 2},
+            {"name": "width", "position": 0, "width": -1}
+        ]}]}"#;
+        let issues = validate("calibration", &[("calibration/quadrant.json", calibration)]);
+        assert_issue(&issues, "calibration/quadrant.json", "$.data[0].detents[0].position", "Detent position 200 is outside of -100..100");
+        assert_issue(&issues, "calibration/quadrant.json", "$.data[0].detents[1].value", "Detent value 2 is outside of -1..1");
+        assert_issue(&issues, "calibration/quadrant.json", "$.data[0].detents[2].width", "Detent width can't be negative");
+    }
+
+    #[test]
+    fn profile_structure() {
+        let issues = validate(
+            "structure",
+            &[
+                ("profiles/unknown_usb_id.json", r#"{"name": "unknown_usb_id", "usb_id": "1234:5678", "controls": []}"#),
+                ("profiles/extends.json", r#"{"name": "extends", "extends": "missing", "controls": []}"#),
+                (
+                    "profiles/controls.json",
+                    r#"{"name": "controls", "controls": [
+                        {"name": "Lever9", "assignments": [{"type": "chord", "threshold": 0.5, "controls": ["Button9"], "action_activate": {"keys": "a"}}]},
+                        {"name": "Button1", "layer": "shifted", "assignments": [{"type": "chord", "threshold": 0.5, "controls": [], "action_activate": {"keys": "bogus"}}]}
+                    ]}"#,
+                ),
+            ],
+        );
+        assert_issue(&issues, "profiles/unknown_usb_id.json", "$.usb_id", "No SDL mapping found for usb_id 1234:5678");
+        assert_issue(&issues, "profiles/extends.json", "$", "Profile extends extends unknown profile missing");
+        assert_issue(&issues, "profiles/controls.json", "$.controls[0].name", "Unknown control \"Lever9\"");
+        assert_issue(&issues, "profiles/controls.json", "$.controls[0].assignments[0].controls", "Unknown chord control \"Button9\"");
+        assert_issue(&issues, "profiles/controls.json", "$.controls[1].assignments[0].controls", "A chord needs at least one other control");
+        assert_issue(
+            &issues,
+            "profiles/controls.json",
+            "$",
+            "Control Button1 uses layer \"shifted\" which is not activated by any shift control",
+        );
+        assert_issue(&issues, "profiles/controls.json", "$.controls", "Unknown key \"bogus\"");
+    }
+
+    #[test]
+    fn timed_and_mouse_assignments() {
+        let issues = validate_assignment(
+            "timed",
+            r#"{"type": "hold", "threshold": 0.5, "duration": 0, "action_activate": {"mouse": {"type": "scroll", "amount": 0}}}"#,
+        );
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].duration", "duration must be greater than 0");
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].action_activate.mouse.amount", "amount must not be 0");
+
+        let issues = validate_assignment("multi_tap", r#"{"type": "multi_tap", "threshold": 0.5, "window": 0}"#);
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].window", "window must be greater than 0");
+        assert_issue(
+            &issues,
+            "profiles/test.json",
+            "$.controls[0].assignments[0]",
+            "At least one of single, double or triple must be defined",
+        );
+
+        let issues = validate_assignment("mouse_scroll", r#"{"type": "mouse_scroll", "notches": 0}"#);
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].notches", "notches must be greater than 0");
+
+        let issues = validate_assignment(
+            "mouse_move",
+            r#"{"type": "momentary", "threshold": 0.5, "action_activate": {"mouse": {"type": "move", "x": 0, "y": 0}}}"#,
+        );
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].action_activate.mouse", "x and y must not both be 0");
+    }
+
+    #[test]
+    fn sequences() {
+        let issues = validate_assignment(
+            "sequences",
+            r#"{"type": "toggle", "threshold": 0.5, "action_activate": {"sequence": []}, "action_deactivate": {"sequence": [{"wait": -1}]}}"#,
+        );
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].action_activate.sequence", "sequence must not be empty");
+        assert_issue(
+            &issues,
+            "profiles/test.json",
+            "$.controls[0].assignments[0].action_deactivate.sequence[0].wait",
+            "wait must not be negative",
+        );
+    }
+
+    #[test]
+    fn sequence_steps_with_mixed_fields_fail_to_parse() {
+        let issues = validate_assignment(
+            "sequence_mixed",
+            r#"{"type": "momentary", "threshold": 0.5, "action_activate": {"sequence": [{"keys": "a", "wait": 1}]}}"#,
+        );
+        assert_issue(&issues, "profiles/test.json", "$", "Could not parse profile file");
+    }
+
+    #[test]
+    fn linear_thresholds() {
+        let issues = validate_assignment(
+            "linear",
+            r#"{"type": "linear", "thresholds": [
+                {"value": 0.1, "value_end": 0.5, "value_step": 0, "action_activate": {"keys": "a"}},
+                {"value": 0.5, "value_end": 0.2, "value_step": 0.1, "action_activate": {"keys": "a"}},
+                {"value": 0.6, "value_end": 0.8, "action_activate": {"keys": "a"}},
+                {"value": 0.3, "action_activate": {"keys": "a"}},
+                {"value": -0.2, "action_activate": {"keys": "a"}},
+                {"value": -0.1, "action_activate": {"keys": "a"}}
+            ]}"#,
+        );
+        assert_issue(
+            &issues,
+            "profiles/test.json",
+            "$.controls[0].assignments[0].thresholds[0].value_step",
+            "value_step must be greater than 0",
+        );
+        assert_issue(
+            &issues,
+            "profiles/test.json",
+            "$.controls[0].assignments[0].thresholds[1].value_end",
+            "value_end must not be lower than value",
+        );
+        assert_issue(
+            &issues,
+            "profiles/test.json",
+            "$.controls[0].assignments[0].thresholds[2]",
+            "value_end and value_step must be used together",
+        );
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].thresholds[3]", "positive thresholds must be ascending");
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].thresholds[5]", "negative thresholds must be descending");
+    }
+
+    #[test]
+    fn input_values() {
+        let issues = validate_assignment(
+            "step",
+            r#"{"type": "direct_control", "controls": "Throttle1", "input_value": {"min": 0, "max": 1, "step": 2, "steps": [0.5, 1.5]}}"#,
+        );
+        assert_issue(
+            &issues,
+            "profiles/test.json",
+            "$.controls[0].assignments[0].input_value.step",
+            "step 2 must be greater than 0 and within 0..1",
+        );
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].input_value.steps[1]", "step 1.5 is outside of 0..1");
+
+        let issues = validate_assignment("steps", r#"{"type": "direct_control", "controls": "Throttle1", "input_value": {"min": 0, "max": 1, "steps": []}}"#);
+        assert_issue(&issues, "profiles/test.json", "$.controls[0].assignments[0].input_value.steps", "steps must not be empty");
+    }
+}
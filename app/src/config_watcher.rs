@@ -0,0 +1,70 @@
+use std::path::{Path, PathBuf};
+
+use notify::{RecursiveMode, Watcher};
+use tokio::sync::mpsc;
+use tokio::time::{sleep, Duration};
+use tokio_util::sync::CancellationToken;
+
+/* directories inside the config dir which trigger a reload when changed */
+const WATCHED_CONFIG_DIRS: [&str; 5] = ["devices", "profiles", "fragments", "calibration", "sdl_mappings"];
+
+pub struct ConfigWatcher {
+    config_dir: PathBuf,
+}
+
+impl ConfigWatcher {
+    pub fn new<T: AsRef<str>>(config_dir: T) -> ConfigWatcher {
+        ConfigWatcher {
+            config_dir: PathBuf::from(config_dir.as_ref()),
+        }
+    }
+
+    fn is_watched_path(&self, path: &Path) -> bool {
+        WATCHED_CONFIG_DIRS.iter().any(|dir| path.starts_with(self.config_dir.join(dir)))
+    }
+
+    /**
+     * Watches the config directory and notifies the returned receiver when any of the config files changed.
+     * Changes are debounced since editors tend to write a file in multiple steps.
+     */
+    pub fn start(self, cancel_token: CancellationToken) -> Result<mpsc::Receiver<()>, String> {
+        let config_dir = self.config_dir.canonicalize().map_err(|e| format!("Could not watch config dir {:?}: {}", self.config_dir, e))?;
+        let watcher_self = ConfigWatcher { config_dir: config_dir.clone() };
+
+        let (file_event_tx, mut file_event_rx) = mpsc::channel::<()>(100);
+        let mut watcher = notify::recommended_watcher(move |result: notify::Result<notify::Event>| match result {
+            Ok(event) => {
+                if event.kind.is_access() || !event.paths.iter().any(|path| watcher_self.is_watched_path(path)) {
+                    return;
+                }
+                /* a full channel means a reload is already pending */
+                let _ = file_event_tx.try_send(());
+            }
+            Err(e) => eprintln!("[Config] Watch error: {}", e),
+        })
+        .map_err(|e| format!("Could not watch config dir {:?}: {}", config_dir, e))?;
+        watcher
+            .watch(&config_dir, RecursiveMode::Recursive)
+            .map_err(|e| format!("Could not watch config dir {:?}: {}", config_dir, e))?;
+
+        let (config_changed_tx, config_changed_rx) = mpsc::channel::<()>(1);
+        tokio::task::spawn(async move {
+            /* keep the watcher alive for as long as the task runs */
+            let _watcher = watcher;
+            loop {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => {
+                        break;
+                    },
+                    Some(_) = file_event_rx.recv() => {
+                        sleep(Duration::from_millis(300)).await;
+                        while file_event_rx.try_recv().is_ok() {}
+                        let _ = config_changed_tx.try_send(());
+                    }
+                }
+            }
+        });
+
+        Ok(config_changed_rx)
+    }
+}
@@ -0,0 +1,55 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use serde::Serialize;
+
+/**
+ * Connection diagnostics of the direct/sync control servers - the game mod is their only client
+ * Timestamps are unix timestamps in milliseconds
+ */
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ConnectionStatus {
+    pub connected_clients: usize,
+    pub last_received_at: Option<u64>,
+    pub last_sent_at: Option<u64>,
+    pub send_errors: usize,
+    pub last_error: Option<String>,
+}
+
+impl ConnectionStatus {
+    pub fn is_connected(&self) -> bool {
+        self.connected_clients > 0
+    }
+
+    pub fn client_connected(&mut self) {
+        self.connected_clients += 1;
+    }
+
+    pub fn client_disconnected(&mut self) {
+        self.connected_clients = self.connected_clients.saturating_sub(1);
+    }
+
+    pub fn message_received(&mut self) {
+        self.last_received_at = Some(now());
+    }
+
+    pub fn message_sent(&mut self) {
+        self.last_sent_at = Some(now());
+    }
+
+    pub fn send_failed<T: ToString>(&mut self, error: T) {
+        self.send_errors += 1;
+        self.last_error = Some(error.to_string());
+    }
+
+    /**
+     * Time since the last message in either direction
+     */
+    pub fn since_last_message(&self) -> Option<Duration> {
+        let last_message_at = self.last_received_at.max(self.last_sent_at)?;
+        Some(Duration::from_millis(now().saturating_sub(last_message_at)))
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
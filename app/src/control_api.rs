@@ -0,0 +1,225 @@
+use std::{net::SocketAddr, sync::Arc};
+
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::{net::TcpListener, sync::watch, task::JoinHandle};
+use tokio_util::sync::CancellationToken;
+use tungstenite::protocol::Message;
+
+use crate::{action_sequencer::ActionSequencer, config_defs::controller_profile::PreferredControlMode, config_loader::ConfigLoader, connection_status::ConnectionStatus};
+
+/**
+ * Messages accepted by the control API
+ * eg: {"type":"set_profile","profile":"class158"}
+ */
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlApiRequest {
+    ListProfiles,
+    GetState,
+    GetConnectionStatus,
+    SetProfile { profile: Option<String> },
+    SetControlMode { control_mode: PreferredControlMode },
+    /* panic button - lets go of every key which is held down */
+    ReleaseAllKeys,
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ControlApiResponse {
+    Profiles { profiles: Vec<String> },
+    State { profile: Option<String>, control_mode: PreferredControlMode },
+    ConnectionStatus { direct_control: ConnectionStatus, sync_control: ConnectionStatus },
+    KeysReleased { keys: Vec<String> },
+    Error { message: String },
+}
+
+pub struct ControlApi {
+    config: watch::Receiver<Arc<ConfigLoader>>,
+    server: Arc<TcpListener>,
+    on_selected_profile_change_sender: Arc<watch::Sender<Option<String>>>,
+    on_preferred_control_mode_change_sender: Arc<watch::Sender<PreferredControlMode>>,
+    direct_controller_status: watch::Receiver<ConnectionStatus>,
+    sync_controller_status: watch::Receiver<ConnectionStatus>,
+    sequencer: Arc<ActionSequencer>,
+}
+
+impl ControlApi {
+    pub async fn new(
+        config: watch::Receiver<Arc<ConfigLoader>>,
+        on_selected_profile_change_sender: Arc<watch::Sender<Option<String>>>,
+        on_preferred_control_mode_change_sender: Arc<watch::Sender<PreferredControlMode>>,
+        direct_controller_status: watch::Receiver<ConnectionStatus>,
+        sync_controller_status: watch::Receiver<ConnectionStatus>,
+        sequencer: Arc<ActionSequencer>,
+        addr: SocketAddr,
+    ) -> Result<Self, String> {
+        let control_api_server = TcpListener::bind(addr).await.map_err(|e| {
+            format!(
+                "Could not bind the control API to {}: {} - is the port already in use? It can be changed with control_api_port in settings.json or --control-api-port",
+                addr, e
+            )
+        })?;
+        println!("[API] Listening on {}", addr);
+
+        Ok(Self {
+            config,
+            server: Arc::new(control_api_server),
+            on_selected_profile_change_sender,
+            on_preferred_control_mode_change_sender,
+            direct_controller_status,
+            sync_controller_status,
+            sequencer,
+        })
+    }
+
+    fn current_state(on_selected_profile_change_sender: &watch::Sender<Option<String>>, on_preferred_control_mode_change_sender: &watch::Sender<PreferredControlMode>) -> ControlApiResponse {
+        ControlApiResponse::State {
+            profile: on_selected_profile_change_sender.borrow().clone(),
+            control_mode: *on_preferred_control_mode_change_sender.borrow(),
+        }
+    }
+
+    async fn handle_request(
+        config: &ConfigLoader,
+        on_selected_profile_change_sender: &watch::Sender<Option<String>>,
+        on_preferred_control_mode_change_sender: &watch::Sender<PreferredControlMode>,
+        direct_controller_status: &watch::Receiver<ConnectionStatus>,
+        sync_controller_status: &watch::Receiver<ConnectionStatus>,
+        sequencer: &ActionSequencer,
+        request: ControlApiRequest,
+    ) -> ControlApiResponse {
+        match request {
+            ControlApiRequest::ListProfiles => {
+                /* controller specific profiles share the name of the generic profile */
+                let mut profiles: Vec<String> = config.controller_profiles.iter().map(|p| p.name.clone()).collect();
+                profiles.sort();
+                profiles.dedup();
+                ControlApiResponse::Profiles { profiles }
+            }
+            ControlApiRequest::GetState => ControlApi::current_state(on_selected_profile_change_sender, on_preferred_control_mode_change_sender),
+            ControlApiRequest::GetConnectionStatus => ControlApiResponse::ConnectionStatus {
+                direct_control: direct_controller_status.borrow().clone(),
+                sync_control: sync_controller_status.borrow().clone(),
+            },
+            ControlApiRequest::SetProfile { profile } => {
+                if let Some(name) = &profile {
+                    if config.find_controller_profile(name, None).is_none() {
+                        return ControlApiResponse::Error {
+                            message: format!("Profile {} not found", name),
+                        };
+                    }
+                }
+                on_selected_profile_change_sender.send_replace(profile);
+                ControlApi::current_state(on_selected_profile_change_sender, on_preferred_control_mode_change_sender)
+            }
+            ControlApiRequest::SetControlMode { control_mode } => {
+                on_preferred_control_mode_change_sender.send_replace(control_mode);
+                ControlApi::current_state(on_selected_profile_change_sender, on_preferred_control_mode_change_sender)
+            }
+            ControlApiRequest::ReleaseAllKeys => {
+                let keys = sequencer.release_all().await;
+                ControlApiResponse::KeysReleased { keys }
+            }
+        }
+    }
+
+    pub fn start(&self, cancel_token: CancellationToken) -> JoinHandle<()> {
+        let server = Arc::clone(&self.server);
+        let config = self.config.clone();
+        let on_selected_profile_change_sender = Arc::clone(&self.on_selected_profile_change_sender);
+        let on_preferred_control_mode_change_sender = Arc::clone(&self.on_preferred_control_mode_change_sender);
+        let direct_controller_status = self.direct_controller_status.clone();
+        let sync_controller_status = self.sync_controller_status.clone();
+        let sequencer = Arc::clone(&self.sequencer);
+
+        tokio::task::spawn(async move {
+            println!("[API] Server started");
+            loop {
+                tokio::select! {
+                    _ = cancel_token.cancelled() => {
+                        break;
+                    },
+                    Ok((tcp_stream, _)) = server.accept() => {
+                        println!("[API] New client connected");
+                        let socket_cancel_token = cancel_token.clone();
+                        let config = config.clone();
+                        let on_selected_profile_change_sender = Arc::clone(&on_selected_profile_change_sender);
+                        let on_preferred_control_mode_change_sender = Arc::clone(&on_preferred_control_mode_change_sender);
+                        let direct_controller_status = direct_controller_status.clone();
+                        let sync_controller_status = sync_controller_status.clone();
+                        let sequencer = Arc::clone(&sequencer);
+                        /* each client is notified of state changes regardless of where they came from */
+                        let mut selected_profile_receiver = on_selected_profile_change_sender.subscribe();
+                        let mut preferred_control_mode_receiver = on_preferred_control_mode_change_sender.subscribe();
+
+                        tokio::task::spawn(async move {
+                            let ws_stream = match tokio_tungstenite::accept_async(tcp_stream).await {
+                                Ok(ws_stream) => ws_stream,
+                                Err(e) => {
+                                    eprintln!("[API] Error during the websocket handshake occurred: {}", e);
+                                    return;
+                                }
+                            };
+                            let (mut write, mut read) = ws_stream.split();
+
+                            loop {
+                                let response = tokio::select! {
+                                    _ = socket_cancel_token.cancelled() => {
+                                        break;
+                                    },
+                                    Ok(_) = selected_profile_receiver.changed() => {
+                                        ControlApi::current_state(&on_selected_profile_change_sender, &on_preferred_control_mode_change_sender)
+                                    },
+                                    Ok(_) = preferred_control_mode_receiver.changed() => {
+                                        ControlApi::current_state(&on_selected_profile_change_sender, &on_preferred_control_mode_change_sender)
+                                    },
+                                    next = read.next() => {
+                                        match next {
+                                            Some(Ok(Message::Text(text))) => match serde_json::from_str::<ControlApiRequest>(&text) {
+                                                Ok(request) => {
+                                                    let current_config = Arc::clone(&config.borrow());
+                                                    let response = ControlApi::handle_request(
+                                                        &current_config,
+                                                        &on_selected_profile_change_sender,
+                                                        &on_preferred_control_mode_change_sender,
+                                                        &direct_controller_status,
+                                                        &sync_controller_status,
+                                                        &sequencer,
+                                                        request,
+                                                    )
+                                                    .await;
+                                                    /* the response already contains the latest state */
+                                                    selected_profile_receiver.borrow_and_update();
+                                                    preferred_control_mode_receiver.borrow_and_update();
+                                                    response
+                                                }
+                                                Err(e) => ControlApiResponse::Error {
+                                                    message: format!("Invalid request: {}", e),
+                                                },
+                                            },
+                                            Some(Ok(Message::Close(_))) => break,
+                                            Some(Ok(_)) => continue,
+                                            Some(Err(e)) => {
+                                                eprintln!("[API] Client error: {}", e);
+                                                break;
+                                            }
+                                            /* the client went away without a close frame */
+                                            None => break,
+                                        }
+                                    },
+                                };
+
+                                let response_text = serde_json::to_string(&response).unwrap();
+                                if let Err(e) = write.send(Message::text(response_text)).await {
+                                    eprintln!("[API] Error sending message: {:?}", e);
+                                    break;
+                                }
+                            }
+                        });
+                    }
+                }
+            }
+        })
+    }
+}
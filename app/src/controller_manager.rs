@@ -1,7 +1,7 @@
 use log::debug;
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, ffi::CStr, sync::Arc};
 use tokio::sync::broadcast::{Receiver, Sender};
-use tokio::sync::Mutex;
+use tokio::sync::{watch, Mutex};
 use tokio_util::sync::CancellationToken;
 
 use sdl2::{event::Event, joystick::Joystick, Sdl};
@@ -9,28 +9,44 @@ use sdl2::{event::Event, joystick::Joystick, Sdl};
 use crate::{
     config_defs::{
         controller_calibration::ControllerCalibrationData,
+        controller_device::ControllerDeviceIdentity,
         controller_sdl_map::{ControllerSdlMapControl, SDLControlKind},
+        usb_id::UsbId,
     },
     config_loader::ConfigLoader,
 };
 
 #[derive(Clone)]
 pub struct SDLJoystick {
-    pub usb_id: String,
+    pub usb_id: UsbId,
+    /* alias from the matching config/devices entry */
+    pub device_alias: Option<String>,
     pub raw: Arc<Joystick>,
 }
 
 #[derive(Clone, Debug)]
 pub struct ControllerManagerChangeEvent {
-    pub usb_id: String,
+    pub usb_id: UsbId,
+    pub device_alias: Option<String>,
     pub control_name: String,
     pub control_state: ControllerManagerControllerControlState,
 }
 
+/**
+ * A connected controller as shown in the UI
+ */
+#[derive(Clone, Debug)]
+pub struct ControllerManagerConnectedController {
+    pub name: String,
+    pub identity: ControllerDeviceIdentity,
+    pub device_alias: Option<String>,
+}
+
 #[derive(Clone, Debug)]
 pub struct ControllerManagerRawEvent {
     pub joystick_index: u32,
-    pub joystick_usb_id: String,
+    pub joystick_usb_id: UsbId,
+    pub joystick_device_alias: Option<String>,
     pub joystick_name: String,
     pub event: Event,
 }
@@ -60,6 +76,7 @@ pub struct ControllerManagerControllerControl {
 
 pub struct ControllerManagerController {
     config: Arc<ConfigLoader>,
+    identity: ControllerDeviceIdentity,
     joystick: SDLJoystick,
     controls: HashMap<String, ControllerManagerControllerControl>,
 
@@ -70,7 +87,12 @@ pub struct ControllerManager {
     config: Arc<ConfigLoader>,
     sdl_context: Arc<Sdl>,
     joystick_subsystem: Arc<sdl2::JoystickSubsystem>,
+    /* keyed by the SDL instance id - the device index is only valid in the added event */
     devices: HashMap<u32, ControllerManagerController>,
+    /* receives reloaded configs */
+    config_receiver: Option<watch::Receiver<Arc<ConfigLoader>>>,
+    /* publishes the connected controllers whenever a controller is added or removed */
+    connected_controllers_sender: Option<watch::Sender<Vec<ControllerManagerConnectedController>>>,
 
     change_event_channel: (Arc<Sender<ControllerManagerChangeEvent>>, Arc<Mutex<Receiver<ControllerManagerChangeEvent>>>),
     raw_event_channel: (Arc<Sender<ControllerManagerRawEvent>>, Arc<Mutex<Receiver<ControllerManagerRawEvent>>>),
@@ -239,7 +261,8 @@ impl ControllerManagerControllerControl {
         };
 
         match self.change_event_channel.0.send(ControllerManagerChangeEvent {
-            usb_id: self.joystick.usb_id.clone(),
+            usb_id: self.joystick.usb_id,
+            device_alias: self.joystick.device_alias.clone(),
             control_name: self.name.clone(),
             control_state: self.state.clone(),
         }) {
@@ -266,13 +289,14 @@ impl ControllerManagerControllerControl {
 impl ControllerManagerController {
     pub fn new(
         config: Arc<ConfigLoader>,
-        usb_id: String,
-        joystick: Joystick,
+        identity: ControllerDeviceIdentity,
+        joystick_arc: Arc<Joystick>,
         change_event_channel: (Arc<Sender<ControllerManagerChangeEvent>>, Arc<Mutex<Receiver<ControllerManagerChangeEvent>>>),
     ) -> ControllerManagerController {
-        let joystick_arc = Arc::new(joystick);
+        let usb_id = identity.usb_id;
+        let device_alias = config.find_controller_device(&identity).map(|device| device.alias.clone());
         let sdl_mapping = config.find_sdl_mapping(&usb_id);
-        let calibration = config.find_controller_calibration(&usb_id);
+        let calibration = config.find_controller_calibration_for_device(&usb_id, device_alias.as_ref());
         let all_controls_calibration_data = calibration.map(|x| x.data.clone()).unwrap_or(Vec::new());
 
         let mut gamepad_controls = HashMap::new();
@@ -282,7 +306,8 @@ impl ControllerManagerController {
 
                 let mut control = ControllerManagerControllerControl::new(
                     SDLJoystick {
-                        usb_id: usb_id.clone(),
+                        usb_id,
+                        device_alias: device_alias.clone(),
                         raw: Arc::clone(&joystick_arc),
                     },
                     control.name.clone(),
@@ -297,8 +322,10 @@ impl ControllerManagerController {
 
         ControllerManagerController {
             config: Arc::clone(&config),
+            identity,
             joystick: SDLJoystick {
-                usb_id: usb_id.clone(),
+                usb_id,
+                device_alias,
                 raw: joystick_arc,
             },
             controls: gamepad_controls,
@@ -356,6 +383,8 @@ impl ControllerManager {
             sdl_context,
             joystick_subsystem,
             devices: HashMap::new(),
+            config_receiver: None,
+            connected_controllers_sender: None,
             change_event_channel: (Arc::new(channel_pair.0), Arc::new(Mutex::new(channel_pair.1))),
             raw_event_channel: (Arc::new(raw_channel_pair.0), Arc::new(Mutex::new(raw_channel_pair.1))),
         }
@@ -365,26 +394,115 @@ impl ControllerManager {
         match event {
             Event::JoyDeviceAdded { which, .. } => {
                 let joystick = self.joystick_subsystem.open(which).unwrap();
-                let product_id = unsafe { sdl2_sys::SDL_JoystickGetDeviceProduct(which as i32) };
-                let vendor_id = unsafe { sdl2_sys::SDL_JoystickGetDeviceVendor(which as i32) };
-                let usb_id: String = format!("{:04X}:{:04X}", vendor_id, product_id);
-                println!("Joystick Opened ({}) {}", joystick.name(), usb_id);
+                let identity = ControllerManager::device_identity(which, &joystick);
+                println!(
+                    "Joystick Opened ({}) {} guid={} serial={} path={}",
+                    joystick.name(),
+                    identity.usb_id,
+                    identity.guid,
+                    identity.serial.as_deref().unwrap_or("-"),
+                    identity.path.as_deref().unwrap_or("-")
+                );
+                let instance_id = joystick.instance_id();
                 let controller = ControllerManagerController::new(
                     Arc::clone(&self.config),
-                    usb_id,
-                    joystick,
+                    identity,
+                    Arc::new(joystick),
                     (Arc::clone(&self.change_event_channel.0), Arc::clone(&self.change_event_channel.1)),
                 );
-                self.devices.insert(which, controller);
+                if let Some(alias) = &controller.joystick.device_alias {
+                    println!("Joystick identified as {}", alias);
+                }
+                self.devices.insert(instance_id, controller);
+                self.send_connected_controllers();
             }
             _ => panic!("Invalid event type"),
         }
     }
 
+    fn c_str_to_option(value: *const std::os::raw::c_char) -> Option<String> {
+        if value.is_null() {
+            return None;
+        }
+        let value = unsafe { CStr::from_ptr(value) }.to_string_lossy().to_string();
+        match value.is_empty() {
+            true => None,
+            false => Some(value),
+        }
+    }
+
+    /**
+     * Collects the identifiers of a newly added joystick; the device index is only valid while handling the added event
+     */
+    fn device_identity(device_index: u32, joystick: &Joystick) -> ControllerDeviceIdentity {
+        let product_id = unsafe { sdl2_sys::SDL_JoystickGetDeviceProduct(device_index as i32) };
+        let vendor_id = unsafe { sdl2_sys::SDL_JoystickGetDeviceVendor(device_index as i32) };
+        let path = ControllerManager::c_str_to_option(unsafe { sdl2_sys::SDL_JoystickPathForIndex(device_index as i32) });
+        let serial = unsafe {
+            let raw_joystick = sdl2_sys::SDL_JoystickFromInstanceID(joystick.instance_id() as i32);
+            match raw_joystick.is_null() {
+                true => None,
+                false => ControllerManager::c_str_to_option(sdl2_sys::SDL_JoystickGetSerial(raw_joystick)),
+            }
+        };
+
+        ControllerDeviceIdentity {
+            usb_id: UsbId::new(vendor_id, product_id),
+            guid: joystick.guid().string(),
+            serial,
+            path,
+        }
+    }
+
+    /**
+     * Swaps in a reloaded config and re-creates the connected devices so the new mappings and calibrations are applied
+     */
+    pub fn set_config(&mut self, config: Arc<ConfigLoader>) {
+        self.config = config;
+        let devices = std::mem::take(&mut self.devices);
+        for (which, controller) in devices.into_iter() {
+            let reloaded_controller = ControllerManagerController::new(
+                Arc::clone(&self.config),
+                controller.identity.clone(),
+                Arc::clone(&controller.joystick.raw),
+                (Arc::clone(&self.change_event_channel.0), Arc::clone(&self.change_event_channel.1)),
+            );
+            self.devices.insert(which, reloaded_controller);
+        }
+        /* the aliases may have changed */
+        self.send_connected_controllers();
+    }
+
+    pub fn watch_config(&mut self, config_receiver: watch::Receiver<Arc<ConfigLoader>>) {
+        self.config_receiver = Some(config_receiver);
+    }
+
+    pub fn report_connected_controllers(&mut self, connected_controllers_sender: watch::Sender<Vec<ControllerManagerConnectedController>>) {
+        self.connected_controllers_sender = Some(connected_controllers_sender);
+        self.send_connected_controllers();
+    }
+
+    fn send_connected_controllers(&self) {
+        if let Some(sender) = &self.connected_controllers_sender {
+            let mut connected_controllers: Vec<ControllerManagerConnectedController> = self
+                .devices
+                .values()
+                .map(|controller| ControllerManagerConnectedController {
+                    name: controller.joystick.raw.name(),
+                    identity: controller.identity.clone(),
+                    device_alias: controller.joystick.device_alias.clone(),
+                })
+                .collect();
+            connected_controllers.sort_by(|a, b| a.name.cmp(&b.name));
+            sender.send_replace(connected_controllers);
+        }
+    }
+
     fn handle_joy_device_removed(&mut self, event: sdl2::event::Event) {
         match event {
             Event::JoyDeviceRemoved { which, .. } => {
                 self.devices.remove(&which);
+                self.send_connected_controllers();
             }
             _ => panic!("Invalid event type"),
         }
@@ -398,7 +516,8 @@ impl ControllerManager {
                         .0
                         .send(ControllerManagerRawEvent {
                             joystick_index: which,
-                            joystick_usb_id: controller.joystick.usb_id.clone(),
+                            joystick_usb_id: controller.joystick.usb_id,
+                            joystick_device_alias: controller.joystick.device_alias.clone(),
                             joystick_name: controller.joystick.raw.name(),
                             event: event.clone(),
                         })
@@ -418,7 +537,8 @@ impl ControllerManager {
                         .0
                         .send(ControllerManagerRawEvent {
                             joystick_index: which,
-                            joystick_usb_id: controller.joystick.usb_id.clone(),
+                            joystick_usb_id: controller.joystick.usb_id,
+                            joystick_device_alias: controller.joystick.device_alias.clone(),
                             joystick_name: controller.joystick.raw.name(),
                             event: event.clone(),
                         })
@@ -439,7 +559,8 @@ impl ControllerManager {
                         .0
                         .send(ControllerManagerRawEvent {
                             joystick_index: which,
-                            joystick_usb_id: controller.joystick.usb_id.clone(),
+                            joystick_usb_id: controller.joystick.usb_id,
+                            joystick_device_alias: controller.joystick.device_alias.clone(),
                             joystick_name: controller.joystick.raw.name(),
                             event: event.clone(),
                         })
@@ -468,6 +589,14 @@ impl ControllerManager {
                 break;
             }
 
+            let reloaded_config = match self.config_receiver.as_mut() {
+                Some(receiver) if receiver.has_changed().unwrap_or(false) => Some(Arc::clone(&receiver.borrow_and_update())),
+                _ => None,
+            };
+            if let Some(config) = reloaded_config {
+                self.set_config(config);
+            }
+
             let possible_event = event_pump.poll_event();
             match possible_event {
                 Some(event) => {
@@ -1,16 +1,21 @@
 use core::fmt;
-use std::sync::Arc;
+use std::{net::SocketAddr, sync::Arc};
 
 use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpListener,
-    sync::{broadcast::Sender, Mutex},
+    sync::{broadcast::Sender, watch, Mutex},
     task::JoinHandle,
 };
 use tokio_util::sync::CancellationToken;
 use tungstenite::protocol::Message;
 
+use crate::{
+    connection_status::ConnectionStatus,
+    wire_protocol::{WireEnvelope, WireMessage, WirePeer, CAPABILITY_DIRECT_CONTROL},
+};
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct DirectControlCommand {
     pub controls: String,
@@ -20,6 +25,7 @@ pub struct DirectControlCommand {
 
 pub struct DirectController {
     server: Arc<TcpListener>,
+    status: Arc<watch::Sender<ConnectionStatus>>,
 }
 
 impl fmt::Display for DirectControlCommand {
@@ -38,12 +44,23 @@ impl fmt::Display for DirectControlCommand {
 }
 
 impl DirectController {
-    pub async fn new() -> Self {
-        let direct_control_server = TcpListener::bind("0.0.0.0:63241").await.unwrap();
+    pub async fn new(addr: SocketAddr) -> Result<Self, String> {
+        let direct_control_server = TcpListener::bind(addr).await.map_err(|e| {
+            format!(
+                "Could not bind the direct control server to {}: {} - is the port already in use? It can be changed with direct_control_port in settings.json or --direct-control-port",
+                addr, e
+            )
+        })?;
+        println!("[DC] Listening on {}", addr);
 
-        Self {
+        Ok(Self {
             server: Arc::new(direct_control_server),
-        }
+            status: Arc::new(watch::channel(ConnectionStatus::default()).0),
+        })
+    }
+
+    pub fn subscribe_status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.status.subscribe()
     }
 
     pub fn start(&self, cancel_token: CancellationToken, direct_control_command_tx: Arc<Mutex<Sender<DirectControlCommand>>>) -> JoinHandle<()> {
@@ -51,6 +68,7 @@ impl DirectController {
 
         let accept_incoming_clients_server = Arc::clone(&server);
         let accept_incoming_clients_cancel_token = cancel_token.clone();
+        let status = Arc::clone(&self.status);
         tokio::task::spawn(async move {
             let cancel_token_clone: CancellationToken = accept_incoming_clients_cancel_token.clone();
             loop {
@@ -65,6 +83,7 @@ impl DirectController {
                     let direct_control_command_tx_lock = direct_control_command_tx.lock().await;
                     let mut client_direct_control_command_receiver = direct_control_command_tx_lock.subscribe();
                     drop(direct_control_command_tx_lock);
+                    let status = Arc::clone(&status);
                     tokio::task::spawn(async move {
                       let ws_stream = match tokio_tungstenite::accept_async(tcp_stream).await {
                         Ok(ws_stream) => ws_stream,
@@ -74,38 +93,67 @@ impl DirectController {
                         }
                       };
                       let (mut write, mut read) = ws_stream.split();
+                      status.send_modify(|status| status.client_connected());
+                      /* older mods don't say hello and only understand the comma separated format */
+                      let mut peer = WirePeer::default();
 
                       loop {
                         tokio::select! {
                           _ = socket_cancel_token.cancelled() => {
                             break;
                           },
-                          Some(next) = read.next() => {
+                          next = read.next() => {
                             match next {
-                              Ok(message) => match message {
+                              Some(Ok(message)) => match message {
                                 tungstenite::Message::Close(_) => { break },
-                                _ => {},
+                                tungstenite::Message::Text(text) => {
+                                  status.send_modify(|status| status.message_received());
+                                  match WireEnvelope::parse(&text) {
+                                    Some(Ok(WireEnvelope { version, message: WireMessage::Hello { capabilities } })) => {
+                                      println!("[DC] Client speaks protocol version {} with capabilities {:?}", version, capabilities);
+                                      peer.hello(version, capabilities);
+                                      if let Err(e) = write.send(Message::text(WireEnvelope::hello(&[CAPABILITY_DIRECT_CONTROL]).to_text())).await {
+                                        eprintln!("[DC] Error sending hello: {:?}", e);
+                                        status.send_modify(|status| status.send_failed(&e));
+                                      }
+                                    },
+                                    Some(Ok(_)) => {},
+                                    Some(Err(e)) => eprintln!("[DC] {}", e),
+                                    None => {},
+                                  }
+                                },
+                                _ => status.send_modify(|status| status.message_received()),
                               },
-                              Err(e) => {
+                              Some(Err(e)) => {
                                 eprintln!("Client error: {}", e);
                                 break;
                               }
+                              /* the stream ended without a close frame */
+                              None => break,
                             }
                           },
                           Ok(message) = client_direct_control_command_receiver.recv() => {
-                            let command_to_send = format!("direct_control,{}", message);
+                            let command_to_send = match peer.supports(CAPABILITY_DIRECT_CONTROL) {
+                              true => peer.envelope(WireMessage::DirectControl(message)).to_text(),
+                              false => format!("direct_control,{}", message),
+                            };
                             println!("[DC] Sending command: {:?}", command_to_send);
                             match write.send(Message::text(command_to_send.clone())).await {
                               Ok(_) => {
                                 println!("[DC] Message sent: {}", command_to_send.clone());
+                                status.send_modify(|status| status.message_sent());
                               },
                               Err(e) => {
                                 eprintln!("[DC] Error sending message: {:?}", e);
+                                status.send_modify(|status| status.send_failed(&e));
                               }
                             }
                           }
                         }
                       }
+
+                      println!("[DC] Client disconnected");
+                      status.send_modify(|status| status.client_disconnected());
                     });
                   }
                 }
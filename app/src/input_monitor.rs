@@ -0,0 +1,144 @@
+use std::collections::HashMap;
+
+use eframe::egui;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+use tokio::sync::watch;
+
+use crate::{
+    calibration_editor::controller_label,
+    config_defs::usb_id::UsbId,
+    config_loader::ConfigLoader,
+    controller_manager::{ControllerManagerChangeEvent, ControllerManagerConnectedController, ControllerManagerControllerControlState},
+    profile_runner::{ProfileRunnerAssignmentCall, ProfileRunnerAssignmentCallAction, ProfileRunnerControlCalls},
+};
+
+/**
+ * Input monitor panel of the UI - lists the mapped controls of the connected controllers with their current value
+ * and the assignment of the active profile which fired last
+ */
+pub struct InputMonitor {
+    connected_controllers: watch::Receiver<Vec<ControllerManagerConnectedController>>,
+    controller_events: broadcast::Receiver<ControllerManagerChangeEvent>,
+    control_calls: watch::Receiver<ProfileRunnerControlCalls>,
+
+    /* latest state by usb_id, device alias and control name */
+    control_states: HashMap<(UsbId, Option<String>, String), ControllerManagerControllerControlState>,
+}
+
+impl InputMonitor {
+    pub fn new(
+        connected_controllers: watch::Receiver<Vec<ControllerManagerConnectedController>>,
+        controller_events: broadcast::Receiver<ControllerManagerChangeEvent>,
+        control_calls: watch::Receiver<ProfileRunnerControlCalls>,
+    ) -> InputMonitor {
+        InputMonitor {
+            connected_controllers,
+            controller_events,
+            control_calls,
+            control_states: HashMap::new(),
+        }
+    }
+
+    fn receive_controller_events(&mut self) {
+        loop {
+            match self.controller_events.try_recv() {
+                Ok(event) => {
+                    self.control_states.insert((event.usb_id, event.device_alias, event.control_name), event.control_state);
+                }
+                Err(TryRecvError::Lagged(_)) => continue,
+                Err(_) => break,
+            }
+        }
+    }
+
+    pub fn show(&mut self, ui: &mut egui::Ui, config: &ConfigLoader, selected_profile: Option<&String>) {
+        self.receive_controller_events();
+        let connected_controllers = self.connected_controllers.borrow().clone();
+        let control_calls = self.control_calls.borrow().clone();
+
+        match selected_profile {
+            Some(profile) => ui.label(format!("Active profile: {}", profile)),
+            None => ui.label("No profile selected - select one on the profile tab to see which assignments fire"),
+        };
+        if connected_controllers.is_empty() {
+            ui.label("No controllers connected");
+        }
+
+        for controller in connected_controllers.iter() {
+            ui.separator();
+            ui.heading(controller_label(controller));
+            let sdl_mapping = match config.find_sdl_mapping(&controller.identity.usb_id) {
+                Some(sdl_mapping) => sdl_mapping,
+                None => {
+                    ui.label(format!(
+                        "There is no SDL mapping for {} - run the calibrate command to map its controls first",
+                        controller.identity.usb_id
+                    ));
+                    continue;
+                }
+            };
+            let profile = selected_profile.and_then(|name| config.find_controller_profile_for_device(name, &controller.identity.usb_id, controller.device_alias.as_ref()));
+            if selected_profile.is_some() && profile.is_none() {
+                ui.label("The active profile has no assignments for this controller");
+            }
+
+            egui::Grid::new(format!("input_monitor_{}_{}", controller.identity.usb_id, controller.device_alias.as_deref().unwrap_or_default()))
+                .striped(true)
+                .show(ui, |ui| {
+                    ui.strong("Control");
+                    ui.strong("Value");
+                    ui.strong("Direction");
+                    ui.strong("Last fired");
+                    ui.end_row();
+                    for control in sdl_mapping.data.iter() {
+                        let control_state = self.control_states.get(&(controller.identity.usb_id, controller.device_alias.clone(), control.name.clone()));
+                        ui.label(&control.name);
+                        ui.label(control_state.map(|state| format!("{:.3}", state.value)).unwrap_or(String::from("-")));
+                        ui.label(match control_state.map(|state| state.direction.0) {
+                            Some(1) => "up",
+                            Some(-1) => "down",
+                            _ => "-",
+                        });
+                        /* the profile might address the control with its device alias */
+                        let last_call = profile
+                            .and_then(|profile| profile.device_control_name(controller.device_alias.as_ref(), &control.name))
+                            .and_then(|control_name| control_calls.get(&control_name))
+                            .and_then(|calls| last_assignment_call(calls));
+                        ui.label(match last_call {
+                            Some((assignment_index, call)) => format!(
+                                "#{} {}: {} ({:.1}s ago)",
+                                assignment_index,
+                                call.assignment.type_name(),
+                                action_label(&call.action),
+                                call.called_at.elapsed().as_secs_f32()
+                            ),
+                            None => String::from("-"),
+                        });
+                        ui.end_row();
+                    }
+                });
+        }
+    }
+}
+
+/**
+ * The most recent call of the control together with its assignment index
+ */
+fn last_assignment_call(calls: &Vec<Option<ProfileRunnerAssignmentCall>>) -> Option<(usize, &ProfileRunnerAssignmentCall)> {
+    calls
+        .iter()
+        .enumerate()
+        .filter_map(|(assignment_index, call)| call.as_ref().map(|call| (assignment_index, call)))
+        .max_by_key(|(_, call)| call.called_at)
+}
+
+fn action_label(action: &ProfileRunnerAssignmentCallAction) -> String {
+    match action {
+        ProfileRunnerAssignmentCallAction::SequencerAction(action) => match (action.release, action.press_time) {
+            (Some(true), _) => format!("release {}", action.keys),
+            (_, Some(_)) => format!("tap {}", action.keys),
+            (_, None) => format!("press {}", action.keys),
+        },
+        _ => action.get_compare_value(),
+    }
+}
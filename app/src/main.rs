@@ -1,21 +1,29 @@
-use clap::{Parser, Subcommand};
-use config_defs::controller_profile::PreferredControlMode;
-use direct_controller::DirectControlCommand;
+use action_sequencer_output::ActionSequencerOutputBackend;
+use clap::{Args as ClapArgs, Parser, Subcommand, ValueEnum};
+use config_defs::{app_settings::AppSettings, controller_profile::PreferredControlMode};
 use std::sync::Arc;
 
-use controller_manager::ControllerManagerChangeEvent;
 use eframe::egui;
-use tokio::sync::Mutex;
 use tokio_util::sync::CancellationToken;
 
 mod action_sequencer;
+mod action_sequencer_keys;
+mod action_sequencer_output;
+mod calibration_editor;
 mod commands;
 mod config_defs;
 mod config_loader;
+mod config_validator;
+mod config_watcher;
+mod connection_status;
+mod control_api;
 mod controller_manager;
 mod direct_controller;
+mod input_monitor;
+mod pipeline;
 mod profile_runner;
 mod sync_controller;
+mod wire_protocol;
 
 #[derive(Subcommand, Debug, Clone)]
 enum Commands {
@@ -23,13 +31,104 @@ enum Commands {
         #[arg(short, long, default_value = "config")]
         config_dir: String,
     },
+    /// Runs the mapper without the UI
+    Run {
+        #[arg(short, long, default_value = "config")]
+        config_dir: String,
+        #[arg(short, long)]
+        profile: Option<String>,
+        #[arg(short = 'm', long, value_enum, default_value_t = ControlModeArg::DirectControl)]
+        control_mode: ControlModeArg,
+        #[command(flatten)]
+        settings: SettingsArgs,
+    },
+    /// Validates the profiles, calibrations and SDL mappings in the config dir
+    Validate {
+        #[arg(short, long, default_value = "config")]
+        config_dir: String,
+    },
+    /// Writes JSON schemas for the profile, calibration and SDL mapping files
+    Schema {
+        #[arg(short, long, default_value = "config/schemas")]
+        output_dir: String,
+    },
+    /// Lists the key names which can be used in the keys of an action
+    Keys,
+}
+
+/* overrides the settings.json in the config dir - only used by the UI and the run command */
+#[derive(ClapArgs, Debug, Clone)]
+struct SettingsArgs {
+    /// Address the direct control, sync control and control API servers listen on [default: 127.0.0.1]
+    #[arg(long)]
+    bind_address: Option<String>,
+    /// Port of the direct control server [default: 63241]
+    #[arg(long)]
+    direct_control_port: Option<u16>,
+    /// Port of the sync control server [default: 63242]
+    #[arg(long)]
+    sync_control_port: Option<u16>,
+    /// Port of the control API server [default: 63243]
+    #[arg(long)]
+    control_api_port: Option<u16>,
+    /// How key presses are sent to the game [default: enigo]
+    #[arg(long, value_enum)]
+    output_backend: Option<OutputBackendArg>,
+}
+
+/* the CLI names of the config enums - keeps clap out of the config types */
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum ControlModeArg {
+    DirectControl,
+    SyncControl,
+}
+
+impl From<ControlModeArg> for PreferredControlMode {
+    fn from(arg: ControlModeArg) -> PreferredControlMode {
+        match arg {
+            ControlModeArg::DirectControl => PreferredControlMode::DirectControl,
+            ControlModeArg::SyncControl => PreferredControlMode::SyncControl,
+        }
+    }
+}
+
+#[derive(ValueEnum, Debug, Clone, Copy)]
+enum OutputBackendArg {
+    Enigo,
+    Uinput,
+    Recording,
+}
+
+impl From<OutputBackendArg> for ActionSequencerOutputBackend {
+    fn from(arg: OutputBackendArg) -> ActionSequencerOutputBackend {
+        match arg {
+            OutputBackendArg::Enigo => ActionSequencerOutputBackend::Enigo,
+            OutputBackendArg::Uinput => ActionSequencerOutputBackend::Uinput,
+            OutputBackendArg::Recording => ActionSequencerOutputBackend::Recording,
+        }
+    }
+}
+
+impl From<SettingsArgs> for AppSettings {
+    fn from(args: SettingsArgs) -> AppSettings {
+        AppSettings {
+            bind_address: args.bind_address,
+            direct_control_port: args.direct_control_port,
+            sync_control_port: args.sync_control_port,
+            control_api_port: args.control_api_port,
+            output_backend: args.output_backend.map(Into::into),
+        }
+    }
 }
 
 #[derive(Parser, Debug)]
-#[command(version, about, long_about = None)]
+#[command(version, about, long_about = None, args_conflicts_with_subcommands = true)]
 struct Args {
     #[command(subcommand)]
     cmd: Option<Commands>,
+    /* the settings of the UI - the run command takes its own */
+    #[command(flatten)]
+    settings: SettingsArgs,
 }
 
 #[tokio::main]
@@ -42,186 +141,223 @@ async fn main() -> eframe::Result {
             commands::run_calibration_mode::run_calibration_mode(config_dir).await;
             return Ok(());
         }
+        Some(Commands::Run {
+            config_dir,
+            profile,
+            control_mode,
+            settings,
+        }) => {
+            if let Err(e) = commands::run_headless_mode::run_headless_mode(
+                config_dir,
+                profile,
+                control_mode.into(),
+                settings.into(),
+            )
+            .await
+            {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Commands::Validate { config_dir }) => {
+            if !commands::run_validation_mode::run_validation_mode(config_dir) {
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Commands::Schema { output_dir }) => {
+            if let Err(e) = commands::run_schema_export_mode::run_schema_export_mode(output_dir) {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+            return Ok(());
+        }
+        Some(Commands::Keys) => {
+            commands::run_list_keys_mode::run_list_keys_mode();
+            return Ok(());
+        }
         None => {
             println!("No command provided - running UI");
         }
     }
 
     let cancel_token = CancellationToken::new();
-
-    let (on_selected_profile_change_sender, mut on_selected_profile_change_receiver) =
-        tokio::sync::watch::channel::<Option<String>>(None);
-    let (on_preferred_control_mode_change_sender, mut on_preferred_control_mode_change_receiver) =
-        tokio::sync::watch::channel::<PreferredControlMode>(PreferredControlMode::DirectControl);
-
-    let mut config = config_loader::ConfigLoader::new();
-    config.load_from_dir(Some("config"));
-    let shared_config = Arc::new(config);
-
-    let sequencer = Arc::new(action_sequencer::ActionSequencer::new());
-
-    let (direct_controller_sender, _) =
-        tokio::sync::broadcast::channel::<DirectControlCommand>(10000);
-    let direct_controller_sender_arc = Arc::new(Mutex::new(direct_controller_sender.clone()));
-    let direct_controller = direct_controller::DirectController::new().await;
-
-    let profile_runner = Arc::new(Mutex::new(profile_runner::ProfileRunner::new(
-        Arc::clone(&shared_config),
-        Arc::clone(&sequencer),
-        Arc::clone(&direct_controller_sender_arc),
-    )));
-
-    let sync_controller = Arc::new(
-        sync_controller::SyncController::new(
-            Arc::clone(&shared_config),
-            Arc::clone(&sequencer),
-            Arc::clone(&profile_runner),
-        )
-        .await,
-    );
-
-    let (controller_manager_event_channel_sender, _) =
-        tokio::sync::broadcast::channel::<ControllerManagerChangeEvent>(10000);
-
-    let controller_manager_event_channel_sender_clone =
-        controller_manager_event_channel_sender.clone();
-    let controller_manager_config: Arc<config_loader::ConfigLoader> = Arc::clone(&shared_config);
-    let controller_manager_cancel_token = cancel_token.clone();
-    tokio::task::spawn_blocking(move || {
-        let mut controller_manager =
-            controller_manager::ControllerManager::new(controller_manager_config);
-        controller_manager.subscribe(
-            controller_manager_event_channel_sender_clone,
-            controller_manager_cancel_token.clone(),
-        );
-        controller_manager.attach(controller_manager_cancel_token.clone());
-    });
-
-    /* update profile settings task */
-    let profile_listener_cancel_token = cancel_token.clone();
-    let profile_listener_profile_runner_clone = Arc::clone(&profile_runner);
-    let sync_controller_clone = Arc::clone(&sync_controller);
-    tokio::task::spawn(async move {
-        loop {
-            tokio::select! {
-                _ = profile_listener_cancel_token.cancelled() => {
-                    break;
-                },
-                _ = on_selected_profile_change_receiver.changed() => {
-                    let profile = on_selected_profile_change_receiver.borrow().clone();
-                    match profile {
-                        Some(profile) => {
-                            println!("Selected profile: {}", profile.clone());
-                            profile_listener_profile_runner_clone.lock().await.set_profile(profile).unwrap();
-                        },
-                        None => {
-                            println!("Cleared Profile");
-                            profile_listener_profile_runner_clone.lock().await.reset_profile().unwrap();
-                        }
-                    }
-                },
-                _ = on_preferred_control_mode_change_receiver.changed() => {
-                    let control_mode = on_preferred_control_mode_change_receiver.borrow().clone();
-                    profile_listener_profile_runner_clone.lock().await.set_preferred_control_mode(control_mode);
-                    sync_controller_clone.reset_control_state().await;
-                }
-            }
-        }
-    });
-
-    let mut controller_manager_event_channel_receiver =
-        controller_manager_event_channel_sender.subscribe();
-    let event_listener_cancel_token = cancel_token.clone();
-    tokio::task::spawn(async move {
-        loop {
-            tokio::select! {
-                _ = event_listener_cancel_token.cancelled() => {
-                    break;
-                }
-                _ = async {
-                    let event = controller_manager_event_channel_receiver.recv().await.unwrap();
-                    profile_runner.lock().await.run(event).await;
-                } => {}
+    let pipeline =
+        match pipeline::Pipeline::start("config", args.settings.into(), cancel_token.clone()).await
+        {
+            Ok(pipeline) => pipeline,
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
             }
-        }
-    });
-
-    sequencer.run(cancel_token.clone());
-    direct_controller.start(
-        cancel_token.clone(),
-        Arc::clone(&direct_controller_sender_arc),
-    );
-
-    sync_controller.start(
-        cancel_token.clone(),
-        controller_manager_event_channel_sender.subscribe(),
-    );
+        };
 
     let options = eframe::NativeOptions {
-        viewport: egui::ViewportBuilder::default().with_inner_size([400.0, 300.0]),
+        viewport: egui::ViewportBuilder::default().with_inner_size([720.0, 560.0]),
         ..Default::default()
     };
-    eframe::run_native(
+    /* the sequencer task stops with the runtime once the window is closed */
+    let exit_sequencer = Arc::clone(&pipeline.sequencer);
+    let result = eframe::run_native(
         "TSW5 Throttle Mapper",
         options,
         Box::new(|_| {
             Ok(Box::new(MainApp {
-                config: shared_config,
+                calibration_editor: calibration_editor::CalibrationEditor::new(
+                    "config",
+                    pipeline.connected_controllers.clone(),
+                    pipeline.controller_event_sender.subscribe(),
+                ),
+                input_monitor: input_monitor::InputMonitor::new(
+                    pipeline.connected_controllers,
+                    pipeline.controller_event_sender.subscribe(),
+                    pipeline.control_calls,
+                ),
+                sequencer: pipeline.sequencer,
+                direct_controller_status: pipeline.direct_controller_status,
+                sync_controller_status: pipeline.sync_controller_status,
+                config: pipeline.config,
+                config_reload_errors: pipeline.config_reload_errors,
                 ui_close_token: cancel_token,
+                tab: MainAppTab::Profile,
                 selected_profile: None,
                 prefer_sync_control_mode: false,
-                on_selected_profile_change_sender,
-                on_preferred_control_mode_change_sender,
+                on_selected_profile_change_sender: pipeline.on_selected_profile_change_sender,
+                on_preferred_control_mode_change_sender: pipeline
+                    .on_preferred_control_mode_change_sender,
             }))
         }),
-    )
+    );
+    exit_sequencer.release_all().await;
+    result
+}
+
+#[derive(PartialEq)]
+enum MainAppTab {
+    Profile,
+    Calibration,
+    InputMonitor,
 }
 
 struct MainApp {
-    config: Arc<config_loader::ConfigLoader>,
+    config: tokio::sync::watch::Receiver<Arc<config_loader::ConfigLoader>>,
+    config_reload_errors: tokio::sync::watch::Receiver<Vec<String>>,
+    direct_controller_status: tokio::sync::watch::Receiver<connection_status::ConnectionStatus>,
+    sync_controller_status: tokio::sync::watch::Receiver<connection_status::ConnectionStatus>,
+    sequencer: Arc<action_sequencer::ActionSequencer>,
     ui_close_token: CancellationToken,
+    calibration_editor: calibration_editor::CalibrationEditor,
+    input_monitor: input_monitor::InputMonitor,
 
     /* local state */
+    tab: MainAppTab,
     selected_profile: Option<String>,
     prefer_sync_control_mode: bool,
 
     /* channels */
-    on_selected_profile_change_sender: tokio::sync::watch::Sender<Option<String>>,
-    on_preferred_control_mode_change_sender: tokio::sync::watch::Sender<PreferredControlMode>,
+    on_selected_profile_change_sender: Arc<tokio::sync::watch::Sender<Option<String>>>,
+    on_preferred_control_mode_change_sender: Arc<tokio::sync::watch::Sender<PreferredControlMode>>,
 }
 
 impl eframe::App for MainApp {
     fn update(&mut self, ctx: &egui::Context, _frame: &mut eframe::Frame) {
+        /* pick up changes made through the control API */
+        self.selected_profile = self.on_selected_profile_change_sender.borrow().clone();
+        self.prefer_sync_control_mode = *self.on_preferred_control_mode_change_sender.borrow()
+            == PreferredControlMode::SyncControl;
+
         let mut selected_profile = self.selected_profile.clone();
         let mut prefer_sync_control_mode = self.prefer_sync_control_mode;
+        let config = Arc::clone(&self.config.borrow());
+        let config_reload_errors = self.config_reload_errors.borrow().clone();
 
-        egui::CentralPanel::default().show(ctx, |ui| {
-            ui.vertical(|ui| {
-                egui::ComboBox::from_label("Select profile")
-                    .selected_text(format!(
-                        "{}",
-                        match &selected_profile {
-                            Some(profile) => profile.clone(),
-                            None => String::from(""),
-                        }
-                    ))
-                    .show_ui(ui, |ui| {
-                        ui.selectable_value(&mut selected_profile, None, "None");
-                        for profile in self.config.controller_profiles.iter() {
-                            ui.selectable_value(
-                                &mut selected_profile,
-                                Some(profile.name.clone()),
-                                profile.name.clone(),
-                            );
-                        }
-                    });
-
-                ui.checkbox(&mut prefer_sync_control_mode, "Prefer sync control mode");
+        egui::TopBottomPanel::top("tabs").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                ui.selectable_value(&mut self.tab, MainAppTab::Profile, "Profile");
+                ui.selectable_value(&mut self.tab, MainAppTab::Calibration, "Calibration");
+                ui.selectable_value(&mut self.tab, MainAppTab::InputMonitor, "Input monitor");
+            });
+        });
 
-                ui.label("Sync Control Mode is less accurate but might be more stable. If you are having problems using direct control mode you can enable the \"Prefer sync control mode\" option.");
+        let direct_controller_status = self.direct_controller_status.borrow().clone();
+        let sync_controller_status = self.sync_controller_status.borrow().clone();
+        egui::TopBottomPanel::bottom("connection_status").show(ctx, |ui| {
+            ui.horizontal(|ui| {
+                connection_status_label(ui, "Direct control", &direct_controller_status);
+                ui.separator();
+                connection_status_label(ui, "Sync control", &sync_controller_status);
+                ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                    if ui
+                        .button("Release all keys")
+                        .on_hover_text("Lets go of every key which is still held down in the game")
+                        .clicked()
+                    {
+                        let sequencer = Arc::clone(&self.sequencer);
+                        tokio::spawn(async move {
+                            sequencer.release_all().await;
+                        });
+                    }
+                });
             });
         });
 
+        egui::CentralPanel::default().show(ctx, |ui| match self.tab {
+            MainAppTab::Calibration => {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.calibration_editor.show(ui, &config);
+                });
+                /* live values */
+                ctx.request_repaint_after(std::time::Duration::from_millis(50));
+            }
+            MainAppTab::InputMonitor => {
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    self.input_monitor.show(ui, &config, selected_profile.as_ref());
+                });
+                ctx.request_repaint_after(std::time::Duration::from_millis(50));
+            }
+            MainAppTab::Profile => {
+                ui.vertical(|ui| {
+                    egui::ComboBox::from_label("Select profile")
+                        .selected_text(format!(
+                            "{}",
+                            match &selected_profile {
+                                Some(profile) => profile.clone(),
+                                None => String::from(""),
+                            }
+                        ))
+                        .show_ui(ui, |ui| {
+                            ui.selectable_value(&mut selected_profile, None, "None");
+                            for profile in config.controller_profiles.iter() {
+                                ui.selectable_value(
+                                    &mut selected_profile,
+                                    Some(profile.name.clone()),
+                                    profile.name.clone(),
+                                );
+                            }
+                        });
+
+                    ui.checkbox(&mut prefer_sync_control_mode, "Prefer sync control mode");
+
+                    ui.label("Sync Control Mode is less accurate but might be more stable. If you are having problems using direct control mode you can enable the \"Prefer sync control mode\" option.");
+
+                    if !config_reload_errors.is_empty() {
+                        ui.separator();
+                        ui.colored_label(
+                            egui::Color32::RED,
+                            "Could not reload config - the previous config is still active:",
+                        );
+                        for error in config_reload_errors.iter() {
+                            ui.colored_label(egui::Color32::RED, error);
+                        }
+                    }
+                });
+            }
+        });
+
+        /* changes can also come from the control API or config reloads */
+        ctx.request_repaint_after(std::time::Duration::from_secs(1));
+
         if selected_profile != self.selected_profile {
             self.selected_profile = selected_profile.clone();
             self.on_selected_profile_change_sender
@@ -244,3 +380,33 @@ impl eframe::App for MainApp {
         self.ui_close_token.cancel();
     }
 }
+
+/**
+ * Shows whether the game mod is connected to one of the control servers
+ */
+fn connection_status_label(
+    ui: &mut egui::Ui,
+    name: &str,
+    status: &connection_status::ConnectionStatus,
+) {
+    let (color, state) = match status.is_connected() {
+        true => (egui::Color32::GREEN, "connected"),
+        false => (egui::Color32::RED, "not connected"),
+    };
+    let (rect, _) = ui.allocate_exact_size(egui::vec2(10.0, 10.0), egui::Sense::hover());
+    ui.painter().circle_filled(rect.center(), 4.0, color);
+    ui.label(format!("{}: {}", name, state));
+    if let Some(since_last_message) = status.since_last_message() {
+        ui.label(format!(
+            "(last message {:.0}s ago)",
+            since_last_message.as_secs_f32()
+        ));
+    }
+    if status.send_errors > 0 {
+        ui.colored_label(
+            egui::Color32::YELLOW,
+            format!("{} send errors", status.send_errors),
+        )
+        .on_hover_text(status.last_error.clone().unwrap_or_default());
+    }
+}
@@ -0,0 +1,297 @@
+use std::sync::Arc;
+
+use tokio::sync::{watch, Mutex};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
+
+use crate::{
+    action_sequencer::ActionSequencer,
+    config_defs::{app_settings::AppSettings, controller_profile::PreferredControlMode},
+    config_loader::ConfigLoader,
+    config_watcher::ConfigWatcher,
+    connection_status::ConnectionStatus,
+    control_api::ControlApi,
+    controller_manager::{ControllerManager, ControllerManagerChangeEvent, ControllerManagerConnectedController},
+    direct_controller::{DirectControlCommand, DirectController},
+    profile_runner::{ProfileRunner, ProfileRunnerControlCalls},
+    sync_controller::SyncController,
+};
+
+/**
+ * Wires up the controller manager, profile runner, action sequencer, the direct/sync control servers and the control API.
+ * Shared between the UI and the headless `run` command.
+ */
+pub struct Pipeline {
+    /* always holds the latest successfully loaded config */
+    pub config: watch::Receiver<Arc<ConfigLoader>>,
+    /* errors of the last config reload - empty when the reload succeeded */
+    pub config_reload_errors: watch::Receiver<Vec<String>>,
+    pub on_selected_profile_change_sender: Arc<watch::Sender<Option<String>>>,
+    pub on_preferred_control_mode_change_sender: Arc<watch::Sender<PreferredControlMode>>,
+    pub connected_controllers: watch::Receiver<Vec<ControllerManagerConnectedController>>,
+    /* subscribe to receive the normalised control changes */
+    pub controller_event_sender: tokio::sync::broadcast::Sender<ControllerManagerChangeEvent>,
+    /* the assignments of the active profile which fired last */
+    pub control_calls: watch::Receiver<ProfileRunnerControlCalls>,
+    /* connection diagnostics of the direct/sync control servers */
+    pub direct_controller_status: watch::Receiver<ConnectionStatus>,
+    pub sync_controller_status: watch::Receiver<ConnectionStatus>,
+    /* release_all lets go of every key the profiles are holding down */
+    pub sequencer: Arc<ActionSequencer>,
+    controller_manager_task: JoinHandle<()>,
+    sequencer_task: JoinHandle<()>,
+}
+
+impl Pipeline {
+    /**
+     * The settings overrides (eg: from the command line) take precedence over the settings.json in the config dir
+     */
+    pub async fn start<T: AsRef<str>>(config_dir: T, settings_overrides: AppSettings, cancel_token: CancellationToken) -> Result<Pipeline, String> {
+        let config_dir = config_dir.as_ref();
+        let settings = ConfigLoader::load_app_settings(config_dir)?.merge(settings_overrides);
+        let direct_control_addr = settings.direct_control_addr()?;
+        let sync_control_addr = settings.sync_control_addr()?;
+        let control_api_addr = settings.control_api_addr()?;
+        let (on_selected_profile_change_sender, mut on_selected_profile_change_receiver) = watch::channel::<Option<String>>(None);
+        let (on_preferred_control_mode_change_sender, mut on_preferred_control_mode_change_receiver) = watch::channel::<PreferredControlMode>(PreferredControlMode::DirectControl);
+        let on_selected_profile_change_sender = Arc::new(on_selected_profile_change_sender);
+        let on_preferred_control_mode_change_sender = Arc::new(on_preferred_control_mode_change_sender);
+
+        let mut config = ConfigLoader::new();
+        config.load_from_dir(Some(config_dir));
+        let shared_config = Arc::new(config);
+        let (config_sender, config_receiver) = watch::channel::<Arc<ConfigLoader>>(Arc::clone(&shared_config));
+        let (config_reload_errors_sender, config_reload_errors_receiver) = watch::channel::<Vec<String>>(Vec::new());
+
+        let output_backend = settings.output_backend.unwrap_or_default();
+        println!("[Pipeline] Sending key presses through the {:?} output", output_backend);
+        let sequencer = Arc::new(ActionSequencer::new(output_backend.create()?));
+
+        let (direct_controller_sender, _) = tokio::sync::broadcast::channel::<DirectControlCommand>(10000);
+        let direct_controller_sender_arc = Arc::new(Mutex::new(direct_controller_sender.clone()));
+        let direct_controller = DirectController::new(direct_control_addr).await?;
+
+        let profile_runner = Arc::new(Mutex::new(ProfileRunner::new(
+            Arc::clone(&shared_config),
+            Arc::clone(&sequencer),
+            Arc::clone(&direct_controller_sender_arc),
+            cancel_token.clone(),
+        )));
+        let control_calls_receiver = profile_runner.lock().await.subscribe_control_calls();
+
+        let sync_controller = Arc::new(SyncController::new(Arc::clone(&shared_config), Arc::clone(&sequencer), Arc::clone(&profile_runner), sync_control_addr).await?);
+
+        let (controller_manager_event_channel_sender, _) = tokio::sync::broadcast::channel::<ControllerManagerChangeEvent>(10000);
+
+        let controller_manager_event_channel_sender_clone = controller_manager_event_channel_sender.clone();
+        let controller_manager_config = Arc::clone(&shared_config);
+        let controller_manager_config_receiver = config_receiver.clone();
+        let controller_manager_cancel_token = cancel_token.clone();
+        let (connected_controllers_sender, connected_controllers_receiver) = watch::channel::<Vec<ControllerManagerConnectedController>>(Vec::new());
+        let controller_manager_task = tokio::task::spawn_blocking(move || {
+            let mut controller_manager = ControllerManager::new(controller_manager_config);
+            controller_manager.watch_config(controller_manager_config_receiver);
+            controller_manager.report_connected_controllers(connected_controllers_sender);
+            controller_manager.subscribe(controller_manager_event_channel_sender_clone, controller_manager_cancel_token.clone());
+            controller_manager.attach(controller_manager_cancel_token.clone());
+        });
+
+        /* update profile settings task */
+        let profile_listener_cancel_token = cancel_token.clone();
+        let profile_listener_profile_runner_clone = Arc::clone(&profile_runner);
+        let profile_listener_sequencer = Arc::clone(&sequencer);
+        let sync_controller_clone = Arc::clone(&sync_controller);
+        tokio::task::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = profile_listener_cancel_token.cancelled() => {
+                        break;
+                    },
+                    _ = on_selected_profile_change_receiver.changed() => {
+                        let profile = on_selected_profile_change_receiver.borrow().clone();
+                        /* the new profile won't release keys held by the previous one */
+                        profile_listener_sequencer.release_all().await;
+                        match profile {
+                            Some(profile) => {
+                                println!("Selected profile: {}", profile.clone());
+                                if let Err(e) = profile_listener_profile_runner_clone.lock().await.set_profile(profile).await {
+                                    eprintln!("{}", e);
+                                }
+                            },
+                            None => {
+                                println!("Cleared Profile");
+                                profile_listener_profile_runner_clone.lock().await.reset_profile().await.unwrap();
+                            }
+                        }
+                    },
+                    _ = on_preferred_control_mode_change_receiver.changed() => {
+                        let control_mode = on_preferred_control_mode_change_receiver.borrow().clone();
+                        profile_listener_sequencer.release_all().await;
+                        profile_listener_profile_runner_clone.lock().await.set_preferred_control_mode(control_mode);
+                        sync_controller_clone.reset_control_state().await;
+                    }
+                }
+            }
+        });
+
+        /* switch profiles automatically when the driven vehicle changes */
+        let mut current_vehicle_receiver = sync_controller.subscribe_current_vehicle();
+        let current_vehicle_cancel_token = cancel_token.clone();
+        let current_vehicle_config = config_receiver.clone();
+        let current_vehicle_profile_change_sender = Arc::clone(&on_selected_profile_change_sender);
+        tokio::task::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = current_vehicle_cancel_token.cancelled() => {
+                        break;
+                    },
+                    Ok(vehicle_class) = current_vehicle_receiver.recv() => {
+                        let config = Arc::clone(&current_vehicle_config.borrow());
+                        match config.find_controller_profile_for_vehicle(&vehicle_class) {
+                            Some(profile) => {
+                                let profile_name = Some(profile.name.clone());
+                                if *current_vehicle_profile_change_sender.borrow() != profile_name {
+                                    println!("Vehicle changed to {} - selecting profile {}", vehicle_class, profile.name);
+                                    current_vehicle_profile_change_sender.send_replace(profile_name);
+                                }
+                            }
+                            None => println!("Vehicle changed to {} - no matching profile", vehicle_class),
+                        }
+                    }
+                }
+            }
+        });
+
+        /* an unplugged controller never sends the release of the controls which were active */
+        let mut removed_controllers_receiver = connected_controllers_receiver.clone();
+        let removed_controllers_cancel_token = cancel_token.clone();
+        let removed_controllers_profile_runner = Arc::clone(&profile_runner);
+        let removed_controllers_sync_controller = Arc::clone(&sync_controller);
+        tokio::task::spawn(async move {
+            let mut previous_controllers = removed_controllers_receiver.borrow_and_update().clone();
+            loop {
+                tokio::select! {
+                    _ = removed_controllers_cancel_token.cancelled() => {
+                        break;
+                    },
+                    Ok(_) = removed_controllers_receiver.changed() => {
+                        let controllers = removed_controllers_receiver.borrow_and_update().clone();
+                        let removed_controllers = previous_controllers.iter().filter(|previous| !controllers.iter().any(|controller| controller.identity == previous.identity));
+                        for removed in removed_controllers {
+                            println!("Controller {} removed - releasing its keys", removed.name);
+                            removed_controllers_profile_runner.lock().await.release_device(&removed.identity.usb_id, removed.device_alias.as_ref()).await;
+                            removed_controllers_sync_controller.release_device(&removed.identity.usb_id, removed.device_alias.as_ref()).await;
+                        }
+                        previous_controllers = controllers;
+                    }
+                }
+            }
+        });
+
+        let mut controller_manager_event_channel_receiver = controller_manager_event_channel_sender.subscribe();
+        let event_listener_cancel_token = cancel_token.clone();
+        let event_listener_profile_runner = Arc::clone(&profile_runner);
+        tokio::task::spawn(async move {
+            loop {
+                tokio::select! {
+                    _ = event_listener_cancel_token.cancelled() => {
+                        break;
+                    }
+                    _ = async {
+                        let event = controller_manager_event_channel_receiver.recv().await.unwrap();
+                        event_listener_profile_runner.lock().await.run(event).await;
+                    } => {}
+                }
+            }
+        });
+
+        let sequencer_task = sequencer.run(cancel_token.clone());
+        direct_controller.start(cancel_token.clone(), Arc::clone(&direct_controller_sender_arc));
+        sync_controller.start(cancel_token.clone(), controller_manager_event_channel_sender.subscribe());
+
+        /* reload the config whenever any of the config files change */
+        match ConfigWatcher::new(config_dir).start(cancel_token.clone()) {
+            Ok(mut config_changed_receiver) => {
+                let config_dir = String::from(config_dir);
+                let config_reload_cancel_token = cancel_token.clone();
+                let config_reload_profile_runner = Arc::clone(&profile_runner);
+                let config_reload_sync_controller = Arc::clone(&sync_controller);
+                tokio::task::spawn(async move {
+                    loop {
+                        tokio::select! {
+                            _ = config_reload_cancel_token.cancelled() => {
+                                break;
+                            },
+                            Some(_) = config_changed_receiver.recv() => {
+                                match ConfigLoader::try_load_from_dir(&config_dir) {
+                                    Ok(config) => {
+                                        println!("[Config] Reloaded config from {}", config_dir);
+                                        let config = Arc::new(config);
+                                        config_reload_profile_runner.lock().await.set_config(Arc::clone(&config)).await;
+                                        config_reload_sync_controller.set_config(Arc::clone(&config)).await;
+                                        config_sender.send_replace(config);
+                                        config_reload_errors_sender.send_replace(Vec::new());
+                                    }
+                                    Err(errors) => {
+                                        eprintln!("[Config] Could not reload config - keeping the previous config active:");
+                                        for error in errors.iter() {
+                                            eprintln!("[Config]   {}", error);
+                                        }
+                                        config_reload_errors_sender.send_replace(errors);
+                                    }
+                                }
+                            }
+                        }
+                    }
+                });
+            }
+            Err(e) => eprintln!("[Config] {}", e),
+        }
+
+        let direct_controller_status = direct_controller.subscribe_status();
+        let sync_controller_status = sync_controller.subscribe_status();
+        match ControlApi::new(
+            config_receiver.clone(),
+            Arc::clone(&on_selected_profile_change_sender),
+            Arc::clone(&on_preferred_control_mode_change_sender),
+            direct_controller_status.clone(),
+            sync_controller_status.clone(),
+            Arc::clone(&sequencer),
+            control_api_addr,
+        )
+        .await
+        {
+            Ok(control_api) => {
+                control_api.start(cancel_token.clone());
+            }
+            Err(e) => eprintln!("[API] {}", e),
+        }
+
+        Ok(Pipeline {
+            config: config_receiver,
+            config_reload_errors: config_reload_errors_receiver,
+            on_selected_profile_change_sender,
+            on_preferred_control_mode_change_sender,
+            connected_controllers: connected_controllers_receiver,
+            controller_event_sender: controller_manager_event_channel_sender,
+            control_calls: control_calls_receiver,
+            direct_controller_status,
+            sync_controller_status,
+            sequencer,
+            controller_manager_task,
+            sequencer_task,
+        })
+    }
+
+    /**
+     * Waits for the controller manager to detach and the sequencer to release the held keys after the cancel token has been cancelled
+     */
+    pub async fn wait(self) {
+        if let Err(e) = self.controller_manager_task.await {
+            eprintln!("Controller manager task failed: {}", e);
+        }
+        if let Err(e) = self.sequencer_task.await {
+            eprintln!("Action sequencer task failed: {}", e);
+        }
+    }
+}
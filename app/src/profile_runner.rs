@@ -1,11 +1,18 @@
 use std::{collections::HashMap, sync::Arc};
 
-use tokio::sync::{broadcast::Sender, Mutex};
+use tokio::sync::{broadcast::Sender, watch, Mutex};
+use tokio::time::{sleep, Duration, Instant};
+use tokio_util::sync::CancellationToken;
 
 use crate::{
-    action_sequencer::ActionSequencer,
-    config_defs::controller_profile::{
-        ControllerProfile, ControllerProfileControlAssignment, ControllerProfileControlAssignmentAction, ControllerProfileControlLinearAssignmentThreshold, PreferredControlMode,
+    action_sequencer::{ActionSequencer, ActionSequencerAction, ActionSequencerMouseAction},
+    config_defs::{
+        controller_profile::{
+            ControllerProfile, ControllerProfileControlAssignment, ControllerProfileControlAssignmentAction, ControllerProfileControlAssignmentSequenceAction,
+            ControllerProfileControlAssignmentSequenceStep, ControllerProfileControlHoldAssignment, ControllerProfileControlLinearAssignmentThreshold, ControllerProfileControlMultiTapAssignment,
+            ControllerProfileMouseAction, ControllerProfileMouseButton, PreferredControlMode,
+        },
+        usb_id::UsbId,
     },
     config_loader::ConfigLoader,
     controller_manager::{ControllerManagerChangeEvent, ControllerManagerControllerControlState},
@@ -16,6 +23,17 @@ use crate::{
 pub enum ProfileRunnerAssignmentCallAction {
     SequencerAction(super::action_sequencer::ActionSequencerAction),
     DirectControlAction(super::direct_controller::DirectControlCommand),
+    SequenceAction(ControllerProfileControlAssignmentSequenceAction),
+    MouseAction(ActionSequencerMouseAction),
+}
+
+/* everything needed to send actions - cloned into the timer and sequence tasks */
+#[derive(Clone)]
+struct ProfileRunnerOutput {
+    sequencer: Arc<ActionSequencer>,
+    direct_control_sender: Arc<Mutex<Sender<DirectControlCommand>>>,
+    /* cancel tokens of the running sequences keyed by control name and assignment index */
+    running_sequences: Arc<Mutex<HashMap<(String, usize), CancellationToken>>>,
 }
 
 #[derive(Clone)]
@@ -24,17 +42,45 @@ pub struct ProfileRunnerAssignmentCall {
     pub control_state: ControllerManagerControllerControlState,
     pub assignment: ControllerProfileControlAssignment,
     pub action: ProfileRunnerAssignmentCallAction,
+    pub called_at: Instant,
+}
+
+/* last call of every assignment keyed by control name - published to the input monitor */
+pub type ProfileRunnerControlCalls = HashMap<String, Vec<Option<ProfileRunnerAssignmentCall>>>;
+
+/* runtime state of the timer based assignments (hold and multi_tap) */
+#[derive(Default)]
+struct ProfileRunnerTimedAssignmentState {
+    pressed: bool,
+    taps: usize,
+    timer: Option<CancellationToken>,
+    /* set when an action fired which needs to be deactivated once the control is released */
+    active: bool,
+    deactivate_action: Option<ProfileRunnerAssignmentCallAction>,
 }
 
 pub struct ProfileRunner {
     config: Arc<ConfigLoader>,
     sequencer: Arc<ActionSequencer>,
     direct_control_sender: Arc<Mutex<Sender<DirectControlCommand>>>,
+    running_sequences: Arc<Mutex<HashMap<(String, usize), CancellationToken>>>,
+    /* the hold and multi_tap timers stop with the app */
+    cancel_token: CancellationToken,
     /* current config */
     profile_name: Option<String>,
     preferred_control_mode: PreferredControlMode,
     /* keeps track of the last called assignments */
-    control_calls: HashMap<String, Vec<Option<ProfileRunnerAssignmentCall>>>,
+    control_calls: ProfileRunnerControlCalls,
+    control_calls_sender: watch::Sender<ProfileRunnerControlCalls>,
+    /* held state of the shift controls and the resulting layer */
+    shift_control_states: HashMap<String, bool>,
+    active_layer: Option<String>,
+    /* keyed by control name and assignment index */
+    timed_assignments: HashMap<(String, usize), Arc<Mutex<ProfileRunnerTimedAssignmentState>>>,
+    /* active chords with their deactivation action */
+    active_chords: HashMap<(String, usize), Option<ProfileRunnerAssignmentCallAction>>,
+    /* latest value of every control - used to evaluate chords */
+    control_values: HashMap<String, f32>,
 }
 
 impl ProfileRunnerAssignmentCallAction {
@@ -46,28 +92,316 @@ impl ProfileRunnerAssignmentCallAction {
             ProfileRunnerAssignmentCallAction::DirectControlAction(action) => {
                 format!("{}", action)
             }
+            ProfileRunnerAssignmentCallAction::SequenceAction(action) => action.get_compare_value(),
+            ProfileRunnerAssignmentCallAction::MouseAction(action) => format!("mouse:{:?}", action),
+        }
+    }
+
+    /**
+     * Converts a configured mouse action - only pressed buttons can be released
+     */
+    pub fn from_mouse_action(action: &ControllerProfileMouseAction, release: bool) -> Option<ActionSequencerMouseAction> {
+        let button = |button: &Option<ControllerProfileMouseButton>| match button.unwrap_or_default() {
+            ControllerProfileMouseButton::Left => enigo::Button::Left,
+            ControllerProfileMouseButton::Middle => enigo::Button::Middle,
+            ControllerProfileMouseButton::Right => enigo::Button::Right,
+        };
+        let mouse_action = match action {
+            ControllerProfileMouseAction::Click { button: mouse_button } => ActionSequencerMouseAction::Button {
+                button: button(mouse_button),
+                direction: enigo::Direction::Click,
+            },
+            ControllerProfileMouseAction::Press { button: mouse_button } => ActionSequencerMouseAction::Button {
+                button: button(mouse_button),
+                direction: enigo::Direction::Press,
+            },
+            ControllerProfileMouseAction::Release { button: mouse_button } => ActionSequencerMouseAction::Button {
+                button: button(mouse_button),
+                direction: enigo::Direction::Release,
+            },
+            ControllerProfileMouseAction::Scroll { amount, horizontal } => ActionSequencerMouseAction::Scroll {
+                amount: *amount,
+                axis: match horizontal {
+                    Some(true) => enigo::Axis::Horizontal,
+                    _ => enigo::Axis::Vertical,
+                },
+            },
+            ControllerProfileMouseAction::Move { x, y } => ActionSequencerMouseAction::Move { x: *x, y: *y },
+        };
+        match release {
+            true => mouse_action.release_action(),
+            false => Some(mouse_action),
         }
     }
+
+    /**
+     * Converts a configured action into a call action; direct control actions can't be released
+     */
+    pub fn from_assignment_action(action: &ControllerProfileControlAssignmentAction, release: bool) -> Option<ProfileRunnerAssignmentCallAction> {
+        match action {
+            ControllerProfileControlAssignmentAction::Keys(action) => Some(ProfileRunnerAssignmentCallAction::SequencerAction(ActionSequencerAction {
+                keys: action.keys.clone(),
+                press_time: action.press_time,
+                wait_time: action.wait_time,
+                release: Some(release),
+                priority: action.priority.unwrap_or_default(),
+            })),
+            ControllerProfileControlAssignmentAction::DirectControl(action) => match release {
+                true => None,
+                false => Some(ProfileRunnerAssignmentCallAction::DirectControlAction(DirectControlCommand {
+                    controls: action.controls.clone(),
+                    input_value: action.value,
+                    hold: action.hold,
+                })),
+            },
+            ControllerProfileControlAssignmentAction::Mouse(action) => ProfileRunnerAssignmentCallAction::from_mouse_action(&action.mouse, release).map(ProfileRunnerAssignmentCallAction::MouseAction),
+            /* sequences are only cancelled by activating them again */
+            ControllerProfileControlAssignmentAction::Sequence(action) => match release {
+                true => None,
+                false => Some(ProfileRunnerAssignmentCallAction::SequenceAction(action.clone())),
+            },
+        }
+    }
+
+    /**
+     * Uses the deactivate action if configured - otherwise releases the activate keys
+     */
+    pub fn from_deactivate_action(
+        action_activate: &ControllerProfileControlAssignmentAction,
+        action_deactivate: &Option<ControllerProfileControlAssignmentAction>,
+    ) -> Option<ProfileRunnerAssignmentCallAction> {
+        match action_deactivate {
+            Some(action) => ProfileRunnerAssignmentCallAction::from_assignment_action(action, false),
+            None => ProfileRunnerAssignmentCallAction::from_assignment_action(action_activate, true),
+        }
+    }
+}
+
+impl ProfileRunnerOutput {
+    /* the assignment (control name and assignment index) the action belongs to */
+    async fn send(&self, assignment: &(String, usize), action: &ProfileRunnerAssignmentCallAction) {
+        match action {
+            ProfileRunnerAssignmentCallAction::SequencerAction(action) => {
+                self.sequencer.add_action(action.clone()).await;
+            }
+            ProfileRunnerAssignmentCallAction::DirectControlAction(action) => {
+                self.send_direct_control_command(action).await;
+            }
+            ProfileRunnerAssignmentCallAction::SequenceAction(action) => {
+                self.run_sequence(assignment, action).await;
+            }
+            ProfileRunnerAssignmentCallAction::MouseAction(action) => {
+                self.sequencer.add_mouse_action(action.clone()).await;
+            }
+        }
+    }
+
+    /* lets the sequencer drop queued key presses of the control which are no longer needed */
+    async fn send_for_control(&self, assignment: &(String, usize), action: &ProfileRunnerAssignmentCallAction) {
+        match action {
+            ProfileRunnerAssignmentCallAction::SequencerAction(action) => {
+                self.sequencer.add_action_for_control(&assignment.0, action.clone()).await;
+            }
+            _ => self.send(assignment, action).await,
+        }
+    }
+
+    async fn cancel_sequences(&self) {
+        for (_, cancel_token) in self.running_sequences.lock().await.drain() {
+            cancel_token.cancel();
+        }
+    }
+
+    async fn send_direct_control_command(&self, command: &DirectControlCommand) {
+        let direct_control_sender = self.direct_control_sender.lock().await;
+        match direct_control_sender.send(command.clone()) {
+            Ok(_) => {}
+            Err(e) => {
+                println!("Error sending direct control command ({:?}): {}", command, e);
+            }
+        }
+    }
+
+    /**
+     * Runs the sequence steps in the background; running the sequence of the same assignment while it is still running cancels it instead
+     */
+    async fn run_sequence(&self, assignment: &(String, usize), action: &ControllerProfileControlAssignmentSequenceAction) {
+        let sequence_key = assignment.clone();
+        let mut running_sequences = self.running_sequences.lock().await;
+        match running_sequences.remove(&sequence_key) {
+            /* sequences stopped by release_all are still listed */
+            Some(cancel_token) if !cancel_token.is_cancelled() => {
+                println!("Cancelled running sequence");
+                cancel_token.cancel();
+                return;
+            }
+            _ => {}
+        }
+        /* release_all stops the running sequences */
+        let cancel_token = self.sequencer.release_token().child_token();
+        running_sequences.insert(sequence_key.clone(), cancel_token.clone());
+        drop(running_sequences);
+
+        let output = self.clone();
+        let steps = action.sequence.clone();
+        tokio::task::spawn(async move {
+            for step in steps.iter() {
+                if cancel_token.is_cancelled() {
+                    break;
+                }
+                /* key steps are tapped - the next step waits until the sequencer is done with them */
+                let delay = match step {
+                    ControllerProfileControlAssignmentSequenceStep::Keys(action) => {
+                        let press_time = action.press_time.unwrap_or(0.1);
+                        let wait_time = action.wait_time.unwrap_or(0.1);
+                        output
+                            .sequencer
+                            .add_action(ActionSequencerAction {
+                                keys: action.keys.clone(),
+                                press_time: Some(press_time),
+                                wait_time: Some(wait_time),
+                                release: None,
+                                priority: action.priority.unwrap_or_default(),
+                            })
+                            .await;
+                        press_time + wait_time
+                    }
+                    ControllerProfileControlAssignmentSequenceStep::DirectControl(action) => {
+                        output
+                            .send_direct_control_command(&DirectControlCommand {
+                                controls: action.controls.clone(),
+                                input_value: action.value,
+                                hold: action.hold,
+                            })
+                            .await;
+                        0.0
+                    }
+                    ControllerProfileControlAssignmentSequenceStep::Mouse(action) => {
+                        if let Some(mouse_action) = ProfileRunnerAssignmentCallAction::from_mouse_action(&action.mouse, false) {
+                            output.sequencer.add_mouse_action(mouse_action).await;
+                        }
+                        0.0
+                    }
+                    ControllerProfileControlAssignmentSequenceStep::Wait(step) => step.wait,
+                };
+                tokio::select! {
+                    _ = cancel_token.cancelled() => break,
+                    _ = sleep(Duration::from_millis((delay * 1000.0).abs() as u64)) => {},
+                }
+            }
+
+            /* a cancelled sequence was already removed by whoever cancelled it - or is replaced once it is started again after release_all */
+            if !cancel_token.is_cancelled() {
+                output.running_sequences.lock().await.remove(&sequence_key);
+            }
+        });
+    }
 }
 
 impl ProfileRunner {
-    pub fn new(config: Arc<ConfigLoader>, sequencer: Arc<ActionSequencer>, direct_control_sender: Arc<Mutex<Sender<DirectControlCommand>>>) -> ProfileRunner {
+    pub fn new(config: Arc<ConfigLoader>, sequencer: Arc<ActionSequencer>, direct_control_sender: Arc<Mutex<Sender<DirectControlCommand>>>, cancel_token: CancellationToken) -> ProfileRunner {
         ProfileRunner {
             config,
             sequencer,
             direct_control_sender,
+            running_sequences: Arc::new(Mutex::new(HashMap::new())),
+            cancel_token,
             profile_name: None,
             preferred_control_mode: PreferredControlMode::DirectControl,
             control_calls: HashMap::new(),
+            control_calls_sender: watch::channel(HashMap::new()).0,
+            shift_control_states: HashMap::new(),
+            active_layer: None,
+            timed_assignments: HashMap::new(),
+            active_chords: HashMap::new(),
+            control_values: HashMap::new(),
         }
     }
 
-    pub fn reset_profile(&mut self) -> Result<(), String> {
+    /**
+     * Swaps in a reloaded config; the last calls are cleared since the assignments might have changed
+     */
+    pub async fn set_config(&mut self, config: Arc<ConfigLoader>) {
+        self.config = config;
+        self.control_calls.clear();
+        self.control_calls_sender.send_modify(|calls| calls.clear());
+        self.cancel_sequences().await;
+        self.reset_layer().await;
+    }
+
+    /**
+     * Receives a copy of the last calls whenever they change
+     */
+    pub fn subscribe_control_calls(&self) -> watch::Receiver<ProfileRunnerControlCalls> {
+        self.control_calls_sender.subscribe()
+    }
+
+    /**
+     * Publishes the last calls of a single control - this runs for every fired assignment so only the changed entry is copied
+     */
+    fn publish_control_calls(&self, control_name: &String) {
+        let control_calls = self.control_calls.get(control_name).cloned();
+        self.control_calls_sender.send_modify(|calls| match control_calls {
+            Some(control_calls) => {
+                calls.insert(control_name.clone(), control_calls);
+            }
+            None => {
+                calls.remove(control_name);
+            }
+        });
+    }
+
+    /**
+     * Stops the running sequences so they don't keep sending keys for the previous profile or config
+     */
+    async fn cancel_sequences(&self) {
+        self.output().cancel_sequences().await;
+    }
+
+    async fn reset_layer(&mut self) {
+        self.shift_control_states.clear();
+        self.active_layer = None;
+        self.reset_timed_assignments().await;
+    }
+
+    /**
+     * Stops the pending timers and deactivates the hold, multi_tap and chord assignments which are still active
+     */
+    async fn reset_timed_assignments(&mut self) {
+        let states: Vec<((String, usize), Arc<Mutex<ProfileRunnerTimedAssignmentState>>)> = self.timed_assignments.drain().collect();
+        for (key, state) in states.into_iter() {
+            self.deactivate_timed_assignment(&key, state).await;
+        }
+        let chords: Vec<((String, usize), Option<ProfileRunnerAssignmentCallAction>)> = self.active_chords.drain().collect();
+        for (key, action) in chords.into_iter() {
+            if let Some(action) = action {
+                self.dispatch_action(&key, &action).await;
+            }
+        }
+        self.control_values.clear();
+    }
+
+    async fn deactivate_timed_assignment(&self, key: &(String, usize), state: Arc<Mutex<ProfileRunnerTimedAssignmentState>>) {
+        let mut state = state.lock().await;
+        if let Some(timer) = state.timer.take() {
+            timer.cancel();
+        }
+        if state.active {
+            state.active = false;
+            if let Some(action) = state.deactivate_action.take() {
+                self.dispatch_action(key, &action).await;
+            }
+        }
+    }
+
+    pub async fn reset_profile(&mut self) -> Result<(), String> {
         self.profile_name = None;
+        self.cancel_sequences().await;
+        self.reset_layer().await;
         return Ok(());
     }
 
-    pub fn set_profile<T: AsRef<str>>(&mut self, name: T) -> Result<(), String> {
+    pub async fn set_profile<T: AsRef<str>>(&mut self, name: T) -> Result<(), String> {
         let name = name.as_ref();
         if &Some(String::from(name)) == &self.profile_name {
             return Ok(());
@@ -77,6 +411,8 @@ impl ProfileRunner {
         match profile {
             Some(_) => {
                 self.profile_name = Some(name.to_string());
+                self.cancel_sequences().await;
+                self.reset_layer().await;
                 Ok(())
             }
             None => Err(format!("Profile {} not found", name)),
@@ -87,9 +423,9 @@ impl ProfileRunner {
         self.preferred_control_mode = mode;
     }
 
-    pub fn get_current_profile(&self, controller_usb_id: Option<String>) -> Option<&ControllerProfile> {
+    pub fn get_current_profile(&self, controller_usb_id: &UsbId, device_alias: Option<&String>) -> Option<&ControllerProfile> {
         match self.profile_name {
-            Some(ref name) => self.config.find_controller_profile(name, controller_usb_id),
+            Some(ref name) => self.config.find_controller_profile_for_device(name, controller_usb_id, device_alias),
             None => None,
         }
     }
@@ -98,6 +434,327 @@ impl ProfileRunner {
         self.preferred_control_mode
     }
 
+    pub fn get_active_layer(&self) -> Option<&String> {
+        self.active_layer.as_ref()
+    }
+
+    /**
+     * Switches the active shift layer. Controls which resolve to a different definition on the new layer
+     * have their held keys released and their last calls forgotten since the assignment indexes no longer match.
+     */
+    async fn set_active_layer(&mut self, profile: &ControllerProfile, layer: Option<String>) {
+        if layer == self.active_layer {
+            return;
+        }
+
+        let previous_layer = self.active_layer.clone();
+        self.active_layer = layer;
+        println!("Active layer: {}", self.active_layer.as_ref().map(|l| l.as_str()).unwrap_or("none"));
+
+        let affected_controls: Vec<String> = self
+            .control_calls
+            .keys()
+            .chain(self.timed_assignments.keys().map(|(name, _)| name))
+            .chain(self.active_chords.keys().map(|(name, _)| name))
+            .filter(|name| {
+                let previous_control = profile.find_layer_control(name, previous_layer.as_ref()).map(|c| &c.layer);
+                let next_control = profile.find_layer_control(name, self.active_layer.as_ref()).map(|c| &c.layer);
+                previous_control != next_control
+            })
+            .cloned()
+            .collect();
+        for control_name in affected_controls.iter() {
+            self.release_control(control_name).await;
+        }
+    }
+
+    /**
+     * Releases everything the control still has active and forgets its last calls
+     */
+    async fn release_control(&mut self, control_name: &String) {
+        let calls = self.control_calls.remove(control_name).unwrap_or(Vec::new());
+        self.publish_control_calls(control_name);
+        for call in calls.into_iter().flatten() {
+            match call.action {
+                /* keys without a press time are held until released */
+                ProfileRunnerAssignmentCallAction::SequencerAction(action) if action.press_time.is_none() && action.release != Some(true) => {
+                    self.sequencer.add_action_for_control(control_name, ActionSequencerAction { release: Some(true), ..action }).await;
+                }
+                /* and so are pressed mouse buttons */
+                ProfileRunnerAssignmentCallAction::MouseAction(action) => {
+                    if let Some(release_action) = action.release_action() {
+                        self.sequencer.add_mouse_action(release_action).await;
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        let timed_assignment_keys: Vec<(String, usize)> = self.timed_assignments.keys().filter(|(name, _)| name == control_name).cloned().collect();
+        for key in timed_assignment_keys.iter() {
+            let state = self.timed_assignments.remove(key).unwrap();
+            self.deactivate_timed_assignment(key, state).await;
+        }
+
+        let chord_keys: Vec<(String, usize)> = self.active_chords.keys().filter(|(name, _)| name == control_name).cloned().collect();
+        for key in chord_keys.iter() {
+            if let Some(Some(action)) = self.active_chords.remove(key) {
+                self.dispatch_action(key, &action).await;
+            }
+        }
+    }
+
+    /**
+     * Names of the profile controls of a device - eg: "Button1", or "left_quadrant.Button1" in multi device profiles
+     */
+    pub fn device_control_names(&self, usb_id: &UsbId, device_alias: Option<&String>) -> Vec<String> {
+        match (self.get_current_profile(usb_id, device_alias), self.config.find_sdl_mapping(usb_id)) {
+            (Some(profile), Some(sdl_map)) => sdl_map.data.iter().filter_map(|control| profile.device_control_name(device_alias, &control.name)).collect(),
+            _ => Vec::new(),
+        }
+    }
+
+    /**
+     * Releases the controls of an unplugged device - the keys held by the other controllers stay down
+     */
+    pub async fn release_device(&mut self, usb_id: &UsbId, device_alias: Option<&String>) {
+        let control_names = self.device_control_names(usb_id, device_alias);
+        self.running_sequences.lock().await.retain(|(control_name, _), cancel_token| {
+            if control_names.contains(control_name) {
+                cancel_token.cancel();
+                return false;
+            }
+            true
+        });
+        for control_name in control_names.iter() {
+            self.release_control(control_name).await;
+            self.control_values.remove(control_name);
+        }
+
+        /* a shift control of the device no longer holds its layer */
+        let config = Arc::clone(&self.config);
+        let profile = match self.profile_name.as_ref().and_then(|name| config.find_controller_profile_for_device(name, usb_id, device_alias)) {
+            Some(profile) => profile,
+            None => return,
+        };
+        let shift_control_count = self.shift_control_states.len();
+        self.shift_control_states.retain(|control_name, _| !control_names.contains(control_name));
+        if self.shift_control_states.len() != shift_control_count {
+            let active_layer = profile.resolve_active_layer(&self.shift_control_states);
+            self.set_active_layer(profile, active_layer).await;
+        }
+    }
+
+    fn output(&self) -> ProfileRunnerOutput {
+        ProfileRunnerOutput {
+            sequencer: Arc::clone(&self.sequencer),
+            direct_control_sender: Arc::clone(&self.direct_control_sender),
+            running_sequences: Arc::clone(&self.running_sequences),
+        }
+    }
+
+    async fn dispatch_action(&self, assignment: &(String, usize), action: &ProfileRunnerAssignmentCallAction) {
+        self.output().send(assignment, action).await;
+    }
+
+    async fn dispatch_action_for_control(&self, assignment: &(String, usize), action: &ProfileRunnerAssignmentCallAction) {
+        self.output().send_for_control(assignment, action).await;
+    }
+
+    fn timed_assignment_state(&mut self, control_name: &String, assignment_index: usize) -> Arc<Mutex<ProfileRunnerTimedAssignmentState>> {
+        let state = self
+            .timed_assignments
+            .entry((control_name.clone(), assignment_index))
+            .or_insert_with(|| Arc::new(Mutex::new(Default::default())));
+        Arc::clone(state)
+    }
+
+    /**
+     * Fires the activate action once the control is held for the configured duration; the deactivate action is fired on release
+     */
+    async fn run_hold_assignment(
+        &mut self,
+        control_name: &String,
+        assignment_index: usize,
+        control_state: &ControllerManagerControllerControlState,
+        assignment: &ControllerProfileControlHoldAssignment,
+    ) {
+        let is_pressed = control_state.value >= assignment.threshold;
+        let state_arc = self.timed_assignment_state(control_name, assignment_index);
+        let mut state = state_arc.lock().await;
+        if is_pressed == state.pressed {
+            return;
+        }
+        state.pressed = is_pressed;
+        if let Some(timer) = state.timer.take() {
+            timer.cancel();
+        }
+
+        let key = (control_name.clone(), assignment_index);
+        if !is_pressed {
+            if state.active {
+                state.active = false;
+                if let Some(action) = state.deactivate_action.take() {
+                    self.dispatch_action(&key, &action).await;
+                }
+            }
+            return;
+        }
+
+        let timer = self.cancel_token.child_token();
+        state.timer = Some(timer.clone());
+        let timed_state = Arc::clone(&state_arc);
+        let output = self.output();
+        let activate_action = ProfileRunnerAssignmentCallAction::from_assignment_action(&assignment.action_activate, false);
+        let deactivate_action = ProfileRunnerAssignmentCallAction::from_deactivate_action(&assignment.action_activate, &assignment.action_deactivate);
+        let duration = Duration::from_millis((assignment.duration * 1000.0).abs() as u64);
+        tokio::task::spawn(async move {
+            tokio::select! {
+                _ = timer.cancelled() => {},
+                _ = sleep(duration) => {
+                    let mut state = timed_state.lock().await;
+                    /* the control might have been released while waiting for the lock */
+                    if timer.is_cancelled() {
+                        return;
+                    }
+                    state.timer = None;
+                    state.active = true;
+                    state.deactivate_action = deactivate_action;
+                    if let Some(action) = &activate_action {
+                        output.send(&key, action).await;
+                    }
+                }
+            }
+        });
+    }
+
+    /**
+     * Counts the presses until no further press follows within the window - reaching the highest configured tap count activates right away.
+     * The deactivate action is fired on release, or right after activation if the control was already released.
+     */
+    async fn run_multi_tap_assignment(
+        &mut self,
+        control_name: &String,
+        assignment_index: usize,
+        control_state: &ControllerManagerControllerControlState,
+        assignment: &ControllerProfileControlMultiTapAssignment,
+    ) {
+        let is_pressed = control_state.value >= assignment.threshold;
+        let state_arc = self.timed_assignment_state(control_name, assignment_index);
+        let mut state = state_arc.lock().await;
+        if is_pressed == state.pressed {
+            return;
+        }
+        state.pressed = is_pressed;
+        if let Some(timer) = state.timer.take() {
+            timer.cancel();
+        }
+
+        let key = (control_name.clone(), assignment_index);
+        if is_pressed {
+            state.taps += 1;
+            if state.taps >= assignment.max_taps() {
+                let taps = state.taps;
+                state.taps = 0;
+                if let Some(actions) = assignment.actions_for_taps(taps) {
+                    state.active = true;
+                    state.deactivate_action = ProfileRunnerAssignmentCallAction::from_deactivate_action(&actions.action_activate, &actions.action_deactivate);
+                    if let Some(action) = ProfileRunnerAssignmentCallAction::from_assignment_action(&actions.action_activate, false) {
+                        self.dispatch_action(&key, &action).await;
+                    }
+                }
+                return;
+            }
+        } else if state.active {
+            state.active = false;
+            if let Some(action) = state.deactivate_action.take() {
+                self.dispatch_action(&key, &action).await;
+            }
+            return;
+        }
+
+        if state.taps == 0 {
+            return;
+        }
+
+        let timer = self.cancel_token.child_token();
+        state.timer = Some(timer.clone());
+        let timed_state = Arc::clone(&state_arc);
+        let output = self.output();
+        let assignment = assignment.clone();
+        let window = Duration::from_millis((assignment.window() * 1000.0).abs() as u64);
+        tokio::task::spawn(async move {
+            tokio::select! {
+                _ = timer.cancelled() => {},
+                _ = sleep(window) => {
+                    let mut state = timed_state.lock().await;
+                    if timer.is_cancelled() {
+                        return;
+                    }
+                    let taps = state.taps;
+                    state.taps = 0;
+                    state.timer = None;
+                    if let Some(actions) = assignment.actions_for_taps(taps) {
+                        if let Some(action) = ProfileRunnerAssignmentCallAction::from_assignment_action(&actions.action_activate, false) {
+                            output.send(&key, &action).await;
+                        }
+                        let deactivate_action = ProfileRunnerAssignmentCallAction::from_deactivate_action(&actions.action_activate, &actions.action_deactivate);
+                        if state.pressed {
+                            state.active = true;
+                            state.deactivate_action = deactivate_action;
+                        } else if let Some(action) = &deactivate_action {
+                            output.send(&key, action).await;
+                        }
+                    }
+                }
+            }
+        });
+    }
+
+    /**
+     * Evaluates the chords which involve the changed control
+     */
+    async fn run_chord_assignments(&mut self, profile: &ControllerProfile, control_name: &String) {
+        for control in profile.controls.iter() {
+            /* only consider the definitions of the active layer */
+            match profile.find_layer_control(&control.name, self.active_layer.as_ref()) {
+                Some(layer_control) if std::ptr::eq(layer_control, control) => {}
+                _ => continue,
+            }
+
+            for (assignment_index, assignment) in control.get_assignments(self.preferred_control_mode).iter().enumerate() {
+                let ControllerProfileControlAssignment::Chord(chord) = assignment else {
+                    continue;
+                };
+                if &control.name != control_name && !chord.controls.contains(control_name) {
+                    continue;
+                }
+
+                let is_active = std::iter::once(&control.name)
+                    .chain(chord.controls.iter())
+                    .all(|name| *self.control_values.get(name).unwrap_or(&0.0) >= chord.threshold);
+                let chord_key = (control.name.clone(), assignment_index);
+                match (is_active, self.active_chords.contains_key(&chord_key)) {
+                    (true, false) => {
+                        self.active_chords.insert(
+                            chord_key.clone(),
+                            ProfileRunnerAssignmentCallAction::from_deactivate_action(&chord.action_activate, &chord.action_deactivate),
+                        );
+                        if let Some(action) = ProfileRunnerAssignmentCallAction::from_assignment_action(&chord.action_activate, false) {
+                            self.dispatch_action(&chord_key, &action).await;
+                        }
+                    }
+                    (false, true) => {
+                        if let Some(Some(action)) = self.active_chords.remove(&chord_key) {
+                            self.dispatch_action(&chord_key, &action).await;
+                        }
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+
     pub async fn call_assignment_action_for_control<T: AsRef<str>>(
         &mut self,
         control_name: T,
@@ -106,7 +763,8 @@ impl ProfileRunner {
         assignment: &ControllerProfileControlAssignment,
         action: Option<ProfileRunnerAssignmentCallAction>,
     ) {
-        let entry = self.control_calls.entry(String::from(control_name.as_ref())).or_insert_with(|| Vec::new());
+        let entry_control_name = String::from(control_name.as_ref());
+        let entry = self.control_calls.entry(entry_control_name.clone()).or_insert_with(|| Vec::new());
         while entry.len() <= assignment_index {
             entry.push(None);
         }
@@ -124,22 +782,12 @@ impl ProfileRunner {
                 /* should always be available - None action should only be set as none for deactivation calls */
                 None => entry[assignment_index].as_ref().unwrap().action.clone(),
             },
+            called_at: Instant::now(),
         });
+        self.publish_control_calls(&entry_control_name);
 
-        match action.as_ref() {
-            Some(ProfileRunnerAssignmentCallAction::SequencerAction(action)) => {
-                self.sequencer.add_action(action.clone()).await;
-            }
-            Some(ProfileRunnerAssignmentCallAction::DirectControlAction(action)) => {
-                let direct_control_sender = self.direct_control_sender.lock().await;
-                match direct_control_sender.send(action.clone()) {
-                    Ok(_) => {}
-                    Err(e) => {
-                        println!("Error sending direct control command ({:?}): {}", action, e);
-                    }
-                }
-            }
-            _ => {}
+        if let Some(action) = action.as_ref() {
+            self.dispatch_action_for_control(&(entry_control_name, assignment_index), action).await;
         }
     }
 
@@ -149,15 +797,30 @@ impl ProfileRunner {
         }
 
         let config_loader = Arc::clone(&self.config);
-        let controller_config = config_loader.find_controller_profile(self.profile_name.as_ref().unwrap(), Some(event.usb_id));
+        let controller_config = config_loader.find_controller_profile_for_device(self.profile_name.as_ref().unwrap(), &event.usb_id, event.device_alias.as_ref());
 
         if controller_config.is_none() {
             return;
         }
 
-        let control_name = event.control_name.clone();
+        /* profiles combining multiple devices address the controls by device - {alias}.{control_name} */
+        let control_name = match controller_config.unwrap().device_control_name(event.device_alias.as_ref(), &event.control_name) {
+            Some(control_name) => control_name,
+            None => return,
+        };
         let control_state = event.control_state.clone();
-        let control_definition = controller_config.unwrap().find_control(control_name.clone());
+
+        /* shift controls only switch layers */
+        if let Some(shift_control) = controller_config.unwrap().find_shift_control(&control_name) {
+            let is_held = control_state.value >= shift_control.threshold.unwrap_or(0.5);
+            self.shift_control_states.insert(shift_control.control.clone(), is_held);
+            let active_layer = controller_config.unwrap().resolve_active_layer(&self.shift_control_states);
+            self.set_active_layer(controller_config.unwrap(), active_layer).await;
+            return;
+        }
+
+        self.control_values.insert(control_name.clone(), control_state.value);
+        let control_definition = controller_config.unwrap().find_layer_control(control_name.clone(), self.active_layer.as_ref());
 
         match control_definition {
             Some(control) => {
@@ -185,23 +848,7 @@ impl ProfileRunner {
                                         assignment_index,
                                         &control_state,
                                         &control_assignment,
-                                        match &assignment.action_activate {
-                                            ControllerProfileControlAssignmentAction::Keys(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                    keys: action.keys.clone(),
-                                                    press_time: action.press_time,
-                                                    wait_time: action.wait_time,
-                                                    release: Some(false),
-                                                }))
-                                            }
-                                            ControllerProfileControlAssignmentAction::DirectControl(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::DirectControlAction(super::direct_controller::DirectControlCommand {
-                                                    controls: action.controls.clone(),
-                                                    input_value: action.value,
-                                                    hold: action.hold,
-                                                }))
-                                            }
-                                        },
+                                        ProfileRunnerAssignmentCallAction::from_assignment_action(&assignment.action_activate, false),
                                     )
                                     .await;
                                 }
@@ -212,37 +859,7 @@ impl ProfileRunner {
                                     assignment_index,
                                     &control_state,
                                     &control_assignment,
-                                    match &assignment.action_deactivate {
-                                        Some(action) => match action {
-                                            ControllerProfileControlAssignmentAction::Keys(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                    keys: action.keys.clone(),
-                                                    press_time: action.press_time,
-                                                    wait_time: action.wait_time,
-                                                    release: Some(false),
-                                                }))
-                                            }
-                                            ControllerProfileControlAssignmentAction::DirectControl(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::DirectControlAction(super::direct_controller::DirectControlCommand {
-                                                    controls: action.controls.clone(),
-                                                    input_value: action.value,
-                                                    hold: action.hold,
-                                                }))
-                                            }
-                                        },
-                                        None => match &assignment.action_activate {
-                                            ControllerProfileControlAssignmentAction::Keys(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                    keys: action.keys.clone(),
-                                                    press_time: action.press_time,
-                                                    wait_time: action.wait_time,
-                                                    release: Some(true),
-                                                }))
-                                            }
-                                            /* can't release keys here so do nothing */
-                                            ControllerProfileControlAssignmentAction::DirectControl(_) => None,
-                                        },
-                                    },
+                                    ProfileRunnerAssignmentCallAction::from_deactivate_action(&assignment.action_activate, &assignment.action_deactivate),
                                 )
                                 .await;
                             }
@@ -281,23 +898,7 @@ impl ProfileRunner {
                                         assignment_index,
                                         &control_state,
                                         &control_assignment,
-                                        match &threshold.action_activate {
-                                            ControllerProfileControlAssignmentAction::Keys(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                    keys: action.keys.clone(),
-                                                    press_time: action.press_time,
-                                                    wait_time: action.wait_time,
-                                                    release: Some(false),
-                                                }))
-                                            }
-                                            ControllerProfileControlAssignmentAction::DirectControl(action) => {
-                                                Some(ProfileRunnerAssignmentCallAction::DirectControlAction(super::direct_controller::DirectControlCommand {
-                                                    controls: action.controls.clone(),
-                                                    input_value: action.value,
-                                                    hold: action.hold,
-                                                }))
-                                            }
-                                        },
+                                        ProfileRunnerAssignmentCallAction::from_assignment_action(&threshold.action_activate, false),
                                     )
                                     .await;
                                 }
@@ -311,36 +912,7 @@ impl ProfileRunner {
                                         assignment_index,
                                         &control_state,
                                         &control_assignment,
-                                        match &threshold.action_deactivate {
-                                            Some(action) => match &action {
-                                                ControllerProfileControlAssignmentAction::Keys(action) => {
-                                                    Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                        keys: action.keys.clone(),
-                                                        press_time: action.press_time,
-                                                        wait_time: action.wait_time,
-                                                        release: Some(false),
-                                                    }))
-                                                }
-                                                ControllerProfileControlAssignmentAction::DirectControl(action) => {
-                                                    Some(ProfileRunnerAssignmentCallAction::DirectControlAction(super::direct_controller::DirectControlCommand {
-                                                        controls: action.controls.clone(),
-                                                        input_value: action.value,
-                                                        hold: action.hold,
-                                                    }))
-                                                }
-                                            },
-                                            None => match &threshold.action_activate {
-                                                ControllerProfileControlAssignmentAction::Keys(action) => {
-                                                    Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                        keys: action.keys.clone(),
-                                                        press_time: action.press_time,
-                                                        wait_time: action.wait_time,
-                                                        release: Some(true),
-                                                    }))
-                                                }
-                                                ControllerProfileControlAssignmentAction::DirectControl(_) => None,
-                                            },
-                                        },
+                                        ProfileRunnerAssignmentCallAction::from_deactivate_action(&threshold.action_activate, &threshold.action_deactivate),
                                     )
                                     .await;
                                 }
@@ -364,23 +936,7 @@ impl ProfileRunner {
                                     assignment_index,
                                     &control_state,
                                     &control_assignment,
-                                    match action_to_call {
-                                        ControllerProfileControlAssignmentAction::Keys(action) => {
-                                            Some(ProfileRunnerAssignmentCallAction::SequencerAction(super::action_sequencer::ActionSequencerAction {
-                                                keys: action.keys.clone(),
-                                                press_time: action.press_time,
-                                                wait_time: action.wait_time,
-                                                release: Some(false),
-                                            }))
-                                        }
-                                        ControllerProfileControlAssignmentAction::DirectControl(action) => {
-                                            Some(ProfileRunnerAssignmentCallAction::DirectControlAction(super::direct_controller::DirectControlCommand {
-                                                controls: action.controls.clone(),
-                                                input_value: action.value,
-                                                hold: action.hold,
-                                            }))
-                                        }
-                                    },
+                                    ProfileRunnerAssignmentCallAction::from_assignment_action(action_to_call, false),
                                 )
                                 .await;
                             } else if last_called_assignment.is_some() && last_called_assignment.unwrap().control_state.value >= assignment.threshold {
@@ -398,9 +954,12 @@ impl ProfileRunner {
                                                 press_time: action.press_time,
                                                 wait_time: action.wait_time,
                                                 release: Some(true),
+                                                priority: action.priority,
                                             }))
                                         }
                                         ProfileRunnerAssignmentCallAction::DirectControlAction(_) => None,
+                                        ProfileRunnerAssignmentCallAction::SequenceAction(_) => None,
+                                        ProfileRunnerAssignmentCallAction::MouseAction(action) => action.release_action().map(ProfileRunnerAssignmentCallAction::MouseAction),
                                     },
                                 )
                                 .await;
@@ -421,11 +980,43 @@ impl ProfileRunner {
                             )
                             .await;
                         }
+                        ControllerProfileControlAssignment::Hold(assignment) => {
+                            self.run_hold_assignment(&control_name, assignment_index, &control_state, assignment).await;
+                        }
+                        ControllerProfileControlAssignment::MultiTap(assignment) => {
+                            self.run_multi_tap_assignment(&control_name, assignment_index, &control_state, assignment).await;
+                        }
+                        ControllerProfileControlAssignment::MouseScroll(assignment) => {
+                            /* partial notches add up - the last call holds the value the last scroll was sent at */
+                            let scrolled_from = match last_called_assignment {
+                                Some(call) => call.control_state.value,
+                                None => control_state.initial_value,
+                            };
+                            let amount = assignment.scroll_amount(scrolled_from, control_state.value);
+                            if amount != 0 {
+                                self.call_assignment_action_for_control(
+                                    control_name.clone(),
+                                    assignment_index,
+                                    &control_state,
+                                    &control_assignment,
+                                    Some(ProfileRunnerAssignmentCallAction::MouseAction(ActionSequencerMouseAction::Scroll {
+                                        amount,
+                                        axis: match assignment.horizontal {
+                                            Some(true) => enigo::Axis::Horizontal,
+                                            _ => enigo::Axis::Vertical,
+                                        },
+                                    })),
+                                )
+                                .await;
+                            }
+                        }
                         _ => {}
                     }
                 }
             }
             None => {}
         }
+
+        self.run_chord_assignments(controller_config.unwrap(), &control_name).await;
     }
 }
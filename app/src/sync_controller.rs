@@ -1,24 +1,32 @@
-use std::{collections::HashMap, sync::Arc};
+use std::{collections::HashMap, net::SocketAddr, sync::Arc};
 
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
 use serde::{Deserialize, Serialize};
 use tokio::{
     net::TcpListener,
     sync::broadcast::{self, Receiver, Sender},
-    sync::Mutex,
+    sync::{watch, Mutex},
     task::JoinHandle,
 };
 use tokio_util::sync::CancellationToken;
+use tungstenite::protocol::Message;
 
 use crate::{
     action_sequencer::{ActionSequencer, ActionSequencerAction},
-    config_defs::controller_profile::{
-        ControllerProfileControlAssignment,
-        ControllerProfileDirectControAssignmentSyncMode,
+    config_defs::{
+        controller_profile::{
+            ControllerProfileControlAssignment, ControllerProfileDirectControAssignmentSyncMode,
+            PreferredControlMode,
+        },
+        usb_id::UsbId,
     },
     config_loader::ConfigLoader,
+    connection_status::ConnectionStatus,
     controller_manager::ControllerManagerChangeEvent,
     profile_runner::ProfileRunner,
+    wire_protocol::{
+        WireEnvelope, WireMessage, CAPABILITY_CURRENT_VEHICLE, CAPABILITY_SYNC_CONTROL,
+    },
 };
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -32,7 +40,7 @@ pub struct SyncControllerControlState {
 }
 
 pub struct SyncController {
-    config: Arc<ConfigLoader>,
+    config: Arc<Mutex<Arc<ConfigLoader>>>,
     sequencer: Arc<ActionSequencer>,
     profile_runner: Arc<Mutex<ProfileRunner>>,
     server: Arc<TcpListener>,
@@ -42,6 +50,9 @@ pub struct SyncController {
         Sender<SyncControllerControlState>,
         Receiver<SyncControllerControlState>,
     ),
+    /* vehicle class reported by the game mod */
+    current_vehicle_channel: Sender<String>,
+    status: Arc<watch::Sender<ConnectionStatus>>,
 }
 
 impl SyncController {
@@ -49,18 +60,55 @@ impl SyncController {
         config: Arc<ConfigLoader>,
         sequencer: Arc<ActionSequencer>,
         profile_runner: Arc<Mutex<ProfileRunner>>,
-    ) -> Self {
-        let direct_control_server = TcpListener::bind("0.0.0.0:63242").await.unwrap();
+        addr: SocketAddr,
+    ) -> Result<Self, String> {
+        let sync_control_server = TcpListener::bind(addr).await.map_err(|e| {
+            format!(
+                "Could not bind the sync control server to {}: {} - is the port already in use? It can be changed with sync_control_port in settings.json or --sync-control-port",
+                addr, e
+            )
+        })?;
+        println!("[SC] Listening on {}", addr);
 
-        Self {
-            config,
+        Ok(Self {
+            config: Arc::new(Mutex::new(config)),
             sequencer,
             profile_runner,
-            server: Arc::new(direct_control_server),
+            server: Arc::new(sync_control_server),
             controls_state_profile: Arc::new(Mutex::new(None)),
             controls_state: Arc::new(Mutex::new(HashMap::new())),
             control_state_changed_channel: broadcast::channel::<SyncControllerControlState>(10000),
+            current_vehicle_channel: broadcast::channel::<String>(100).0,
+            status: Arc::new(watch::channel(ConnectionStatus::default()).0),
+        })
+    }
+
+    pub fn subscribe_status(&self) -> watch::Receiver<ConnectionStatus> {
+        self.status.subscribe()
+    }
+
+    /**
+     * Parses the comma separated messages of older mods
+     * current_vehicle,{vehicle_class} or sync_control,{identifier},{value}
+     */
+    fn parse_legacy_message(text: &str) -> Option<WireMessage> {
+        if let Some(("current_vehicle", vehicle_class)) = text.split_once(",") {
+            return Some(WireMessage::CurrentVehicle {
+                vehicle_class: vehicle_class.to_string(),
+            });
+        }
+        let parts = text.split(",").collect::<Vec<&str>>();
+        if parts[0] != "sync_control" || parts.len() != 3 {
+            return None;
         }
+        Some(WireMessage::SyncControl {
+            identifier: parts[1].to_string(),
+            value: parts[2].parse::<f32>().ok()?,
+        })
+    }
+
+    pub fn subscribe_current_vehicle(&self) -> Receiver<String> {
+        self.current_vehicle_channel.subscribe()
     }
 
     pub async fn reset_control_state(&self) {
@@ -68,6 +116,64 @@ impl SyncController {
         controls_state_lock.clear();
     }
 
+    /**
+     * Stops the sync controls of an unplugged device - it won't send the target values which would stop them anymore
+     */
+    pub async fn release_device(&self, usb_id: &UsbId, device_alias: Option<&String>) {
+        let identifiers: Vec<String> = {
+            let profile_runner_lock = self.profile_runner.lock().await;
+            let control_names = profile_runner_lock.device_control_names(usb_id, device_alias);
+            match profile_runner_lock.get_current_profile(usb_id, device_alias) {
+                Some(profile) => profile
+                    .controls
+                    .iter()
+                    .filter(|control| control_names.contains(&control.name))
+                    .flat_map(|control| control.get_assignments(PreferredControlMode::SyncControl))
+                    .filter_map(|assignment| match assignment {
+                        ControllerProfileControlAssignment::SyncControl(assignment) => {
+                            Some(assignment.identifier)
+                        }
+                        _ => None,
+                    })
+                    .collect(),
+                None => Vec::new(),
+            }
+        };
+
+        let mut controls_state_lock = self.controls_state.lock().await;
+        for identifier in identifiers.iter() {
+            let control_state = match controls_state_lock.remove(identifier) {
+                Some(control_state) => control_state,
+                None => continue,
+            };
+            let held_action = match (control_state.moving, control_state.target_profile) {
+                (1, Some(target_profile)) => target_profile.action_increase,
+                (-1, Some(target_profile)) => target_profile.action_decrease,
+                _ => continue,
+            };
+            self.sequencer
+                .add_action_for_control(
+                    identifier,
+                    ActionSequencerAction {
+                        keys: held_action.keys.clone(),
+                        press_time: held_action.press_time,
+                        wait_time: held_action.wait_time,
+                        release: Some(true),
+                        priority: held_action.priority.unwrap_or_default(),
+                    },
+                )
+                .await;
+        }
+    }
+
+    /**
+     * Swaps in a reloaded config and clears the control state since the sync control assignments might have changed
+     */
+    pub async fn set_config(&self, config: Arc<ConfigLoader>) {
+        *self.config.lock().await = config;
+        self.reset_control_state().await;
+    }
+
     pub fn start(
         &self,
         cancel_token: CancellationToken,
@@ -95,8 +201,11 @@ impl SyncController {
                     }
 
                     let mut control_state_lock = controls_state.lock().await;
-                    /* unwrapping since it should always exist */
-                    let mut_control_state = control_state_lock.get_mut(state.identifier.as_str()).unwrap();
+                    let mut_control_state = match control_state_lock.get_mut(state.identifier.as_str()) {
+                      Some(control_state) if control_state.target_profile.is_some() => control_state,
+                      /* reset or released since the change was sent */
+                      _ => continue,
+                    };
                     let MARGIN_OF_ERROR = 0.005;
                     let should_stop_moving =
                       /* was increasing and has now exceeded value */
@@ -113,11 +222,12 @@ impl SyncController {
                         1 => mut_control_state.target_profile.clone().unwrap().action_increase,
                         _ => mut_control_state.target_profile.clone().unwrap().action_decrease,
                       };
-                      sequencer.add_action(ActionSequencerAction {
+                      sequencer.add_action_for_control(&state.identifier, ActionSequencerAction {
                         keys: action_to_release.keys.clone(),
                         press_time: action_to_release.press_time,
                         wait_time: action_to_release.wait_time,
                         release: Some(true),
+                        priority: action_to_release.priority.unwrap_or_default(),
                       }).await;
                       /* set moving param to 0 */
                       mut_control_state.moving = 0;
@@ -126,11 +236,12 @@ impl SyncController {
                     /* should start increasing if not already */
                     if should_start_increasing {
                       let action = mut_control_state.target_profile.clone().unwrap().action_increase;
-                      sequencer.add_action(ActionSequencerAction {
+                      sequencer.add_action_for_control(&state.identifier, ActionSequencerAction {
                         keys: action.keys.clone(),
                         press_time: action.press_time,
                         wait_time: action.wait_time,
                         release: Some(false),
+                        priority: action.priority.unwrap_or_default(),
                       }).await;
                       /* set moving param to 0 */
                       mut_control_state.moving = 1;
@@ -139,11 +250,12 @@ impl SyncController {
                     /* should start decreasing if not already */
                     if should_start_decreasing {
                       let action = mut_control_state.target_profile.clone().unwrap().action_decrease;
-                      sequencer.add_action(ActionSequencerAction {
+                      sequencer.add_action_for_control(&state.identifier, ActionSequencerAction {
                         keys: action.keys.clone(),
                         press_time: action.press_time,
                         wait_time: action.wait_time,
                         release: Some(false),
+                        priority: action.priority.unwrap_or_default(),
                       }).await;
                       /* set moving param to 0 */
                       mut_control_state.moving = -1;
@@ -167,8 +279,9 @@ impl SyncController {
                   },
                   Ok(event) = controller_receiver.recv() => {
                     let profile_runner_lock = profile_runner.lock().await;
-                    let profile = profile_runner_lock.get_current_profile(Some(event.usb_id));
+                    let profile = profile_runner_lock.get_current_profile(&event.usb_id, event.device_alias.as_ref());
                     let preferred_control_mode = profile_runner_lock.get_preferred_control_mode();
+                    let active_layer = profile_runner_lock.get_active_layer().cloned();
                     match profile {
                       Some(profile) => {
                         let controls_state_profile_lock = controls_state_profile.lock().await;
@@ -179,7 +292,10 @@ impl SyncController {
                           drop(controls_state_lock);
                         }
 
-                        let control = profile.find_control(event.control_name);
+                        let control = match profile.device_control_name(event.device_alias.as_ref(), &event.control_name) {
+                          Some(control_name) => profile.find_layer_control(control_name, active_layer.as_ref()),
+                          None => None,
+                        };
                         if let Some(control_config) = control {
                           let assignments = control_config.get_assignments(preferred_control_mode);
                           for assignment in assignments.iter() {
@@ -222,6 +338,8 @@ impl SyncController {
         let accept_incoming_clients_server = Arc::clone(&server);
         let accept_incoming_clients_cancel_token = cancel_token.clone();
         let control_state_changed_channel_sender = self.control_state_changed_channel.0.clone();
+        let current_vehicle_channel_sender = self.current_vehicle_channel.clone();
+        let status = Arc::clone(&self.status);
         tokio::task::spawn(async move {
             println!("[SC] Server started");
             let cancel_token_clone: CancellationToken =
@@ -237,6 +355,8 @@ impl SyncController {
                     let controls_state = Arc::clone(&controls_state);
                     let socket_cancel_token = cancel_token_clone.clone();
                     let control_state_changed_channel_sender = control_state_changed_channel_sender.clone();
+                    let current_vehicle_channel_sender = current_vehicle_channel_sender.clone();
+                    let status = Arc::clone(&status);
 
                     tokio::task::spawn(async move {
                       let ws_stream = match tokio_tungstenite::accept_async(tcp_stream).await {
@@ -246,41 +366,69 @@ impl SyncController {
                           return;
                         }
                       };
-                      let (_, mut read) = ws_stream.split();
+                      let (mut write, mut read) = ws_stream.split();
+                      status.send_modify(|status| status.client_connected());
 
                       loop {
                         tokio::select! {
                           _ = socket_cancel_token.cancelled() => {
                             break;
                           },
-                          Some(next) = read.next() => {
+                          next = read.next() => {
                             match next {
-                              Ok(message) => match message {
+                              Some(Ok(message)) => match message {
                                 tungstenite::Message::Text(text) => {
                                   println!("[SC] Received message: {}", text);
-                                  /* message should follow format sync_control,{identifier},{value} */
-                                  let parts = text.split(",").collect::<Vec<&str>>();
-                                  /* skip message if not sync_control message */
-                                  if parts[0] != "sync_control" || parts.len() != 3 {
-                                    continue;
-                                  }
+                                  status.send_modify(|status| status.message_received());
+                                  let (version, message) = match WireEnvelope::parse(&text) {
+                                    Some(Ok(envelope)) => (envelope.version, envelope.message),
+                                    Some(Err(e)) => {
+                                      eprintln!("[SC] {}", e);
+                                      continue;
+                                    },
+                                    /* older mods only send the comma separated format */
+                                    None => match SyncController::parse_legacy_message(&text) {
+                                      Some(message) => (0, message),
+                                      None => continue,
+                                    },
+                                  };
+
+                                  let (identifier, value) = match message {
+                                    WireMessage::Hello { capabilities } => {
+                                      println!("[SC] Client speaks protocol version {} with capabilities {:?}", version, capabilities);
+                                      let hello = WireEnvelope::hello(&[CAPABILITY_SYNC_CONTROL, CAPABILITY_CURRENT_VEHICLE]);
+                                      if let Err(e) = write.send(Message::text(hello.to_text())).await {
+                                        eprintln!("[SC] Error sending hello: {:?}", e);
+                                        status.send_modify(|status| status.send_failed(&e));
+                                      }
+                                      continue;
+                                    },
+                                    /* sent whenever the driven vehicle changes */
+                                    WireMessage::CurrentVehicle { vehicle_class } => {
+                                      /* nobody might be listening - ignore */
+                                      let _ = current_vehicle_channel_sender.send(vehicle_class);
+                                      continue;
+                                    },
+                                    WireMessage::SyncControl { identifier, value } => (identifier, value),
+                                    WireMessage::DirectControl(_) => continue,
+                                  };
 
                                   let mut controls_state_lock = controls_state.lock().await;
-                                  let updated_state = match controls_state_lock.get_mut(parts[1]) {
+                                  let updated_state = match controls_state_lock.get_mut(identifier.as_str()) {
                                     Some(control_state) => {
-                                      control_state.current_value = parts[2].parse::<f32>().unwrap();
+                                      control_state.current_value = value;
                                       control_state
                                     },
                                     None => {
                                     let new_control_state = SyncControllerControlState {
-                                      identifier: parts[1].to_string(),
-                                      current_value: parts[2].parse::<f32>().unwrap(),
-                                      target_value: parts[2].parse::<f32>().unwrap(),
+                                      identifier: identifier.clone(),
+                                      current_value: value,
+                                      target_value: value,
                                       moving: 0,
                                       target_profile: None,
                                     };
-                                    controls_state_lock.insert(String::from(parts[1]), new_control_state);
-                                    controls_state_lock.get_mut(parts[1]).unwrap()
+                                    controls_state_lock.insert(identifier.clone(), new_control_state);
+                                    controls_state_lock.get_mut(identifier.as_str()).unwrap()
                                     },
                                   };
                                   control_state_changed_channel_sender.send(updated_state.clone()).unwrap();
@@ -288,14 +436,19 @@ impl SyncController {
                                 tungstenite::Message::Close(_) => { break },
                                 _ => {},
                               },
-                              Err(e) => {
+                              Some(Err(e)) => {
                                 eprintln!("Client error: {}", e);
                                 break;
                               }
+                              /* the stream ended without a close frame */
+                              None => break,
                             }
                           },
                         }
                       }
+
+                      println!("[SC] Client disconnected");
+                      status.send_modify(|status| status.client_disconnected());
                     });
                   }
                 }
@@ -0,0 +1,136 @@
+use serde::{Deserialize, Serialize};
+
+use crate::direct_controller::DirectControlCommand;
+
+/* bump when the messages change in an incompatible way - the mod has the same definitions in its wire_protocol.rs */
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const CAPABILITY_DIRECT_CONTROL: &str = "direct_control";
+pub const CAPABILITY_SYNC_CONTROL: &str = "sync_control";
+pub const CAPABILITY_CURRENT_VEHICLE: &str = "current_vehicle";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WireMessage {
+    /* sent by both sides after connecting */
+    Hello { capabilities: Vec<String> },
+    DirectControl(DirectControlCommand),
+    SyncControl { identifier: String, value: f32 },
+    CurrentVehicle { vehicle_class: String },
+}
+
+/**
+ * JSON message exchanged with the game mod
+ * eg: {"version":1,"type":"direct_control","controls":"Throttle1","input_value":0.5,"hold":null}
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireEnvelope {
+    pub version: u32,
+    #[serde(flatten)]
+    pub message: WireMessage,
+}
+
+impl WireEnvelope {
+    pub fn new(message: WireMessage) -> WireEnvelope {
+        WireEnvelope { version: PROTOCOL_VERSION, message }
+    }
+
+    pub fn hello(capabilities: &[&str]) -> WireEnvelope {
+        WireEnvelope::new(WireMessage::Hello {
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /**
+     * Returns None for messages in the legacy comma separated format
+     */
+    pub fn parse(text: &str) -> Option<Result<WireEnvelope, String>> {
+        if !text.trim_start().starts_with('{') {
+            return None;
+        }
+        let envelope = match serde_json::from_str::<WireEnvelope>(text) {
+            Ok(envelope) => envelope,
+            Err(e) => return Some(Err(format!("Invalid message {}: {}", text, e))),
+        };
+        /* the hello is understood by every version - everything else of a newer version might have changed */
+        match envelope.message {
+            WireMessage::Hello { .. } => {}
+            _ if envelope.version > PROTOCOL_VERSION => return Some(Err(format!("Unsupported protocol version {} (up to {} is supported): {}", envelope.version, PROTOCOL_VERSION, text))),
+            _ => {}
+        }
+        Some(Ok(envelope))
+    }
+}
+
+/**
+ * Protocol state of a connection - the legacy format is used until the peer said hello
+ */
+#[derive(Debug, Clone, Default)]
+pub struct WirePeer {
+    pub version: Option<u32>,
+    pub capabilities: Vec<String>,
+}
+
+impl WirePeer {
+    pub fn hello(&mut self, version: u32, capabilities: Vec<String>) {
+        /* both sides speak the lower version - the newer side steps down so the older one can parse its messages */
+        self.version = Some(version.min(PROTOCOL_VERSION));
+        self.capabilities = capabilities;
+    }
+
+    /**
+     * Wraps the message in an envelope stamped with the negotiated version
+     */
+    pub fn envelope(&self, message: WireMessage) -> WireEnvelope {
+        WireEnvelope {
+            version: self.version.unwrap_or(PROTOCOL_VERSION),
+            message,
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.version.is_some() && self.capabilities.iter().any(|c| c == capability)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sync_control(version: u32) -> String {
+        WireEnvelope {
+            version,
+            message: WireMessage::SyncControl {
+                identifier: String::from("Throttle1"),
+                value: 0.5,
+            },
+        }
+        .to_text()
+    }
+
+    #[test]
+    fn newer_peer_is_answered_in_our_version() {
+        let mut peer = WirePeer::default();
+        peer.hello(PROTOCOL_VERSION + 1, vec![String::from(CAPABILITY_SYNC_CONTROL)]);
+
+        let text = peer.envelope(WireMessage::CurrentVehicle { vehicle_class: String::from("BR101") }).to_text();
+        assert_eq!(WireEnvelope::parse(&text).unwrap().unwrap().version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn messages_of_a_newer_version_only_parse_once_negotiated() {
+        /* what a newer peer sends before and after it received our hello */
+        assert!(WireEnvelope::parse(&sync_control(PROTOCOL_VERSION + 1)).unwrap().is_err());
+        assert!(WireEnvelope::parse(&sync_control(PROTOCOL_VERSION)).unwrap().is_ok());
+
+        let hello = WireEnvelope {
+            version: PROTOCOL_VERSION + 1,
+            message: WireMessage::Hello { capabilities: vec![] },
+        };
+        assert!(WireEnvelope::parse(&hello.to_text()).unwrap().is_ok());
+    }
+}
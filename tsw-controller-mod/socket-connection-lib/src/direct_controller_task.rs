@@ -1,10 +1,11 @@
-use futures_util::StreamExt;
+use futures_util::{SinkExt, StreamExt};
 use std::{
     collections::{HashMap, VecDeque},
     ffi::CString,
     sync::Arc,
     time::Duration,
 };
+use tungstenite::Utf8Bytes;
 
 use tokio::{
     runtime::Runtime,
@@ -12,27 +13,41 @@ use tokio::{
 };
 use tokio_tungstenite::connect_async;
 
-const DIRECT_CONTROL_WS_ADDR: &str = "ws://127.0.0.1:63241";
+use crate::wire_protocol::{DirectControlCommand, WireEnvelope, WireMessage, CAPABILITY_DIRECT_CONTROL};
+
+/* receives direct_control,{control_name},{input_value},{flag|flag} */
+type DirectControlMessageCallback = extern "C" fn(*const std::ffi::c_char);
+/* receives the control name, the input value and whether the hold flag is set */
+type DirectControlCommandCallback = extern "C" fn(*const std::ffi::c_char, f32, bool);
 
 pub struct DirectControllerTask {
     tokio_runtime: &'static Runtime,
+    /* eg: ws://127.0.0.1:63241 */
+    ws_addr: String,
     /* direct control callback watcher values */
-    direct_control_callback_tx: Arc<watch::Sender<Option<extern "C" fn(*const std::ffi::c_char)>>>,
-    direct_control_callback_rx:
-        Arc<watch::Receiver<Option<extern "C" fn(*const std::ffi::c_char)>>>,
+    direct_control_callback_tx: Arc<watch::Sender<Option<DirectControlMessageCallback>>>,
+    direct_control_callback_rx: Arc<watch::Receiver<Option<DirectControlMessageCallback>>>,
+    direct_control_command_callback_tx: Arc<watch::Sender<Option<DirectControlCommandCallback>>>,
+    direct_control_command_callback_rx: Arc<watch::Receiver<Option<DirectControlCommandCallback>>>,
     /* message queue */
-    direct_control_message_queue: Arc<Mutex<VecDeque<String>>>,
+    direct_control_message_queue: Arc<Mutex<VecDeque<DirectControlCommand>>>,
 }
 
 impl DirectControllerTask {
     /* this task handles listening to the DC WS and propagates the values into a VecDequeue */
     pub fn spawn_dc_listener_task(&self) {
         let message_queue = Arc::clone(&self.direct_control_message_queue);
+        let ws_addr = self.ws_addr.clone();
         self.tokio_runtime.spawn(async move {
             loop {
-                match connect_async(DIRECT_CONTROL_WS_ADDR).await {
+                match connect_async(ws_addr.as_str()).await {
                     Ok((mut socket, _)) => {
-                        println!("[DirectControllerTask] Connected..");
+                        println!("[DirectControllerTask] Connected to {}", ws_addr);
+                        /* older apps ignore the hello and keep sending the comma separated format */
+                        let hello = WireEnvelope::hello(&[CAPABILITY_DIRECT_CONTROL]).to_text();
+                        if let Err(e) = socket.send(tungstenite::Message::Text(Utf8Bytes::from(hello))).await {
+                            eprintln!("[DirectControllerTask] Error sending hello: {}", e);
+                        }
 
                         loop {
                             tokio::select! {
@@ -40,11 +55,26 @@ impl DirectControllerTask {
                                     match msg {
                                         Ok(msg) => match msg {
                                             tungstenite::Message::Text(text) => {
-                                                println!("[DirectControllerTask] Queueing Message: {}", text.to_string());
-                                                let mut message_queue_lock =
-                                                message_queue.lock().await;
-                                                message_queue_lock.push_back(text.to_string());
-                                                drop(message_queue_lock);
+                                                let command = match WireEnvelope::parse(&text) {
+                                                    Some(Ok(WireEnvelope { message: WireMessage::DirectControl(command), .. })) => Some(command),
+                                                    Some(Ok(WireEnvelope { version, message: WireMessage::Hello { capabilities } })) => {
+                                                        println!("[DirectControllerTask] App speaks protocol version {} with capabilities {:?}", version, capabilities);
+                                                        None
+                                                    }
+                                                    Some(Ok(_)) => None,
+                                                    Some(Err(e)) => {
+                                                        eprintln!("[DirectControllerTask] {}", e);
+                                                        None
+                                                    }
+                                                    None => DirectControlCommand::from_legacy_text(&text),
+                                                };
+                                                if let Some(command) = command {
+                                                    println!("[DirectControllerTask] Queueing Message: {}", text.to_string());
+                                                    let mut message_queue_lock =
+                                                    message_queue.lock().await;
+                                                    message_queue_lock.push_back(command);
+                                                    drop(message_queue_lock);
+                                                }
                                             }
                                             tungstenite::Message::Close(_) => {
                                                 socket.close(None).await.unwrap();
@@ -75,39 +105,43 @@ impl DirectControllerTask {
     /* this task handles reading messages from the queue periodically and sending them to lua */
     pub fn spawn_queue_propagation_task(&self) {
         let callback_channel_rx = Arc::clone(&self.direct_control_callback_rx);
+        let command_callback_channel_rx = Arc::clone(&self.direct_control_command_callback_rx);
         let message_queue = Arc::clone(&self.direct_control_message_queue);
         self.tokio_runtime.spawn(async move {
             loop {
                 let callback_option = callback_channel_rx.borrow().clone();
-                if callback_option.is_none() {
-                    continue;
-                }
+                let command_callback_option = command_callback_channel_rx.borrow().clone();
 
                 let mut message_queue = message_queue.lock().await;
                 // only take latest of current tick
-                let mut control_value_map: HashMap<String, String> = HashMap::new();
-                while message_queue.len() > 0 {
-                    let message = message_queue.pop_front().unwrap();
-                    let parts: Vec<&str> = message.split(",").collect();
-                    if parts.len() >= 3 && parts[0] == "direct_control" {
-                        control_value_map.insert(parts[1].to_string(), message);
+                let mut control_value_map: HashMap<String, DirectControlCommand> = HashMap::new();
+                if callback_option.is_some() || command_callback_option.is_some() {
+                    while let Some(command) = message_queue.pop_front() {
+                        control_value_map.insert(command.controls.clone(), command);
                     }
                 }
+                // drop locks before calling back
+                drop(message_queue);
 
-                // forward all control values
-                for (_, message) in control_value_map.iter() {
-                    callback_option.as_ref().unwrap()(
-                        CString::new(message.clone()).unwrap().as_ptr(),
-                    );
+                // forward all control values - the structured callback can't be confused by commas in control names
+                for (_, command) in control_value_map.into_iter() {
+                    match (command_callback_option, callback_option) {
+                        (Some(command_callback), _) => {
+                            command_callback(CString::new(command.controls.clone()).unwrap().as_ptr(), command.input_value, command.hold == Some(true));
+                        }
+                        (None, Some(callback)) => {
+                            let message = WireMessage::DirectControl(command).to_legacy_text().unwrap();
+                            callback(CString::new(message).unwrap().as_ptr());
+                        }
+                        (None, None) => {}
+                    }
                 }
-                // drop locks before waiting
-                drop(message_queue);
                 tokio::time::sleep(Duration::from_millis(1000 / 10)).await;
             }
         });
     }
 
-    pub fn set_callback(&self, callback: extern "C" fn(*const std::ffi::c_char)) {
+    pub fn set_callback(&self, callback: DirectControlMessageCallback) {
         match self.direct_control_callback_tx.send(Some(callback)) {
             Ok(_) => {}
             Err(e) => {
@@ -116,18 +150,29 @@ impl DirectControllerTask {
         }
     }
 
-    pub fn new(tokio_runtime: &'static Runtime) -> DirectControllerTask {
+    pub fn set_command_callback(&self, callback: DirectControlCommandCallback) {
+        match self.direct_control_command_callback_tx.send(Some(callback)) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("[DirectControllerTask] Failed to set command callback: {}", e);
+            }
+        }
+    }
+
+    pub fn new(tokio_runtime: &'static Runtime, ws_addr: String) -> DirectControllerTask {
         // let handle = lua.create_table().unwrap();
-        let (direct_control_callback_tx, direct_control_callback_rx) =
-            watch::channel::<Option<extern "C" fn(*const std::ffi::c_char)>>(None);
-        let direct_control_message_queue: Arc<Mutex<VecDeque<String>>> =
-            Arc::new(Mutex::new(VecDeque::new()));
+        let (direct_control_callback_tx, direct_control_callback_rx) = watch::channel::<Option<DirectControlMessageCallback>>(None);
+        let (direct_control_command_callback_tx, direct_control_command_callback_rx) = watch::channel::<Option<DirectControlCommandCallback>>(None);
+        let direct_control_message_queue: Arc<Mutex<VecDeque<DirectControlCommand>>> = Arc::new(Mutex::new(VecDeque::new()));
 
         DirectControllerTask {
             tokio_runtime,
+            ws_addr,
             direct_control_message_queue,
             direct_control_callback_tx: Arc::new(direct_control_callback_tx),
             direct_control_callback_rx: Arc::new(direct_control_callback_rx),
+            direct_control_command_callback_tx: Arc::new(direct_control_command_callback_tx),
+            direct_control_command_callback_rx: Arc::new(direct_control_command_callback_rx),
         }
     }
 }
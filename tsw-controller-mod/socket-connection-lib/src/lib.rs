@@ -1,13 +1,17 @@
 pub(crate) mod direct_controller_task;
+pub(crate) mod settings;
 pub(crate) mod sync_controller_task;
+pub(crate) mod wire_protocol;
 
 use once_cell::sync::Lazy;
 use tokio::runtime::Runtime;
 static TOKIO_RUNTIME: Lazy<Runtime> = Lazy::new(|| tokio::runtime::Builder::new_multi_thread().enable_all().build().expect("Failed to create runtime"));
 
-static DIRECT_CONTROLLER_TASK: Lazy<direct_controller_task::DirectControllerTask> = Lazy::new(|| direct_controller_task::DirectControllerTask::new(&TOKIO_RUNTIME));
+static SETTINGS: Lazy<settings::ModSettings> = Lazy::new(settings::ModSettings::load);
 
-static SYNC_CONTROLLER_TASK: Lazy<sync_controller_task::SyncControllerTask> = Lazy::new(|| sync_controller_task::SyncControllerTask::new(&TOKIO_RUNTIME));
+static DIRECT_CONTROLLER_TASK: Lazy<direct_controller_task::DirectControllerTask> = Lazy::new(|| direct_controller_task::DirectControllerTask::new(&TOKIO_RUNTIME, SETTINGS.direct_control_ws_addr()));
+
+static SYNC_CONTROLLER_TASK: Lazy<sync_controller_task::SyncControllerTask> = Lazy::new(|| sync_controller_task::SyncControllerTask::new(&TOKIO_RUNTIME, SETTINGS.sync_control_ws_addr()));
 
 #[repr(C)]
 pub struct ControlValue {
@@ -27,13 +31,35 @@ pub unsafe extern "C" fn tsw_controller_mod_set_direct_controller_callback(callb
     DIRECT_CONTROLLER_TASK.set_callback(callback);
 }
 
+/**
+ * Receives the control name, input value and hold flag of every direct control command - takes precedence over the message callback
+ */
+#[no_mangle]
+pub unsafe extern "C" fn tsw_controller_mod_set_direct_control_command_callback(callback: extern "C" fn(*const std::ffi::c_char, f32, bool)) {
+    DIRECT_CONTROLLER_TASK.set_command_callback(callback);
+}
+
 #[no_mangle]
 pub unsafe extern "C" fn tsw_controller_mod_send_sync_controller_message(message: *const std::ffi::c_char) {
     SYNC_CONTROLLER_TASK.send(message);
 }
 
+#[no_mangle]
+pub unsafe extern "C" fn tsw_controller_mod_send_sync_control_value(identifier: *const std::ffi::c_char, value: f32) {
+    SYNC_CONTROLLER_TASK.send_value(identifier, value);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn tsw_controller_mod_send_current_vehicle_message(vehicle_class: *const std::ffi::c_char) {
+    SYNC_CONTROLLER_TASK.send_current_vehicle(vehicle_class);
+}
+
 #[no_mangle]
 #[cfg(target_os="windows")]
-pub extern "system" fn DllMain(_hinst_dll: *mut u8, _fwd_reason: u32, _lp_reserved: *mut u8) -> i32 {
+pub extern "system" fn DllMain(hinst_dll: *mut u8, fwd_reason: u32, _lp_reserved: *mut u8) -> i32 {
+    /* DLL_PROCESS_ATTACH - remember the module to find the settings file next to the DLL */
+    if fwd_reason == 1 {
+        settings::MODULE_HANDLE.store(hinst_dll, std::sync::atomic::Ordering::Relaxed);
+    }
     1
 }
@@ -0,0 +1,114 @@
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use serde::Deserialize;
+
+const DEFAULT_ADDRESS: &str = "127.0.0.1";
+const DEFAULT_DIRECT_CONTROL_PORT: u16 = 63241;
+const DEFAULT_SYNC_CONTROL_PORT: u16 = 63242;
+
+/* read from the directory of the DLL */
+const SETTINGS_FILE_NAME: &str = "tsw_controller_mod_settings.json";
+
+/* module handle of the DLL - set by DllMain */
+#[cfg(target_os = "windows")]
+pub(crate) static MODULE_HANDLE: std::sync::atomic::AtomicPtr<u8> = std::sync::atomic::AtomicPtr::new(std::ptr::null_mut());
+
+/**
+ * Where to find the direct/sync control servers of the app - needs to match the app's settings.json
+ * The TSW_CONTROLLER_ADDRESS, TSW_CONTROLLER_DIRECT_CONTROL_PORT and TSW_CONTROLLER_SYNC_CONTROL_PORT environment variables take precedence over the settings file
+ */
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModSettings {
+    /* address of the machine running the app */
+    pub address: Option<String>,
+    pub direct_control_port: Option<u16>,
+    pub sync_control_port: Option<u16>,
+}
+
+impl ModSettings {
+    pub fn load() -> ModSettings {
+        let settings = match settings_file_path() {
+            Some(path) => ModSettings::read_file(&path),
+            None => ModSettings::default(),
+        };
+        ModSettings {
+            address: env::var("TSW_CONTROLLER_ADDRESS").ok().or(settings.address),
+            direct_control_port: env_port("TSW_CONTROLLER_DIRECT_CONTROL_PORT").or(settings.direct_control_port),
+            sync_control_port: env_port("TSW_CONTROLLER_SYNC_CONTROL_PORT").or(settings.sync_control_port),
+        }
+    }
+
+    fn read_file(path: &Path) -> ModSettings {
+        let contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            /* the settings file is optional */
+            Err(_) => return ModSettings::default(),
+        };
+        match serde_json::from_str(&contents) {
+            Ok(settings) => {
+                println!("[Settings] Read {:?}", path);
+                settings
+            }
+            Err(e) => {
+                eprintln!("[Settings] Could not parse {:?} - using the defaults: {}", path, e);
+                ModSettings::default()
+            }
+        }
+    }
+
+    fn ws_addr(&self, port: u16) -> String {
+        let address = self.address.as_deref().unwrap_or(DEFAULT_ADDRESS);
+        /* IPv6 addresses need brackets in urls */
+        match address.contains(':') {
+            true => format!("ws://[{}]:{}", address, port),
+            false => format!("ws://{}:{}", address, port),
+        }
+    }
+
+    pub fn direct_control_ws_addr(&self) -> String {
+        self.ws_addr(self.direct_control_port.unwrap_or(DEFAULT_DIRECT_CONTROL_PORT))
+    }
+
+    pub fn sync_control_ws_addr(&self) -> String {
+        self.ws_addr(self.sync_control_port.unwrap_or(DEFAULT_SYNC_CONTROL_PORT))
+    }
+}
+
+fn env_port(name: &str) -> Option<u16> {
+    let value = env::var(name).ok()?;
+    match value.parse::<u16>() {
+        Ok(port) => Some(port),
+        Err(_) => {
+            eprintln!("[Settings] Ignoring {}={} - not a valid port", name, value);
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn settings_file_path() -> Option<PathBuf> {
+    extern "system" {
+        fn GetModuleFileNameW(module: *mut u8, file_name: *mut u16, size: u32) -> u32;
+    }
+
+    let module = MODULE_HANDLE.load(std::sync::atomic::Ordering::Relaxed);
+    if module.is_null() {
+        return None;
+    }
+    let mut buffer = vec![0u16; 1024];
+    let length = unsafe { GetModuleFileNameW(module, buffer.as_mut_ptr(), buffer.len() as u32) } as usize;
+    if length == 0 || length >= buffer.len() {
+        return None;
+    }
+    let dll_path = PathBuf::from(String::from_utf16_lossy(&buffer[..length]));
+    Some(dll_path.parent()?.join(SETTINGS_FILE_NAME))
+}
+
+/* the DLL location is only known on windows - fall back to the working directory */
+#[cfg(not(target_os = "windows"))]
+fn settings_file_path() -> Option<PathBuf> {
+    Some(PathBuf::from(SETTINGS_FILE_NAME))
+}
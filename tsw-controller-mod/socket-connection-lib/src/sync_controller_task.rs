@@ -8,29 +8,51 @@ use tokio::{
 };
 use tokio_tungstenite::connect_async;
 
-const SYNC_CONTROL_WS_ADDR: &str = "ws://127.0.0.1:63242";
+use crate::wire_protocol::{WireEnvelope, WireMessage, WirePeer, CAPABILITY_CURRENT_VEHICLE, CAPABILITY_SYNC_CONTROL};
 
 pub struct SyncControllerTask {
     tokio_runtime: &'static Runtime,
+    /* eg: ws://127.0.0.1:63242 */
+    ws_addr: String,
     /* channel for sending and receiving */
-    sync_control_channel_tx: Arc<mpsc::Sender<String>>,
-    sync_control_channel_rx: Arc<Mutex<mpsc::Receiver<String>>>,
+    sync_control_channel_tx: Arc<mpsc::Sender<WireMessage>>,
+    sync_control_channel_rx: Arc<Mutex<mpsc::Receiver<WireMessage>>>,
+    /* last reported vehicle class - re-sent whenever the connection is re-established */
+    current_vehicle: Arc<std::sync::Mutex<Option<String>>>,
 }
 
 impl SyncControllerTask {
     /* this task handles reading messages from the message channel and sends them to the SC WS connection */
     pub fn spawn_sc_forwarding_task(&self) {
         let message_channel_rx = Arc::clone(&self.sync_control_channel_rx);
+        let current_vehicle = Arc::clone(&self.current_vehicle);
+        let ws_addr = self.ws_addr.clone();
 
         self.tokio_runtime.spawn(async move {
             let mut message_channel_rx_lock = message_channel_rx.lock().await;
             loop {
-                match connect_async(SYNC_CONTROL_WS_ADDR).await {
+                match connect_async(ws_addr.as_str()).await {
                     Ok((mut socket, _)) => {
-                        println!("[SyncControllerTask] Connected..");
+                        println!("[SyncControllerTask] Connected to {}", ws_addr);
+                        /* messages are sent in the legacy format until the app said hello */
+                        let mut peer = WirePeer::default();
+                        let hello = WireEnvelope::hello(&[CAPABILITY_SYNC_CONTROL, CAPABILITY_CURRENT_VEHICLE]).to_text();
+                        if let Err(e) = socket.send(tungstenite::Message::Text(Utf8Bytes::from(hello))).await {
+                            eprintln!("[SC] Error sending hello: {}", e);
+                        }
+                        let current_vehicle_message = current_vehicle.lock().unwrap().as_ref().map(|vehicle| WireMessage::CurrentVehicle { vehicle_class: vehicle.clone() });
+                        if let Some(message) = current_vehicle_message.and_then(|message| peer.encode(message)) {
+                            if let Err(e) = socket.send(tungstenite::Message::Text(Utf8Bytes::from(message))).await {
+                                eprintln!("[SC] Error sending current vehicle: {}", e);
+                            }
+                        }
                         loop {
                             tokio::select! {
                                 Some(msg) = message_channel_rx_lock.recv() => {
+                                    let msg = match peer.encode(msg) {
+                                        Some(msg) => msg,
+                                        None => continue,
+                                    };
                                     println!("[SyncControllerTask] Sending Message: {}", msg.clone());
                                     match socket.send(tungstenite::Message::Text(Utf8Bytes::from(msg))).await {
                                         Err(e) => {
@@ -46,6 +68,14 @@ impl SyncControllerTask {
                                 Some(msg) = socket.next() => {
                                     match msg {
                                         Ok(msg) => match msg {
+                                            tungstenite::Message::Text(text) => match WireEnvelope::parse(&text) {
+                                                Some(Ok(WireEnvelope { version, message: WireMessage::Hello { capabilities } })) => {
+                                                    println!("[SC] App speaks protocol version {} with capabilities {:?}", version, capabilities);
+                                                    peer.hello(version, capabilities);
+                                                }
+                                                Some(Err(e)) => eprintln!("[SC] {}", e),
+                                                _ => {}
+                                            },
                                             tungstenite::Message::Close(_) => {
                                                 socket.close(None).await.unwrap();
                                                 break;
@@ -87,7 +117,35 @@ impl SyncControllerTask {
             message.clone()
         );
 
-        match sync_control_channel_tx.blocking_send(format!("sync_control,{}", message.clone())) {
+        /* format: {identifier},{value} - the value is split off from the end so the identifier can contain commas */
+        let message = match message.rsplit_once(",").and_then(|(identifier, value)| Some((identifier, value.parse::<f32>().ok()?))) {
+            Some((identifier, value)) => WireMessage::SyncControl {
+                identifier: identifier.to_string(),
+                value,
+            },
+            None => {
+                eprintln!("[SyncControllerTask] Invalid SC message: {}", message);
+                return;
+            }
+        };
+
+        match sync_control_channel_tx.blocking_send(message) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("[SyncControllerTask] Error sending SC message: {}", e);
+            }
+        }
+    }
+
+    pub unsafe fn send_value(&self, raw_identifier: *const std::ffi::c_char, value: f32) {
+        if self.sync_control_channel_tx.capacity() == 0 {
+            eprintln!("[SyncControllerTask] Channel is full, dropping message");
+            return;
+        }
+
+        let identifier = String::from(CStr::from_ptr(raw_identifier).to_str().unwrap());
+        println!("[SyncControllerTask] Sending SC value: {}={}", identifier, value);
+        match self.sync_control_channel_tx.blocking_send(WireMessage::SyncControl { identifier, value }) {
             Ok(_) => {}
             Err(e) => {
                 eprintln!("[SyncControllerTask] Error sending SC message: {}", e);
@@ -95,12 +153,27 @@ impl SyncControllerTask {
         }
     }
 
-    pub fn new(tokio_runtime: &'static Runtime) -> SyncControllerTask {
-        let (sync_control_channel_tx, sync_control_channel_rx) = mpsc::channel::<String>(50);
+    pub unsafe fn send_current_vehicle(&self, raw: *const std::ffi::c_char) {
+        let vehicle = String::from(CStr::from_ptr(raw).to_str().unwrap());
+        println!("[SyncControllerTask] Current vehicle: {}", vehicle.clone());
+        *self.current_vehicle.lock().unwrap() = Some(vehicle.clone());
+
+        match self.sync_control_channel_tx.try_send(WireMessage::CurrentVehicle { vehicle_class: vehicle }) {
+            Ok(_) => {}
+            Err(e) => {
+                eprintln!("[SyncControllerTask] Error sending current vehicle message: {}", e);
+            }
+        }
+    }
+
+    pub fn new(tokio_runtime: &'static Runtime, ws_addr: String) -> SyncControllerTask {
+        let (sync_control_channel_tx, sync_control_channel_rx) = mpsc::channel::<WireMessage>(50);
         SyncControllerTask {
             tokio_runtime,
+            ws_addr,
             sync_control_channel_tx: Arc::new(sync_control_channel_tx),
             sync_control_channel_rx: Arc::new(Mutex::new(sync_control_channel_rx)),
+            current_vehicle: Arc::new(std::sync::Mutex::new(None)),
         }
     }
 }
@@ -0,0 +1,200 @@
+use serde::{Deserialize, Serialize};
+
+/* needs to match the definitions in the app's wire_protocol.rs */
+pub const PROTOCOL_VERSION: u32 = 1;
+
+pub const CAPABILITY_DIRECT_CONTROL: &str = "direct_control";
+pub const CAPABILITY_SYNC_CONTROL: &str = "sync_control";
+pub const CAPABILITY_CURRENT_VEHICLE: &str = "current_vehicle";
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DirectControlCommand {
+    pub controls: String,
+    pub input_value: f32,
+    pub hold: Option<bool>,
+}
+
+impl DirectControlCommand {
+    /**
+     * Parses the comma separated messages of older apps
+     * direct_control,{controls},{input_value},{flag|flag}
+     */
+    pub fn from_legacy_text(text: &str) -> Option<DirectControlCommand> {
+        let parts: Vec<&str> = text.split(",").collect();
+        if parts.len() < 3 || parts[0] != "direct_control" {
+            return None;
+        }
+        Some(DirectControlCommand {
+            controls: parts[1].to_string(),
+            input_value: parts[2].parse::<f32>().ok()?,
+            hold: Some(parts.get(3).map_or(false, |flags| flags.split("|").any(|flag| flag == "hold"))),
+        })
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum WireMessage {
+    /* sent by both sides after connecting */
+    Hello { capabilities: Vec<String> },
+    DirectControl(DirectControlCommand),
+    SyncControl { identifier: String, value: f32 },
+    CurrentVehicle { vehicle_class: String },
+}
+
+/**
+ * JSON message exchanged with the app
+ * eg: {"version":1,"type":"sync_control","identifier":"Throttle1","value":0.5}
+ */
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct WireEnvelope {
+    pub version: u32,
+    #[serde(flatten)]
+    pub message: WireMessage,
+}
+
+impl WireEnvelope {
+    pub fn new(message: WireMessage) -> WireEnvelope {
+        WireEnvelope { version: PROTOCOL_VERSION, message }
+    }
+
+    pub fn hello(capabilities: &[&str]) -> WireEnvelope {
+        WireEnvelope::new(WireMessage::Hello {
+            capabilities: capabilities.iter().map(|c| c.to_string()).collect(),
+        })
+    }
+
+    pub fn to_text(&self) -> String {
+        serde_json::to_string(self).unwrap()
+    }
+
+    /**
+     * Returns None for messages in the legacy comma separated format
+     */
+    pub fn parse(text: &str) -> Option<Result<WireEnvelope, String>> {
+        if !text.trim_start().starts_with('{') {
+            return None;
+        }
+        let envelope = match serde_json::from_str::<WireEnvelope>(text) {
+            Ok(envelope) => envelope,
+            Err(e) => return Some(Err(format!("Invalid message {}: {}", text, e))),
+        };
+        /* the hello is understood by every version - everything else of a newer version might have changed */
+        match envelope.message {
+            WireMessage::Hello { .. } => {}
+            _ if envelope.version > PROTOCOL_VERSION => return Some(Err(format!("Unsupported protocol version {} (up to {} is supported): {}", envelope.version, PROTOCOL_VERSION, text))),
+            _ => {}
+        }
+        Some(Ok(envelope))
+    }
+}
+
+impl WireMessage {
+    /**
+     * Formats the message in the comma separated format older apps understand
+     * direct_control,{controls},{input_value},{flag|flag} | sync_control,{identifier},{value} | current_vehicle,{vehicle_class}
+     */
+    pub fn to_legacy_text(&self) -> Option<String> {
+        match self {
+            WireMessage::Hello { .. } => None,
+            WireMessage::DirectControl(command) => {
+                let flags = match command.hold {
+                    Some(true) => "hold",
+                    _ => "",
+                };
+                Some(format!("direct_control,{},{},{}", command.controls, command.input_value, flags))
+            }
+            WireMessage::SyncControl { identifier, value } => Some(format!("sync_control,{},{}", identifier, value)),
+            WireMessage::CurrentVehicle { vehicle_class } => Some(format!("current_vehicle,{}", vehicle_class)),
+        }
+    }
+
+    /**
+     * The capability the peer needs to receive the message as JSON
+     */
+    pub fn capability(&self) -> Option<&'static str> {
+        match self {
+            WireMessage::Hello { .. } => None,
+            WireMessage::DirectControl(_) => Some(CAPABILITY_DIRECT_CONTROL),
+            WireMessage::SyncControl { .. } => Some(CAPABILITY_SYNC_CONTROL),
+            WireMessage::CurrentVehicle { .. } => Some(CAPABILITY_CURRENT_VEHICLE),
+        }
+    }
+}
+
+/**
+ * Protocol state of a connection - the legacy format is used until the peer said hello
+ */
+#[derive(Debug, Clone, Default)]
+pub struct WirePeer {
+    pub version: Option<u32>,
+    pub capabilities: Vec<String>,
+}
+
+impl WirePeer {
+    pub fn hello(&mut self, version: u32, capabilities: Vec<String>) {
+        /* both sides speak the lower version - the newer side steps down so the older one can parse its messages */
+        self.version = Some(version.min(PROTOCOL_VERSION));
+        self.capabilities = capabilities;
+    }
+
+    /**
+     * Wraps the message in an envelope stamped with the negotiated version
+     */
+    pub fn envelope(&self, message: WireMessage) -> WireEnvelope {
+        WireEnvelope {
+            version: self.version.unwrap_or(PROTOCOL_VERSION),
+            message,
+        }
+    }
+
+    pub fn supports(&self, capability: &str) -> bool {
+        self.version.is_some() && self.capabilities.iter().any(|c| c == capability)
+    }
+
+    /**
+     * Encodes the message as JSON if the peer understands it - otherwise in the legacy format
+     */
+    pub fn encode(&self, message: WireMessage) -> Option<String> {
+        match message.capability() {
+            Some(capability) if !self.supports(capability) => message.to_legacy_text(),
+            _ => Some(self.envelope(message).to_text()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn newer_app_gets_messages_in_the_negotiated_version() {
+        let mut peer = WirePeer::default();
+        peer.hello(PROTOCOL_VERSION + 1, vec![String::from(CAPABILITY_SYNC_CONTROL)]);
+
+        let text = peer
+            .encode(WireMessage::SyncControl {
+                identifier: String::from("Throttle1"),
+                value: 0.5,
+            })
+            .unwrap();
+        assert_eq!(WireEnvelope::parse(&text).unwrap().unwrap().version, PROTOCOL_VERSION);
+    }
+
+    #[test]
+    fn messages_of_a_newer_version_are_rejected() {
+        let text = WireEnvelope {
+            version: PROTOCOL_VERSION + 1,
+            message: WireMessage::CurrentVehicle { vehicle_class: String::from("BR101") },
+        }
+        .to_text();
+        assert!(WireEnvelope::parse(&text).unwrap().is_err());
+    }
+
+    #[test]
+    fn peer_without_hello_gets_the_legacy_format() {
+        let peer = WirePeer::default();
+        let text = peer.encode(WireMessage::CurrentVehicle { vehicle_class: String::from("BR101") }).unwrap();
+        assert_eq!(text, "current_vehicle,BR101");
+    }
+}
@@ -10,6 +10,13 @@ void tsw_controller_mod_start();
 
 void tsw_controller_mod_set_direct_controller_callback(void (*callback)(const char*));
 
+/// Receives the control name, input value and hold flag of every direct control command - takes precedence over the message callback
+void tsw_controller_mod_set_direct_control_command_callback(void (*callback)(const char*, float, bool));
+
 void tsw_controller_mod_send_sync_controller_message(const char *message);
 
+void tsw_controller_mod_send_sync_control_value(const char *identifier, float value);
+
+void tsw_controller_mod_send_current_vehicle_message(const char *vehicle_class);
+
 }  // extern "C"
@@ -115,6 +115,9 @@ class TSWControllerMod : public RC::CppUserModBase
     static inline std::shared_mutex VHID_COMPONENTS_TO_RELEASE_MUTEX;
     static inline std::unordered_map<RC::StringType, Unreal::UObject*> VHID_COMPONENTS_TO_RELEASE;
 
+    /* class name of the last drivable actor - used to let the app switch profiles */
+    static inline RC::StringType CURRENT_VEHICLE_CLASS;
+
     static bool is_within_margin_of_error(float current, float target)
     {
         return abs(target - current) < 0.05f;
@@ -252,6 +255,19 @@ class TSWControllerMod : public RC::CppUserModBase
         return res;
     }
 
+    static void report_current_vehicle(Unreal::UObject* drivable_actor)
+    {
+        Unreal::UClass* vehicle_class = drivable_actor->GetClassPrivate();
+        if (!vehicle_class) return;
+
+        RC::StringType vehicle_class_name = vehicle_class->GetName();
+        if (vehicle_class_name == TSWControllerMod::CURRENT_VEHICLE_CLASS) return;
+
+        TSWControllerMod::CURRENT_VEHICLE_CLASS = vehicle_class_name;
+        Output::send<LogLevel::Verbose>(STR("[TSWControllerMod] Current vehicle changed: {}\n"), vehicle_class_name);
+        tsw_controller_mod_send_current_vehicle_message(std::string(vehicle_class_name.begin(), vehicle_class_name.end()).c_str());
+    }
+
     static void on_process_event_pre_callback(Unreal::UObject* context, Unreal::UFunction* function, void* params)
     {
         if (function->GetName() != STR("Tick"))
@@ -311,6 +327,8 @@ class TSWControllerMod : public RC::CppUserModBase
             return;
         }
 
+        TSWControllerMod::report_current_vehicle(drivable_actor_result.DrivableActor);
+
         Unreal::UFunction* find_virtual_hid_component_func = drivable_actor_result.DrivableActor->GetFunctionByNameInChain(STR("FindVirtualHIDComponent"));
         if (!find_virtual_hid_component_func) return;
 
@@ -368,19 +386,16 @@ class TSWControllerMod : public RC::CppUserModBase
         }
     }
 
-    static void on_direct_control_message_received(const char* raw_message)
+    static void on_direct_control_command_received(const char* raw_control_name, float input_value, bool hold)
     {
         /* update DC target state */
         std::unique_lock<std::shared_mutex> lock(TSWControllerMod::DIRECT_CONTROL_TARGET_STATE_MUTEX);
 
-        auto message = RC::ensure_str(std::string{raw_message});
-        auto parts = TSWControllerMod::wstring_split(message, STR(","));
-        /* format: direct_control,{control_name},{target_value},{flag|flag} */
-        if (parts.size() < 4 || parts[0] != STR("direct_control")) return;
-
-        Output::send<LogLevel::Verbose>(STR("[TSWControllerMod] Processing Direct Control message: {}\n"), message);
-        std::vector<RC::StringType> flags = TSWControllerMod::wstring_split(parts[3], STR("|"));
-        TSWControllerMod::DIRECT_CONTROL_TARGET_STATE[parts[1]] = std::make_tuple(std::stof(parts[2]), flags);
+        auto control_name = RC::ensure_str(std::string{raw_control_name});
+        Output::send<LogLevel::Verbose>(STR("[TSWControllerMod] Processing Direct Control command: {} {}\n"), control_name, input_value);
+        std::vector<RC::StringType> flags;
+        if (hold) flags.push_back(STR("hold"));
+        TSWControllerMod::DIRECT_CONTROL_TARGET_STATE[control_name] = std::make_tuple(input_value, flags);
     }
 
     static void on_ts2_virtualhidcomponent_inputvaluechanged(Unreal::UnrealScriptFunctionCallableContext context, void* custom_data)
@@ -399,9 +414,9 @@ class TSWControllerMod : public RC::CppUserModBase
             }
 
             VirtualHIDComponent_InputValueChangedParams inptu_value_changed_params = context.GetParams<VirtualHIDComponent_InputValueChangedParams>();
-            auto message = input_identifier->ToString() + STR(",") + std::to_wstring(inptu_value_changed_params.NewValue);
-            Output::send<LogLevel::Verbose>(STR("[TSWControllerMod] Sending SC message {}\n"), message);
-            tsw_controller_mod_send_sync_controller_message(std::string(message.begin(), message.end()).c_str());
+            auto identifier = input_identifier->ToString();
+            Output::send<LogLevel::Verbose>(STR("[TSWControllerMod] Sending SC value {} {}\n"), identifier, inptu_value_changed_params.NewValue);
+            tsw_controller_mod_send_sync_control_value(std::string(identifier.begin(), identifier.end()).c_str(), inptu_value_changed_params.NewValue);
         }
     }
 
@@ -430,7 +445,7 @@ class TSWControllerMod : public RC::CppUserModBase
         Output::send<LogLevel::Verbose>(STR("[TSWControllerMod] Registering hooks and callbacks"));
         Unreal::Hook::RegisterProcessEventPreCallback(TSWControllerMod::on_process_event_pre_callback);
         unreal_function->RegisterPostHook(TSWControllerMod::on_ts2_virtualhidcomponent_inputvaluechanged);
-        tsw_controller_mod_set_direct_controller_callback(TSWControllerMod::on_direct_control_message_received);
+        tsw_controller_mod_set_direct_control_command_callback(TSWControllerMod::on_direct_control_command_received);
     }
 
     ~TSWControllerMod() override = default;